@@ -0,0 +1,163 @@
+//! Minimal reader for ZIP archives (e.g. CBZ files).
+//!
+//! Only what is needed to extract pages is supported: stored and deflated
+//! entries, without encryption nor ZIP64 extensions.
+use eyre::{bail, ensure, eyre, Context, Result};
+use rayon::prelude::*;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Signature of a local file header, found at the start of an archive.
+const LOCAL_HEADER_SIGNATURE: &[u8] = b"PK\x03\x04";
+/// Signature of the end of central directory record.
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8] = b"PK\x05\x06";
+/// Signature of a central directory file header.
+const CENTRAL_HEADER_SIGNATURE: &[u8] = b"PK\x01\x02";
+
+/// Size of the fixed part of the end of central directory record.
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+/// Size of the fixed part of a central directory file header.
+const CENTRAL_HEADER_SIZE: usize = 46;
+/// Size of the fixed part of a local file header.
+const LOCAL_HEADER_SIZE: usize = 30;
+
+/// A file stored in an archive.
+pub struct Entry {
+    /// Path of the file inside the archive.
+    pub name: PathBuf,
+    /// Uncompressed content of the file.
+    pub contents: Vec<u8>,
+}
+
+/// Check if the given path is a ZIP archive.
+pub fn is_archive(path: &Path) -> Result<bool> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+
+    let mut signature = [0; 4];
+    let mut file = File::open(path)
+        .wrap_err_with(|| format!("open {}", path.display()))?;
+    Ok(match file.read_exact(&mut signature) {
+        Ok(()) => {
+            signature == LOCAL_HEADER_SIGNATURE
+                || signature == END_OF_CENTRAL_DIRECTORY_SIGNATURE
+        },
+        Err(_) => false,
+    })
+}
+
+/// Extract every file from the archive at the given path.
+///
+/// The whole archive is read at once, then its files are inflated in
+/// parallel: both the archive and every uncompressed file are kept in memory
+/// until returned.
+pub fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+    let mut file = File::open(path)
+        .wrap_err_with(|| format!("open {}", path.display()))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .wrap_err_with(|| format!("read {}", path.display()))?;
+
+    let (count, mut offset) = find_central_directory(&data)?;
+    let mut stored = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = data
+            .get(offset..offset + CENTRAL_HEADER_SIZE)
+            .filter(|header| header.starts_with(CENTRAL_HEADER_SIGNATURE))
+            .ok_or_else(|| eyre!("invalid central directory header"))?;
+        let flags = read_u16(header, 8);
+        let method = read_u16(header, 10);
+        let compressed_size = read_u32(header, 20) as usize;
+        let name_len = read_u16(header, 28) as usize;
+        let extra_len = read_u16(header, 30) as usize;
+        let comment_len = read_u16(header, 32) as usize;
+        let local_offset = read_u32(header, 42) as usize;
+
+        let name_start = offset + CENTRAL_HEADER_SIZE;
+        let name = data
+            .get(name_start..name_start + name_len)
+            .ok_or_else(|| eyre!("truncated central directory"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        offset = name_start + name_len + extra_len + comment_len;
+
+        // Directories are only recorded for their name.
+        if name.ends_with('/') {
+            continue;
+        }
+        ensure!(flags & 1 == 0, "{} is encrypted", name);
+
+        let compressed = local_data(&data, local_offset, compressed_size)
+            .ok_or_else(|| eyre!("invalid local header for {}", name))?;
+        stored.push((name, method, compressed));
+    }
+
+    stored
+        .into_par_iter()
+        .map(|(name, method, compressed)| {
+            let contents = match method {
+                0 => compressed.to_vec(),
+                8 => miniz_oxide::inflate::decompress_to_vec(compressed)
+                    .map_err(|err| eyre!("inflate {}: {:?}", name, err))?,
+                _ => bail!(
+                    "unsupported compression method {} for {}",
+                    method,
+                    name
+                ),
+            };
+            Ok(Entry {
+                name: PathBuf::from(name),
+                contents,
+            })
+        })
+        .collect()
+}
+
+/// Locate the central directory, returns its number of entries and offset.
+fn find_central_directory(data: &[u8]) -> Result<(usize, usize)> {
+    ensure!(
+        data.len() >= END_OF_CENTRAL_DIRECTORY_SIZE,
+        "not a ZIP archive"
+    );
+
+    // The record is at the very end, followed by a variable-length comment.
+    let start = (0..=data.len() - END_OF_CENTRAL_DIRECTORY_SIZE)
+        .rev()
+        .find(|&i| data[i..].starts_with(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+        .ok_or_else(|| eyre!("end of central directory not found"))?;
+    let record = &data[start..];
+
+    let count = read_u16(record, 10);
+    let offset = read_u32(record, 16);
+    ensure!(
+        count != u16::MAX && offset != u32::MAX,
+        "ZIP64 archives are not supported"
+    );
+
+    Ok((count.into(), offset as usize))
+}
+
+/// Return the (compressed) data of the entry whose local header is at
+/// `offset`.
+fn local_data(data: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    let header = data
+        .get(offset..offset + LOCAL_HEADER_SIZE)
+        .filter(|header| header.starts_with(LOCAL_HEADER_SIGNATURE))?;
+    let name_len = read_u16(header, 26) as usize;
+    let extra_len = read_u16(header, 28) as usize;
+    let start = offset + LOCAL_HEADER_SIZE + name_len + extra_len;
+
+    data.get(start..start + size)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().expect("2 bytes"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4 bytes"))
+}
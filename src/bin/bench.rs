@@ -0,0 +1,182 @@
+//! A small tool to measure how fast each algorithm hashes a set of images.
+//!
+//! Every page of the given directory (or archive) is hashed several times with
+//! each perceptual hash, and the throughput is printed as a table.
+//! Useful to pick an algorithm that is fast enough for large libraries, or to
+//! see how much downscaling the pages speeds the hashing up. The peak memory
+//! usage of hashing the pages once streamed and once mapped in memory is
+//! compared as well (on Linux), to see how much mapping them saves.
+use eyre::{ensure, Context, Result};
+use img_match::{
+    hash_images,
+    Algorithm,
+    Cache,
+    HashConfig,
+    HashOptions,
+    ProgressBar,
+};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opts {
+    /// Directory (or ZIP/CBZ archive) of the pages to hash.
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+
+    /// Number of times the pages are hashed with each algorithm.
+    #[structopt(short = "n", long, default_value = "3")]
+    runs: u32,
+
+    /// Look for pages in subdirectories as well.
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// Disable the DCT preprocessing of the images.
+    #[structopt(long)]
+    no_dct: bool,
+
+    /// Also hash the pages once downscaled to at most this many pixels, to
+    /// compare with the full size.
+    #[structopt(long, value_name = "PIXELS")]
+    max_decode_dim: Option<u32>,
+
+    /// Map the page files in memory instead of streaming them.
+    #[structopt(long)]
+    mmap: bool,
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::from_args();
+    ensure!(opts.runs > 0, "at least one run is required");
+
+    // Nothing is cached, to measure the actual hashing.
+    let cache = Cache::default();
+    let progress = ProgressBar::hidden();
+
+    ensure!(
+        opts.max_decode_dim != Some(0),
+        "the maximum dimension cannot be null"
+    );
+    // The full size first, then downscaled.
+    let mut dimensions = vec![None];
+    dimensions.extend(opts.max_decode_dim.map(Some));
+
+    println!(
+        "{:<16} {:>8} {:>8} {:>6} {:>10} {:>10}",
+        "ALGORITHM", "MAX DIM", "PAGES", "RUNS", "TOTAL", "PAGES/S"
+    );
+    for name in Algorithm::NAMES {
+        for &max_dimension in &dimensions {
+            bench(&opts, name, max_dimension, &cache, &progress)?;
+        }
+    }
+    compare_memory(&opts, &cache, &progress)
+}
+
+/// Hash the pages once streamed and once mapped in memory, and print the peak
+/// of the memory allocated by each (when known).
+///
+/// The resident size would count the mapped pages, that the kernel can drop
+/// at will: only the anonymous memory (`RssAnon`) is sampled, every
+/// millisecond.
+fn compare_memory(
+    opts: &Opts,
+    cache: &Cache,
+    progress: &ProgressBar,
+) -> Result<()> {
+    if anonymous_memory().is_none() {
+        return Ok(());
+    }
+
+    println!("\n{:<16} {:>12}", "PAGES", "PEAK MEMORY");
+    for mmap in [false, true] {
+        let options = HashOptions {
+            recursive: opts.recursive,
+            mmap,
+            ..HashOptions::default()
+        };
+        let done = AtomicBool::new(false);
+        let peak = thread::scope(|scope| {
+            let sampler = scope.spawn(|| {
+                let mut peak = 0;
+                while !done.load(Ordering::Relaxed) {
+                    peak = peak.max(anonymous_memory().unwrap_or_default());
+                    thread::sleep(Duration::from_millis(1));
+                }
+                peak
+            });
+            let outcome = hash_images(&opts.path, &options, cache, progress);
+            done.store(true, Ordering::Relaxed);
+            let peak = sampler.join().expect("sampler panicked");
+            outcome.map(|_| peak)
+        })
+        .wrap_err_with(|| format!("hashing {}", opts.path.display()))?;
+        let mode = if mmap { "mapped" } else { "streamed" };
+        println!("{:<16} {:>8} MiB", mode, peak / 1024);
+    }
+
+    Ok(())
+}
+
+/// Return the anonymous memory of the process (its allocations, rather than
+/// its mapped files), in KiB, when known.
+fn anonymous_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("RssAnon:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Hash the pages with the given algorithm, downscaled to `max_dimension`
+/// (if any), and print the throughput.
+fn bench(
+    opts: &Opts,
+    name: &str,
+    max_dimension: Option<u32>,
+    cache: &Cache,
+    progress: &ProgressBar,
+) -> Result<()> {
+    let algorithm = name.parse::<Algorithm>()?;
+    let options = HashOptions {
+        config: HashConfig::new(algorithm, 8, 8)?
+            .dct(!opts.no_dct)
+            .max_dimension(max_dimension.unwrap_or_default()),
+        recursive: opts.recursive,
+        mmap: opts.mmap,
+        ..HashOptions::default()
+    };
+
+    let mut pages = 0;
+    let start = Instant::now();
+    for _ in 0..opts.runs {
+        pages = hash_images(&opts.path, &options, cache, progress)
+            .wrap_err_with(|| format!("hashing {}", opts.path.display()))?
+            .images
+            .len();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<16} {:>8} {:>8} {:>6} {:>9.2}s {:>10.1}",
+        name,
+        max_dimension.map_or_else(|| "-".to_owned(), |max| max.to_string()),
+        pages,
+        opts.runs,
+        elapsed.as_secs_f64(),
+        (pages as f64 * f64::from(opts.runs)) / elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
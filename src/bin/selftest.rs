@@ -0,0 +1,133 @@
+//! A small tool to check that hashing an image always gives the same hash.
+//!
+//! The given image is hashed several times with each perceptual hash, both on
+//! the current thread and in parallel, and every hash must be identical.
+//! Useful to trust a cache, which relies on the hashes being deterministic.
+use eyre::{bail, ensure, Context, Result};
+use img_match::{hash_bytes, Algorithm, HashConfig, HashOptions};
+use rayon::prelude::*;
+use std::{fs, path::PathBuf};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opts {
+    /// Image to hash.
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+
+    /// Number of times the image is hashed with each algorithm, serially and
+    /// then in parallel.
+    #[structopt(short = "n", long, default_value = "8")]
+    runs: usize,
+
+    /// Width and height of the hash.
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["WIDTH", "HEIGHT"],
+        default_value = "8 8",
+        use_delimiter = true,
+        value_delimiter = " "
+    )]
+    hash_size: Vec<u32>,
+
+    /// Disable the DCT preprocessing of the images.
+    #[structopt(long)]
+    no_dct: bool,
+
+    /// Crop the uniform borders of the image before hashing it.
+    #[structopt(long)]
+    trim_borders: bool,
+
+    /// Convert the image to grayscale before hashing it.
+    #[structopt(long)]
+    grayscale: bool,
+
+    /// Equalize the tones of the image before hashing it.
+    #[structopt(long)]
+    equalize: bool,
+
+    /// Rotate the image as told by its EXIF orientation before hashing it.
+    #[structopt(long)]
+    apply_exif_orientation: bool,
+
+    /// Pad the image into a square before hashing it.
+    #[structopt(long)]
+    pad_to_square: bool,
+
+    /// Blur the image before hashing it (0 to disable).
+    #[structopt(long, default_value = "0", value_name = "SIGMA")]
+    preblur: f32,
+
+    /// Frame of the animated image to hash, counting from 0.
+    #[structopt(long, default_value = "0", value_name = "N")]
+    gif_frame: u32,
+
+    /// Downscale the image beyond this many pixels before hashing it (0 to
+    /// disable).
+    #[structopt(long, default_value = "0", value_name = "PIXELS")]
+    max_decode_dim: u32,
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::from_args();
+    ensure!(opts.runs > 0, "at least one run is required");
+
+    let contents = fs::read(&opts.path)
+        .wrap_err_with(|| format!("read {}", opts.path.display()))?;
+    let filename = opts.path.file_name().expect("missing filename");
+
+    let mut failures = Vec::new();
+    for name in Algorithm::NAMES {
+        let algorithm = name.parse::<Algorithm>()?;
+        let options = HashOptions {
+            config: HashConfig::new(
+                algorithm,
+                opts.hash_size[0],
+                opts.hash_size[1],
+            )?
+            .dct(!opts.no_dct)
+            .trim_borders(opts.trim_borders)
+            .grayscale(opts.grayscale)
+            .equalize(opts.equalize)
+            .exif_orientation(opts.apply_exif_orientation)
+            .pad_to_square(opts.pad_to_square)
+            .preblur(opts.preblur)
+            .frame(opts.gif_frame)
+            .max_dimension(opts.max_decode_dim),
+            mirror: true,
+            invert: true,
+            ..HashOptions::default()
+        };
+        let hash = || {
+            hash_bytes(filename, 0, contents.clone(), &options).map(|image| {
+                (image.hash, image.mirror_hash, image.inverted_hash)
+            })
+        };
+
+        let serial = (0..opts.runs).map(|_| hash()).collect::<Result<Vec<_>>>();
+        let parallel = (0..opts.runs)
+            .into_par_iter()
+            .map(|_| hash())
+            .collect::<Result<Vec<_>>>();
+        let hashes = [serial?, parallel?].concat();
+
+        let distinct = hashes.iter().filter(|&hash| hash != &hashes[0]).count();
+        if distinct == 0 {
+            println!("{:<16} OK ({})", name, hashes[0].0.to_base64());
+        } else {
+            println!(
+                "{:<16} FAILED ({} of {} hashes differ)",
+                name,
+                distinct,
+                hashes.len()
+            );
+            failures.push(*name);
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!("hashing is not deterministic with {}", failures.join(", "));
+    }
+    Ok(())
+}
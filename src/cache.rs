@@ -0,0 +1,407 @@
+//! Persistent cache of the page hashes.
+//!
+//! The cache is a text file with one page per line, made of tab-separated
+//! fields: index, size (in bytes), hexadecimal digest of the content (`-` when
+//! unknown), base64-encoded hash and path.
+//!
+//! The hash settings the pages were hashed with are recorded after the
+//! header: a cache made with other settings (or before they were recorded) is
+//! ignored, as its hashes cannot be compared.
+//!
+//! While hashing, the new entries can be appended to the file as soon as they
+//! are computed: entries found later in the file take precedence, and a last
+//! line cut short by an interruption is ignored.
+//!
+//! The cache can also be stored in an SQLite database instead (see
+//! [`Cache::load_db`]).
+use crate::{db, warn, HashOptions, HashedImage};
+use eyre::{eyre, Context, Result};
+use img_hash::ImageHash;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Header of the cache file, used to detect incompatible formats.
+const HEADER: &str = "# img-match cache v3";
+/// Headers of the previous formats, without the hash settings.
+const OLD_HEADERS: &[&str] = &["# img-match cache v1", "# img-match cache v2"];
+/// Prefix of the line recording the hash settings, after the header.
+const SETTINGS_PREFIX: &str = "# settings: ";
+
+/// A cached hash.
+#[derive(Clone, PartialEq)]
+struct Entry {
+    index: usize,
+    size: usize,
+    digest: Option<u64>,
+    hash: ImageHash,
+}
+
+/// Hashes of previously seen pages, indexed by path and by content digest.
+#[derive(Default)]
+pub struct Cache {
+    /// Hash settings of the cached pages (see [`settings`]).
+    settings: String,
+    entries: HashMap<PathBuf, Entry>,
+    digests: HashMap<u64, ImageHash>,
+    /// File where the new hashes are appended, if enabled.
+    journal: Option<Mutex<File>>,
+    /// Database where the pages missing from `entries` are looked up, and
+    /// the changed ones stored, if any.
+    db: Option<db::Database>,
+    /// Paths of the entries added or changed since loaded.
+    changed: HashSet<PathBuf>,
+}
+
+impl Cache {
+    /// Create an empty cache, for the pages hashed with the given options.
+    pub fn new(options: &HashOptions) -> Self {
+        Self {
+            settings: settings(options),
+            ..Self::default()
+        }
+    }
+
+    /// Load the cache from the given path, for the pages hashed with the
+    /// given options.
+    ///
+    /// A missing file results in an empty cache, as does a cache made with
+    /// other hash settings (with a warning).
+    pub fn load(path: &Path, options: &HashOptions) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::new(options));
+            },
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("open {}", path.display()));
+            },
+        };
+
+        let mut lines = BufReader::new(file).lines();
+        match lines.next().transpose()? {
+            Some(header) if header == HEADER => {},
+            Some(header) if OLD_HEADERS.contains(&header.as_str()) => {
+                warn!(
+                    "ignored the cache {}: it does not record its hash \
+                     settings",
+                    path.display()
+                );
+                return Ok(Self::new(options));
+            },
+            None => return Ok(Self::new(options)),
+            Some(_) => {
+                return Err(eyre!("unsupported cache format"));
+            },
+        }
+
+        let cache = Self::new(options);
+        let line = lines.next().transpose()?.unwrap_or_default();
+        let recorded = line
+            .strip_prefix(SETTINGS_PREFIX)
+            .ok_or_else(|| eyre!("missing hash settings"))?;
+        if recorded != cache.settings {
+            warn!(
+                "ignored the cache {}: made with other hash settings ({})",
+                path.display(),
+                recorded
+            );
+            return Ok(cache);
+        }
+
+        let mut cache = cache;
+        let mut lines = lines.enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let line = line.wrap_err("read cache")?;
+            let (path, entry) = match parse_entry(&line) {
+                Ok(entry) => entry,
+                // The run appending to the cache may have been interrupted.
+                Err(err) if lines.peek().is_none() => {
+                    warn!("ignored the last entry of the cache: {}", err);
+                    break;
+                },
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| {
+                        format!("invalid entry at line {}", i + 3)
+                    });
+                },
+            };
+            cache.insert(path, entry);
+        }
+
+        Ok(cache)
+    }
+
+    /// Open the cache stored in the SQLite database at the given path
+    /// (requires the `sqlite` feature), for the pages hashed with the given
+    /// options.
+    ///
+    /// The pages are looked up in the database as needed, rather than loaded.
+    /// A missing database is created, empty. The pages hashed with other
+    /// settings are removed from the database (with a warning).
+    pub fn load_db(path: &Path, options: &HashOptions) -> Result<Self> {
+        let cache = Self::new(options);
+        Ok(Self {
+            db: Some(db::Database::open(path, &cache.settings)?),
+            ..cache
+        })
+    }
+
+    /// Store the pages hashed (or changed) since the cache was opened in its
+    /// SQLite database (see [`Cache::load_db`]), replacing the previous
+    /// entries of their paths.
+    pub fn save_db(&self) -> Result<()> {
+        let db = match &self.db {
+            Some(db) => db,
+            None => return Ok(()),
+        };
+        db.write(self.changed.iter().map(|path| {
+            let entry = &self.entries[path];
+            db::Row {
+                path: path.clone(),
+                index: entry.index,
+                size: entry.size,
+                digest: entry.digest,
+                hash: entry.hash.to_base64(),
+            }
+        }))
+    }
+
+    /// Write the cache to the given path.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut paths = self.entries.keys().collect::<Vec<_>>();
+        paths.sort();
+
+        let mut file = BufWriter::new(
+            File::create(path)
+                .wrap_err_with(|| format!("create {}", path.display()))?,
+        );
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "{}{}", SETTINGS_PREFIX, self.settings)?;
+        for path in paths {
+            file.write_all(format_entry(path, &self.entries[path]).as_bytes())?;
+        }
+        file.flush().wrap_err("write cache")
+    }
+
+    /// Append the hashes computed from now on to the cache file at `path`, as
+    /// soon as each page is hashed, so that an interrupted run can resume
+    /// where it stopped.
+    ///
+    /// The file is written first, with the current entries.
+    pub fn append_to(&mut self, path: &Path) -> Result<()> {
+        self.save(path)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("open {}", path.display()))?;
+        self.journal = Some(Mutex::new(file));
+        Ok(())
+    }
+
+    /// Append the hash just computed for the page at `path` to the cache file,
+    /// if enabled (see [`Cache::append_to`]).
+    pub(crate) fn record(
+        &self,
+        path: &Path,
+        index: usize,
+        size: usize,
+        digest: Option<u64>,
+        hash: &ImageHash,
+    ) -> Result<()> {
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+        let line = format_entry(
+            path,
+            &Entry {
+                index,
+                size,
+                digest,
+                hash: hash.clone(),
+            },
+        );
+        // Whole lines are written at once, to not interleave them.
+        journal
+            .lock()
+            .expect("cache journal poisoned")
+            .write_all(line.as_bytes())
+            .wrap_err("append to cache")
+    }
+
+    /// Return the number of cached hashes (those stored in the database when
+    /// opened, for an SQLite cache).
+    pub fn len(&self) -> usize {
+        match &self.db {
+            Some(db) => db.len(),
+            None => self.entries.len(),
+        }
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the cached hash of the page at `path`, if its size is unchanged.
+    pub fn get(&self, path: &Path, size: usize) -> Result<Option<ImageHash>> {
+        let entry = self.lookup(path)?;
+        Ok(entry
+            .filter(|entry| entry.size == size)
+            .map(|entry| entry.hash))
+    }
+
+    /// Return the cached hash of a page with the given content digest.
+    pub fn get_by_digest(&self, digest: u64) -> Result<Option<ImageHash>> {
+        if let Some(hash) = self.digests.get(&digest) {
+            return Ok(Some(hash.clone()));
+        }
+        match &self.db {
+            Some(db) => match db.get_by_digest(digest)? {
+                Some(row) => Ok(Some(from_row(row)?.1.hash)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Record the hashes of the images found under `root`.
+    pub fn update(
+        &mut self,
+        root: &Path,
+        images: &[HashedImage],
+    ) -> Result<()> {
+        for image in images {
+            let path = root.join(&image.path);
+            let previous = self.lookup(&path)?;
+            // Keep the known digest of the pages that were not read.
+            let digest = image.digest.or_else(|| {
+                previous
+                    .as_ref()
+                    .filter(|entry| entry.size == image.size)
+                    .and_then(|entry| entry.digest)
+            });
+            let entry = Entry {
+                index: image.index,
+                size: image.size,
+                digest,
+                hash: image.hash.clone(),
+            };
+            if previous.as_ref() != Some(&entry) {
+                self.changed.insert(path.clone());
+            }
+            self.insert(path, entry);
+        }
+        Ok(())
+    }
+
+    /// Look the entry of the page at `path` up, in the database if needed.
+    fn lookup(&self, path: &Path) -> Result<Option<Entry>> {
+        if let Some(entry) = self.entries.get(path) {
+            return Ok(Some(entry.clone()));
+        }
+        match &self.db {
+            Some(db) => match db.get(path)? {
+                Some(row) => Ok(Some(from_row(row)?.1)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, entry: Entry) {
+        if let Some(digest) = entry.digest {
+            self.digests.insert(digest, entry.hash.clone());
+        }
+        self.entries.insert(path, entry);
+    }
+}
+
+/// Turn a page stored in the database into an entry.
+fn from_row(row: db::Row) -> Result<(PathBuf, Entry)> {
+    let hash = ImageHash::from_base64(&row.hash).map_err(|err| {
+        eyre!("invalid hash of {}: {:?}", row.path.display(), err)
+    })?;
+    Ok((
+        row.path,
+        Entry {
+            index: row.index,
+            size: row.size,
+            digest: row.digest,
+            hash,
+        },
+    ))
+}
+
+/// Describe the settings the hashes depend on, to only reuse the hashes
+/// computed with the same ones.
+fn settings(options: &HashOptions) -> String {
+    let config = &options.config;
+    format!(
+        "algorithm={} size={}x{} dct={} trim_borders={} grayscale={} \
+         equalize={} exif_orientation={} pad_to_square={} preblur={} \
+         frame={} max_dimension={} pdf_dpi={}",
+        config.algorithm,
+        config.width,
+        config.height,
+        config.dct,
+        config.trim_borders,
+        config.grayscale,
+        config.equalize,
+        config.exif_orientation,
+        config.pad_to_square,
+        config.preblur.map_or(0., f32::from_bits),
+        config.frame,
+        config.max_dimension.unwrap_or_default(),
+        options.pdf_dpi
+    )
+}
+
+/// Format an entry as a line of the cache file.
+fn format_entry(path: &Path, entry: &Entry) -> String {
+    let digest = entry
+        .digest
+        .map_or_else(|| "-".to_owned(), |digest| format!("{:016x}", digest));
+    format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        entry.index,
+        entry.size,
+        digest,
+        entry.hash.to_base64(),
+        path.display()
+    )
+}
+
+fn parse_entry(line: &str) -> Result<(PathBuf, Entry)> {
+    let mut fields = line.splitn(5, '\t');
+    let mut next_field =
+        |name| fields.next().ok_or_else(|| eyre!("missing field {}", name));
+
+    let index = next_field("index")?.parse().wrap_err("invalid index")?;
+    let size = next_field("size")?.parse().wrap_err("invalid size")?;
+    let digest = match next_field("digest")? {
+        "-" => None,
+        digest => {
+            Some(u64::from_str_radix(digest, 16).wrap_err("invalid digest")?)
+        },
+    };
+    let hash = ImageHash::from_base64(next_field("hash")?)
+        .map_err(|err| eyre!("invalid hash: {:?}", err))?;
+    let path = PathBuf::from(next_field("path")?);
+
+    Ok((
+        path,
+        Entry {
+            index,
+            size,
+            digest,
+            hash,
+        },
+    ))
+}
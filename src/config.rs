@@ -0,0 +1,478 @@
+//! Default options read from a configuration file.
+//!
+//! The file uses a subset of TOML: one `key = value` per line, where the key is
+//! the long name of an option and the value is a string, a number, a boolean
+//! (for the flags) or an array (for the repeatable options). It is read into
+//! [`Defaults`], whose settings fill the options that are not given on the
+//! command line.
+use crate::{
+    report::{ColorChoice, Format},
+    Opts,
+};
+use eyre::{bail, ensure, eyre, Context, Result};
+use img_match::{Algorithm, Assignment, Glob, Metric, Prefer, SortKey};
+use serde::{
+    de::{
+        self,
+        value::{Error, MapDeserializer, SeqDeserializer},
+        IntoDeserializer,
+        Visitor,
+    },
+    forward_to_deserialize_any,
+    Deserialize,
+    Deserializer,
+};
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use structopt::clap::ArgMatches;
+
+/// Configuration file looked up in the current directory, without `--config`.
+pub const DEFAULT_PATH: &str = "img-match.toml";
+
+/// Settings that a configuration file can give a default value.
+///
+/// The inputs, outputs and actions of a run (the versions, `--script`,
+/// `--watch`…) must be given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Defaults {
+    distance: Option<u8>,
+    filename_fallback: Option<bool>,
+    flatten_for_matching: Option<bool>,
+    report_duplicates: Option<bool>,
+    align: Option<bool>,
+    accept_distance: Option<u8>,
+    position_weight: Option<f64>,
+    position_window: Option<usize>,
+    #[serde(deserialize_with = "from_str")]
+    assignment: Option<Assignment>,
+    #[serde(deserialize_with = "from_str")]
+    metric: Option<Metric>,
+    #[serde(deserialize_with = "from_str")]
+    prefer: Option<Prefer>,
+    brute_force: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    format: Option<Format>,
+    #[serde(deserialize_with = "from_str")]
+    color: Option<ColorChoice>,
+    no_color: Option<bool>,
+    recursive: Option<bool>,
+    follow_symlinks: Option<bool>,
+    no_follow_symlinks: Option<bool>,
+    ignore_hidden: Option<bool>,
+    include_hidden: Option<bool>,
+    cache: Option<PathBuf>,
+    db: Option<PathBuf>,
+    cache_by_content: Option<bool>,
+    pair_identical: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    algorithm: Option<Algorithm>,
+    hash_size: Option<Vec<u32>>,
+    no_dct: Option<bool>,
+    mirror: Option<bool>,
+    invert: Option<bool>,
+    trim_borders: Option<bool>,
+    grayscale: Option<bool>,
+    equalize: Option<bool>,
+    apply_exif_orientation: Option<bool>,
+    pad_to_square: Option<bool>,
+    preblur: Option<f32>,
+    gif_frame: Option<u32>,
+    max_decode_dim: Option<u32>,
+    quiet: Option<bool>,
+    threads: Option<usize>,
+    verbose: Option<u8>,
+    skip_errors: Option<bool>,
+    pdf_dpi: Option<u32>,
+    min_width: Option<u32>,
+    min_height: Option<u32>,
+    mmap: Option<bool>,
+    max_pages: Option<usize>,
+    #[serde(deserialize_with = "from_str")]
+    sort_key: Option<SortKey>,
+    include_ext: Option<Vec<String>>,
+    exclude_ext: Option<Vec<String>>,
+    #[serde(deserialize_with = "from_strs")]
+    exclude_glob: Option<Vec<Glob>>,
+}
+
+impl Defaults {
+    /// Read the defaults from the configuration file at the given path.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("read {}", path.display()))?;
+        Self::parse(&content)
+            .wrap_err_with(|| format!("parse {}", path.display()))
+    }
+
+    /// Parse the content of a configuration file.
+    fn parse(content: &str) -> Result<Self> {
+        let options = parse_options(content)?;
+        let defaults =
+            Self::deserialize(MapDeserializer::new(options.into_iter()))?;
+        if let Some(size) = &defaults.hash_size {
+            ensure!(size.len() == 2, "hash-size takes a width and a height");
+        }
+        Ok(defaults)
+    }
+
+    /// Set the options that are not given on the command line (as told by
+    /// `matches`) to their default value, if any.
+    ///
+    /// An option is also left as is when an option it conflicts with is
+    /// given, e.g. `cache` when `--db` is.
+    pub fn apply(self, opts: &mut Opts, matches: &ArgMatches<'_>) {
+        let given = |names: &[&str]| {
+            names.iter().any(|name| matches.occurrences_of(name) > 0)
+        };
+        macro_rules! merge {
+            ($($field:ident $(unless $($other:literal),+)?;)*) => {$(
+                if let Some(value) = self.$field {
+                    let name = stringify!($field).replace('_', "-");
+                    if !given(&[name.as_str() $($(, $other)+)?]) {
+                        opts.$field = value.into();
+                    }
+                }
+            )*};
+        }
+
+        merge! {
+            distance;
+            filename_fallback unless "stream";
+            flatten_for_matching unless "stream";
+            report_duplicates unless "stream";
+            align;
+            accept_distance;
+            position_weight;
+            position_window;
+            assignment;
+            metric;
+            prefer;
+            brute_force;
+            format;
+            color unless "no-color";
+            no_color unless "color";
+            recursive;
+            follow_symlinks unless "no-follow-symlinks";
+            no_follow_symlinks unless "follow-symlinks";
+            ignore_hidden unless "include-hidden";
+            include_hidden unless "ignore-hidden";
+            cache unless "db";
+            db unless "cache";
+            cache_by_content unless "db";
+            pair_identical unless "stream", "load-hashes";
+            algorithm;
+            hash_size;
+            no_dct;
+            mirror;
+            invert;
+            trim_borders;
+            grayscale;
+            equalize;
+            apply_exif_orientation;
+            pad_to_square;
+            preblur;
+            gif_frame;
+            max_decode_dim;
+            quiet;
+            threads;
+            verbose;
+            skip_errors;
+            pdf_dpi;
+            min_width;
+            min_height;
+            mmap;
+            max_pages;
+            sort_key;
+            include_ext;
+            exclude_ext;
+            exclude_glob;
+        }
+    }
+}
+
+/// Fill the options not given on the command line from the configuration file
+/// given with `--config`, or the one in the current directory (if any).
+///
+/// The file in use is recorded as `--config`.
+pub fn apply_defaults(opts: &mut Opts, matches: &ArgMatches<'_>) -> Result<()> {
+    let path = match &opts.config {
+        Some(path) => path.clone(),
+        None => match Some(PathBuf::from(DEFAULT_PATH))
+            .filter(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => return Ok(()),
+        },
+    };
+
+    Defaults::load(&path)?.apply(opts, matches);
+    opts.config = Some(path);
+    Ok(())
+}
+
+/// Deserialize a value from its string representation, like on the command
+/// line.
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Deserialize an array of values from their string representations, like on
+/// the command line.
+fn from_strs<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Value of an option in the configuration file.
+#[derive(Debug)]
+enum Value {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Self::Bool(value) => visitor.visit_bool(value),
+            Self::Integer(value) => visitor.visit_i64(value),
+            Self::Float(value) => visitor.visit_f64(value),
+            Self::String(value) => visitor.visit_string(value),
+            Self::Array(values) => {
+                visitor.visit_seq(SeqDeserializer::new(values.into_iter()))
+            },
+        }
+    }
+
+    // Every value of the file is set: absent options are left out.
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple tuple_struct
+        map struct enum identifier ignored_any
+    }
+}
+
+/// Parse the options of a configuration file, with their names normalized to
+/// those of the command line.
+fn parse_options(content: &str) -> Result<Vec<(String, Value)>> {
+    let mut options = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = parse_line(line)
+            .wrap_err_with(|| format!("invalid line {}", number + 1))?;
+        options.push((key, value));
+    }
+    Ok(options)
+}
+
+/// Parse a `key = value` line.
+fn parse_line(line: &str) -> Result<(String, Value)> {
+    if line.starts_with('[') {
+        bail!("tables are not supported");
+    }
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| eyre!("expected `key = value`"))?;
+    let key = key.trim().trim_matches('"').replace('_', "-");
+    if key.is_empty() {
+        bail!("missing option name");
+    }
+
+    let mut input = value.trim();
+    let value = if let Some(rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        input = rest.trim_start();
+        while !input.starts_with(']') {
+            let (item, rest) = parse_scalar(input)?;
+            values.push(item);
+            input = rest.trim_start();
+            input = input.strip_prefix(',').unwrap_or(input).trim_start();
+            if input.is_empty() {
+                bail!("unterminated array");
+            }
+        }
+        input = &input[1..];
+        Value::Array(values)
+    } else {
+        let (item, rest) = parse_scalar(input)?;
+        input = rest;
+        item
+    };
+
+    let rest = input.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        bail!("unexpected {:?} after the value", rest);
+    }
+    Ok((key, value))
+}
+
+/// Parse a string, number or boolean at the start of `input`, returning it with
+/// the rest of the input.
+fn parse_scalar(input: &str) -> Result<(Value, &str)> {
+    let mut chars = input.char_indices();
+    match chars.next() {
+        Some((_, quote @ ('"' | '\''))) => {
+            let mut value = String::new();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    c if c == quote => {
+                        return Ok((Value::String(value), &input[i + 1..]));
+                    },
+                    '\\' if quote == '"' => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((_, c @ ('"' | '\\'))) => value.push(c),
+                        _ => bail!("invalid escape sequence"),
+                    },
+                    c => value.push(c),
+                }
+            }
+            bail!("unterminated string")
+        },
+        Some(_) => {
+            let end = input.find([',', ']', '#']).unwrap_or(input.len());
+            let text = input[..end].trim();
+            let value = match text {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => match text.parse() {
+                    Ok(value) => Value::Integer(value),
+                    Err(_) => match text.parse() {
+                        Ok(value) => Value::Float(value),
+                        Err(_) => bail!(
+                            "invalid value {:?} (strings must be quoted)",
+                            text
+                        ),
+                    },
+                },
+            };
+            Ok((value, &input[end..]))
+        },
+        None => bail!("missing value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    /// Parse the command line, then fill the options it lacks from the given
+    /// configuration file.
+    fn with_defaults(args: &[&str], content: &str) -> Opts {
+        let args = ["img-match", "--old", "o", "--new", "n"].iter().chain(args);
+        let matches = Opts::clap()
+            .get_matches_from_safe(args)
+            .expect("parse the command line");
+        let mut opts = Opts::from_clap(&matches);
+        Defaults::parse(content)
+            .expect("parse the configuration")
+            .apply(&mut opts, &matches);
+        opts
+    }
+
+    #[test]
+    fn parse() {
+        let content = "# Hasher settings\n\
+                       algorithm = \"blockhash\"\n\
+                       hash_size = [16, 16] # width and height\n\
+                       no-dct = true\n\
+                       preblur = 1.5\n\
+                       exclude-glob = ['*.tmp', \"cover?.png\"]\n";
+        let opts = with_defaults(&[], content);
+        assert_eq!(opts.algorithm, Algorithm::Blockhash);
+        assert_eq!(opts.hash_size, [16, 16]);
+        assert!(opts.no_dct);
+        assert_eq!(opts.preblur, 1.5);
+        assert_eq!(opts.exclude_glob.len(), 2);
+
+        for content in [
+            "[options]",
+            "distance",
+            "distance = 3 4",
+            "format = json",
+            "name = \"unterminated",
+            "exclude-ext = [\"a\"",
+            "hash-size = [16]",
+            "unknown = 1",
+        ] {
+            assert!(Defaults::parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let content = "distance = 7\nalgorithm = \"mean\"\nrecursive = true";
+        let opts = with_defaults(
+            &["--distance", "3", "--algorithm", "gradient"],
+            content,
+        );
+        assert_eq!(opts.distance, Some(3));
+        assert_eq!(opts.algorithm, Algorithm::Gradient);
+        // The options not given take the value of the file.
+        assert!(opts.recursive);
+    }
+
+    #[test]
+    fn conflicting_defaults() {
+        // A default conflicting with an option given is left out.
+        let content = "cache = \"hashes.cache\"\ndb = \"hashes.db\"";
+        let opts = with_defaults(&["--db", "library.db"], content);
+        assert_eq!(opts.cache, None);
+        assert_eq!(opts.db.as_deref(), Some(Path::new("library.db")));
+        let opts = with_defaults(&["--cache", "library.cache"], content);
+        assert_eq!(opts.cache.as_deref(), Some(Path::new("library.cache")));
+        assert_eq!(opts.db, None);
+
+        let opts = with_defaults(&["--no-color"], "color = \"always\"");
+        assert!(opts.no_color);
+        assert_eq!(opts.color, ColorChoice::Auto);
+        let opts = with_defaults(&["--color", "always"], "no-color = true");
+        assert!(!opts.no_color);
+        assert_eq!(opts.color, ColorChoice::Always);
+    }
+}
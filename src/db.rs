@@ -0,0 +1,659 @@
+//! Storage of the cache in an SQLite database, for large libraries.
+//!
+//! The pages are stored in a `pages` table: path (as a blob of the bytes of
+//! the file name, so that the names which are not valid Unicode do not
+//! collide), size (in bytes),
+//! base64-encoded hash, index and hexadecimal digest of the content (`NULL`
+//! when unknown). The hash settings of these pages are stored in a `settings`
+//! table, as the value of the `hash` key.
+//!
+//! The pages are looked up one at a time, by path or by digest, rather than
+//! loaded whole, and only the pages hashed during a run are written back: the
+//! database can hold a large library.
+//!
+//! The database is handled by the SQLite library of the system, which must be
+//! installed, and only when the `sqlite` feature is enabled.
+use eyre::Result;
+#[cfg(not(feature = "sqlite"))]
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "sqlite")]
+use std::{
+    convert::{TryFrom, TryInto},
+    sync::Mutex,
+};
+
+/// A page stored in the database.
+pub struct Row {
+    pub path: PathBuf,
+    pub index: usize,
+    pub size: usize,
+    pub digest: Option<u64>,
+    /// Base64-encoded hash.
+    pub hash: String,
+}
+
+/// A database of page hashes, where the pages are looked up one at a time.
+#[cfg(feature = "sqlite")]
+pub struct Database {
+    connection: Mutex<sqlite::Database>,
+    /// Number of pages stored when the database was opened.
+    len: usize,
+}
+
+#[cfg(feature = "sqlite")]
+impl Database {
+    /// Open the database at the given path, for the pages hashed with the
+    /// given settings.
+    ///
+    /// A missing database is created, empty. When the pages were hashed with
+    /// other settings, they are all removed.
+    pub fn open(path: &Path, settings: &str) -> Result<Self> {
+        use crate::warn;
+
+        let db = sqlite::Database::open(path)?;
+        let recorded = {
+            let mut statement =
+                db.prepare("SELECT value FROM settings WHERE key = 'hash'")?;
+            match statement.step()? {
+                true => statement.column_text(0),
+                false => None,
+            }
+        };
+        let mut len = {
+            let mut statement = db.prepare("SELECT COUNT(*) FROM pages")?;
+            statement.step()?;
+            usize::try_from(statement.column_int(0))?
+        };
+        if recorded.as_deref() != Some(settings) {
+            if len > 0 {
+                warn!(
+                    "removed the {} pages of the cache {}: hashed with other \
+                     settings",
+                    len,
+                    path.display()
+                );
+            }
+            let transaction = db.transaction()?;
+            db.execute("DELETE FROM pages")?;
+            let mut statement = db.prepare(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('hash', \
+                 ?)",
+            )?;
+            statement.bind_text(1, settings)?;
+            statement.step()?;
+            drop(statement);
+            transaction.commit()?;
+            len = 0;
+        }
+
+        Ok(Self {
+            connection: Mutex::new(db),
+            len,
+        })
+    }
+
+    /// Return the number of pages stored when the database was opened.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Look the page at the given path up.
+    pub fn get(&self, path: &Path) -> Result<Option<Row>> {
+        let db = self.connection.lock().expect("database poisoned");
+        let mut statement = db.prepare(
+            "SELECT path, size, hash, idx, digest FROM pages WHERE path = ?",
+        )?;
+        statement.bind_blob(1, &path_bytes(path))?;
+        match statement.step()? {
+            true => read_row(&statement).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Look a page up by the digest of its content.
+    pub fn get_by_digest(&self, digest: u64) -> Result<Option<Row>> {
+        let db = self.connection.lock().expect("database poisoned");
+        let mut statement = db.prepare(
+            "SELECT path, size, hash, idx, digest FROM pages WHERE digest = ? \
+             LIMIT 1",
+        )?;
+        statement.bind_text(1, &format_digest(digest))?;
+        match statement.step()? {
+            true => read_row(&statement).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Store the given pages, replacing the previous entries of the same
+    /// paths.
+    pub fn write(&self, rows: impl Iterator<Item = Row>) -> Result<()> {
+        let db = self.connection.lock().expect("database poisoned");
+        let transaction = db.transaction()?;
+        let mut statement = db.prepare(
+            "INSERT OR REPLACE INTO pages (path, size, hash, idx, digest) \
+             VALUES (?, ?, ?, ?, ?)",
+        )?;
+        for row in rows {
+            statement.bind_blob(1, &path_bytes(&row.path))?;
+            statement.bind_int(2, row.size.try_into()?)?;
+            statement.bind_text(3, &row.hash)?;
+            statement.bind_int(4, row.index.try_into()?)?;
+            match row.digest {
+                Some(digest) => {
+                    statement.bind_text(5, &format_digest(digest))?
+                },
+                None => statement.bind_null(5)?,
+            }
+            statement.step()?;
+            statement.reset()?;
+        }
+        drop(statement);
+        transaction.commit()
+    }
+}
+
+/// Read the page on the current row of a statement selecting `path, size,
+/// hash, idx, digest`.
+#[cfg(feature = "sqlite")]
+fn read_row(statement: &sqlite::Statement<'_>) -> Result<Row> {
+    use eyre::Context;
+
+    let digest = statement
+        .column_text(4)
+        .map(|digest| {
+            u64::from_str_radix(&digest, 16).wrap_err("invalid digest")
+        })
+        .transpose()?;
+    Ok(Row {
+        path: path_from_bytes(statement.column_blob(0).unwrap_or_default())?,
+        size: statement
+            .column_int(1)
+            .try_into()
+            .wrap_err("invalid size")?,
+        hash: statement.column_text(2).unwrap_or_default(),
+        index: statement
+            .column_int(3)
+            .try_into()
+            .wrap_err("invalid index")?,
+        digest,
+    })
+}
+
+/// Return the bytes of a path, as stored in the database: those of the file
+/// name on Unix, and its UTF-16 code units (little-endian) on Windows.
+#[cfg(feature = "sqlite")]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+
+        path.as_os_str()
+            .encode_wide()
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        path.to_string_lossy().as_bytes().to_vec()
+    }
+}
+
+/// Return the path stored as the given bytes (see `path_bytes`).
+#[cfg(feature = "sqlite")]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+        Ok(OsString::from_vec(bytes).into())
+    }
+    #[cfg(windows)]
+    {
+        use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+        eyre::ensure!(bytes.len() % 2 == 0, "invalid path");
+        let wide = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect::<Vec<_>>();
+        Ok(OsString::from_wide(&wide).into())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        use eyre::Context;
+
+        Ok(String::from_utf8(bytes).wrap_err("invalid path")?.into())
+    }
+}
+
+/// Format a digest as stored in the database.
+#[cfg(feature = "sqlite")]
+fn format_digest(digest: u64) -> String {
+    format!("{:016x}", digest)
+}
+
+/// A database of page hashes.
+///
+/// It cannot be opened, as the support of SQLite is disabled.
+#[cfg(not(feature = "sqlite"))]
+pub struct Database(Infallible);
+
+#[cfg(not(feature = "sqlite"))]
+impl Database {
+    /// Open the database at the given path.
+    ///
+    /// Always fails, as the support of SQLite is disabled.
+    pub fn open(_path: &Path, _settings: &str) -> Result<Self> {
+        eyre::bail!("SQLite support is disabled (enable the `sqlite` feature)")
+    }
+
+    pub fn len(&self) -> usize {
+        match self.0 {}
+    }
+
+    pub fn get(&self, _path: &Path) -> Result<Option<Row>> {
+        match self.0 {}
+    }
+
+    pub fn get_by_digest(&self, _digest: u64) -> Result<Option<Row>> {
+        match self.0 {}
+    }
+
+    pub fn write(&self, _rows: impl Iterator<Item = Row>) -> Result<()> {
+        match self.0 {}
+    }
+}
+
+/// Minimal bindings to the SQLite library.
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use eyre::{ensure, eyre, Context, Result};
+    use std::{
+        convert::TryFrom,
+        ffi::{CStr, CString},
+        os::raw::{c_char, c_int, c_void},
+        path::Path,
+        ptr,
+    };
+
+    const SQLITE_OK: c_int = 0;
+    const SQLITE_ROW: c_int = 100;
+    const SQLITE_DONE: c_int = 101;
+    const SQLITE_NULL: c_int = 5;
+    const SQLITE_OPEN_READWRITE: c_int = 0x02;
+    const SQLITE_OPEN_CREATE: c_int = 0x04;
+    /// Destructor telling SQLite to copy the bound values.
+    const SQLITE_TRANSIENT: isize = -1;
+
+    /// Version of the schema, stored as the `user_version` of the database.
+    const SCHEMA_VERSION: i64 = 3;
+
+    #[repr(C)]
+    struct Sqlite3 {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct Sqlite3Stmt {
+        _private: [u8; 0],
+    }
+
+    #[link(name = "sqlite3")]
+    extern "C" {
+        fn sqlite3_open_v2(
+            filename: *const c_char,
+            db: *mut *mut Sqlite3,
+            flags: c_int,
+            vfs: *const c_char,
+        ) -> c_int;
+        fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+        fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+        fn sqlite3_exec(
+            db: *mut Sqlite3,
+            sql: *const c_char,
+            callback: *const c_void,
+            arg: *mut c_void,
+            errmsg: *mut *mut c_char,
+        ) -> c_int;
+        fn sqlite3_prepare_v2(
+            db: *mut Sqlite3,
+            sql: *const c_char,
+            len: c_int,
+            statement: *mut *mut Sqlite3Stmt,
+            tail: *mut *const c_char,
+        ) -> c_int;
+        fn sqlite3_step(statement: *mut Sqlite3Stmt) -> c_int;
+        fn sqlite3_reset(statement: *mut Sqlite3Stmt) -> c_int;
+        fn sqlite3_finalize(statement: *mut Sqlite3Stmt) -> c_int;
+        fn sqlite3_bind_text(
+            statement: *mut Sqlite3Stmt,
+            index: c_int,
+            text: *const c_char,
+            len: c_int,
+            destructor: isize,
+        ) -> c_int;
+        fn sqlite3_bind_blob(
+            statement: *mut Sqlite3Stmt,
+            index: c_int,
+            blob: *const c_void,
+            len: c_int,
+            destructor: isize,
+        ) -> c_int;
+        fn sqlite3_bind_int64(
+            statement: *mut Sqlite3Stmt,
+            index: c_int,
+            value: i64,
+        ) -> c_int;
+        fn sqlite3_bind_null(
+            statement: *mut Sqlite3Stmt,
+            index: c_int,
+        ) -> c_int;
+        fn sqlite3_column_type(
+            statement: *mut Sqlite3Stmt,
+            column: c_int,
+        ) -> c_int;
+        fn sqlite3_column_text(
+            statement: *mut Sqlite3Stmt,
+            column: c_int,
+        ) -> *const u8;
+        fn sqlite3_column_blob(
+            statement: *mut Sqlite3Stmt,
+            column: c_int,
+        ) -> *const c_void;
+        fn sqlite3_column_bytes(
+            statement: *mut Sqlite3Stmt,
+            column: c_int,
+        ) -> c_int;
+        fn sqlite3_column_int64(
+            statement: *mut Sqlite3Stmt,
+            column: c_int,
+        ) -> i64;
+    }
+
+    /// A connection to a database, closed on drop.
+    pub struct Database(*mut Sqlite3);
+
+    impl Database {
+        /// Open (or create) the database at the given path, with the table
+        /// of the pages.
+        pub fn open(path: &Path) -> Result<Self> {
+            let filename = CString::new(path.to_string_lossy().as_bytes())
+                .wrap_err("invalid database path")?;
+            let mut db = ptr::null_mut();
+            // SAFETY: the arguments are valid, and the connection is closed on
+            // drop even when the opening fails.
+            let code = unsafe {
+                sqlite3_open_v2(
+                    filename.as_ptr(),
+                    &mut db,
+                    SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+                    ptr::null(),
+                )
+            };
+            let db = Self(db);
+            if code != SQLITE_OK {
+                return Err(db.error())
+                    .wrap_err_with(|| format!("open {}", path.display()));
+            }
+
+            let version = {
+                let mut statement = db.prepare("PRAGMA user_version")?;
+                statement.step()?;
+                statement.column_int(0)
+            };
+            // The first version only lacks the settings, and the first two
+            // stored the paths as (lossy) text: their pages are hashed again.
+            ensure!(
+                (0..=SCHEMA_VERSION).contains(&version),
+                "unsupported cache format"
+            );
+            if (1..3).contains(&version) {
+                db.execute("DROP TABLE IF EXISTS pages")?;
+            }
+            db.execute(&format!(
+                "CREATE TABLE IF NOT EXISTS pages (path BLOB PRIMARY KEY, \
+                 size INTEGER NOT NULL, hash TEXT NOT NULL, idx INTEGER NOT \
+                 NULL, digest TEXT); CREATE TABLE IF NOT EXISTS settings \
+                 (key TEXT PRIMARY KEY, value TEXT NOT NULL); PRAGMA \
+                 user_version = {}; CREATE INDEX IF NOT EXISTS pages_digest \
+                 ON pages (digest)",
+                SCHEMA_VERSION
+            ))?;
+            Ok(db)
+        }
+
+        /// Run the given SQL statements, ignoring their results.
+        pub fn execute(&self, sql: &str) -> Result<()> {
+            let sql = CString::new(sql).wrap_err("invalid statement")?;
+            // SAFETY: the connection is open, and no callback is given.
+            let code = unsafe {
+                sqlite3_exec(
+                    self.0,
+                    sql.as_ptr(),
+                    ptr::null(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                )
+            };
+            self.check(code)
+        }
+
+        /// Begin a transaction, rolled back unless committed.
+        pub fn transaction(&self) -> Result<Transaction<'_>> {
+            self.execute("BEGIN")?;
+            Ok(Transaction {
+                db: self,
+                committed: false,
+            })
+        }
+
+        /// Prepare a single SQL statement.
+        pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+            let sql = CString::new(sql).wrap_err("invalid statement")?;
+            let mut statement = ptr::null_mut();
+            // SAFETY: the connection is open, and the statement is finalized
+            // on drop.
+            let code = unsafe {
+                sqlite3_prepare_v2(
+                    self.0,
+                    sql.as_ptr(),
+                    -1,
+                    &mut statement,
+                    ptr::null_mut(),
+                )
+            };
+            self.check(code)?;
+            Ok(Statement {
+                db: self,
+                statement,
+            })
+        }
+
+        /// Turn a result code into an error, with the last error message.
+        fn check(&self, code: c_int) -> Result<()> {
+            match code {
+                SQLITE_OK => Ok(()),
+                _ => Err(self.error()),
+            }
+        }
+
+        fn error(&self) -> eyre::Report {
+            // SAFETY: the message is valid until the next call, and copied.
+            let message = unsafe { CStr::from_ptr(sqlite3_errmsg(self.0)) };
+            eyre!("SQLite error: {}", message.to_string_lossy())
+        }
+    }
+
+    impl Drop for Database {
+        fn drop(&mut self) {
+            // SAFETY: every statement borrows the connection, so they are
+            // already finalized.
+            unsafe {
+                sqlite3_close(self.0);
+            }
+        }
+    }
+
+    // SAFETY: SQLite connections can be used from any thread, one at a time
+    // (the statements borrow the connection, so they cannot be sent apart).
+    unsafe impl Send for Database {}
+
+    /// A transaction, rolled back on drop unless committed.
+    pub struct Transaction<'a> {
+        db: &'a Database,
+        committed: bool,
+    }
+
+    impl Transaction<'_> {
+        /// Commit the transaction.
+        ///
+        /// It is rolled back when the commit fails.
+        pub fn commit(mut self) -> Result<()> {
+            self.db.execute("COMMIT")?;
+            self.committed = true;
+            Ok(())
+        }
+    }
+
+    impl Drop for Transaction<'_> {
+        fn drop(&mut self) {
+            if !self.committed {
+                // Nothing more can be done about a failed rollback: SQLite
+                // rolls the transaction back when the connection is closed.
+                let _ = self.db.execute("ROLLBACK");
+            }
+        }
+    }
+
+    /// A prepared statement, finalized on drop.
+    pub struct Statement<'a> {
+        db: &'a Database,
+        statement: *mut Sqlite3Stmt,
+    }
+
+    impl Statement<'_> {
+        /// Run the statement until its next row, returning whether there is
+        /// one.
+        pub fn step(&mut self) -> Result<bool> {
+            // SAFETY: the statement is prepared.
+            match unsafe { sqlite3_step(self.statement) } {
+                SQLITE_ROW => Ok(true),
+                SQLITE_DONE => Ok(false),
+                _ => Err(self.db.error()),
+            }
+        }
+
+        /// Reset the statement, to run it again.
+        pub fn reset(&mut self) -> Result<()> {
+            // SAFETY: the statement is prepared.
+            self.db.check(unsafe { sqlite3_reset(self.statement) })
+        }
+
+        /// Bind a text to the parameter at `index` (from 1).
+        pub fn bind_text(&mut self, index: c_int, text: &str) -> Result<()> {
+            // SAFETY: the text is copied by SQLite.
+            let code = unsafe {
+                sqlite3_bind_text(
+                    self.statement,
+                    index,
+                    text.as_ptr().cast(),
+                    c_int::try_from(text.len())?,
+                    SQLITE_TRANSIENT,
+                )
+            };
+            self.db.check(code)
+        }
+
+        /// Bind a blob to the parameter at `index` (from 1).
+        pub fn bind_blob(&mut self, index: c_int, blob: &[u8]) -> Result<()> {
+            // SAFETY: the blob is copied by SQLite.
+            let code = unsafe {
+                sqlite3_bind_blob(
+                    self.statement,
+                    index,
+                    blob.as_ptr().cast(),
+                    c_int::try_from(blob.len())?,
+                    SQLITE_TRANSIENT,
+                )
+            };
+            self.db.check(code)
+        }
+
+        /// Bind an integer to the parameter at `index` (from 1).
+        pub fn bind_int(&mut self, index: c_int, value: i64) -> Result<()> {
+            // SAFETY: the statement is prepared.
+            let code =
+                unsafe { sqlite3_bind_int64(self.statement, index, value) };
+            self.db.check(code)
+        }
+
+        /// Bind `NULL` to the parameter at `index` (from 1).
+        pub fn bind_null(&mut self, index: c_int) -> Result<()> {
+            // SAFETY: the statement is prepared.
+            let code = unsafe { sqlite3_bind_null(self.statement, index) };
+            self.db.check(code)
+        }
+
+        /// Return the text in the given column (from 0) of the current row,
+        /// unless it is `NULL`.
+        pub fn column_text(&self, column: c_int) -> Option<String> {
+            // SAFETY: the statement is on a row, and the text is valid for
+            // `len` bytes until the next step (it is copied).
+            unsafe {
+                if sqlite3_column_type(self.statement, column) == SQLITE_NULL {
+                    return None;
+                }
+                let text = sqlite3_column_text(self.statement, column);
+                if text.is_null() {
+                    return Some(String::new());
+                }
+                let len = sqlite3_column_bytes(self.statement, column);
+                let bytes = std::slice::from_raw_parts(text, len as usize);
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            }
+        }
+
+        /// Return the blob in the given column (from 0) of the current row,
+        /// unless it is `NULL`.
+        pub fn column_blob(&self, column: c_int) -> Option<Vec<u8>> {
+            // SAFETY: the statement is on a row, and the blob is valid for
+            // `len` bytes until the next step (it is copied).
+            unsafe {
+                if sqlite3_column_type(self.statement, column) == SQLITE_NULL {
+                    return None;
+                }
+                let blob = sqlite3_column_blob(self.statement, column);
+                if blob.is_null() {
+                    return Some(Vec::new());
+                }
+                let len = sqlite3_column_bytes(self.statement, column);
+                Some(
+                    std::slice::from_raw_parts(blob.cast::<u8>(), len as usize)
+                        .to_vec(),
+                )
+            }
+        }
+
+        /// Return the integer in the given column (from 0) of the current row.
+        pub fn column_int(&self, column: c_int) -> i64 {
+            // SAFETY: the statement is on a row.
+            unsafe { sqlite3_column_int64(self.statement, column) }
+        }
+    }
+
+    impl Drop for Statement<'_> {
+        fn drop(&mut self) {
+            // SAFETY: the statement is prepared (or null, which is a no-op).
+            unsafe {
+                sqlite3_finalize(self.statement);
+            }
+        }
+    }
+}
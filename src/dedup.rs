@@ -0,0 +1,98 @@
+//! Detection of duplicate pages within a single version.
+use crate::HashedImage;
+use bktree::BkTree;
+use img_hash::ImageHash;
+use std::collections::HashMap;
+
+/// Pages sharing the same hash, identified by their position in the input.
+struct Group {
+    hash: ImageHash,
+    pages: Vec<usize>,
+}
+
+fn group_distance(group1: &Group, group2: &Group) -> isize {
+    group1.hash.dist(&group2.hash) as isize
+}
+
+/// Group together the pages that are within `distance` of each other.
+///
+/// Being "within distance" is transitive here: if A is close to B and B is
+/// close to C, then A, B and C end up in the same cluster.
+///
+/// Only the clusters of at least two pages are returned, ordered by the
+/// position of their first page. Pages are ordered by position inside each
+/// cluster.
+pub fn find_duplicates(
+    images: Vec<HashedImage>,
+    distance: u8,
+) -> Vec<Vec<HashedImage>> {
+    // The BK-tree ignores elements at a distance of 0 from an existing one, so
+    // identical hashes must be grouped beforehand.
+    let mut groups = HashMap::<_, Vec<_>>::new();
+    for (i, image) in images.iter().enumerate() {
+        groups.entry(image.hash.clone()).or_default().push(i);
+    }
+    let mut hashes = BkTree::new(group_distance);
+    hashes.insert_all(
+        groups
+            .into_iter()
+            .map(|(hash, pages)| Group { hash, pages }),
+    );
+
+    // Merge the groups that are close enough, using an union-find.
+    let mut parents = (0..images.len()).collect::<Vec<_>>();
+    for group in hashes.iter() {
+        let query = Group {
+            hash: group.hash.clone(),
+            pages: Vec::new(),
+        };
+        let root = group.pages[0];
+        for (other, _) in hashes.find(query, distance.into()) {
+            for &page in &other.pages {
+                union(&mut parents, root, page);
+            }
+        }
+    }
+
+    let mut clusters = HashMap::<_, Vec<_>>::new();
+    for i in 0..images.len() {
+        clusters.entry(find(&mut parents, i)).or_default().push(i);
+    }
+    let mut clusters = clusters
+        .into_values()
+        .filter(|cluster| cluster.len() > 1)
+        .map(|cluster| {
+            let mut cluster = cluster
+                .into_iter()
+                .map(|i| images[i].clone())
+                .collect::<Vec<_>>();
+            cluster.sort_by_key(|image| image.index);
+            cluster
+        })
+        .collect::<Vec<_>>();
+    clusters.sort_by_key(|cluster| cluster[0].index);
+
+    clusters
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+
+    // Path compression, to keep the lookups fast.
+    let mut i = i;
+    while parents[i] != root {
+        let next = parents[i];
+        parents[i] = root;
+        i = next;
+    }
+
+    root
+}
+
+fn union(parents: &mut [usize], i: usize, j: usize) {
+    let (i, j) = (find(parents, i), find(parents, j));
+    parents[i] = j;
+}
@@ -0,0 +1,230 @@
+//! Presentation of the matching as a diff between the two versions.
+use crate::{HashedImage, MatchReport};
+
+/// How a page changed between the two versions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// The page is in both versions, in the same order.
+    Kept,
+    /// The page is in both versions, but moved relative to the others.
+    Reordered,
+    /// The page only exists in the new version.
+    Inserted,
+    /// The page only exists in the old version.
+    Deleted,
+}
+
+/// A page of the diff.
+#[derive(Clone, Debug)]
+pub struct PageChange {
+    pub change: Change,
+    /// Page from the old version, if any.
+    pub old: Option<HashedImage>,
+    /// Page from the new version, if any.
+    pub new: Option<HashedImage>,
+}
+
+/// Turn the matching into a diff between the two versions.
+///
+/// The longest sequence of matches in the same order in both versions is kept
+/// as is, and the other matches are flagged as reordered. The pages are listed
+/// in the order of the new version, with the deleted pages inserted where they
+/// used to be.
+pub fn diff_pages(report: &MatchReport) -> Vec<PageChange> {
+    // Find the matches that are kept in order.
+    let old_indices = report
+        .mapping
+        .iter()
+        .filter_map(|m| m.dst.as_ref().map(|(image, _)| image.index))
+        .collect::<Vec<_>>();
+    let backbone = longest_increasing_subsequence(&old_indices);
+
+    let mut deleted = report.missing.iter().collect::<Vec<_>>();
+    deleted.sort_by_key(|image| image.index);
+    let mut deleted = deleted.into_iter().peekable();
+
+    let mut changes = Vec::new();
+    let mut position = 0;
+    for m in &report.mapping {
+        let change = match &m.dst {
+            Some((image, _)) => {
+                // The backbone holds the positions among the matches.
+                let kept = backbone.binary_search(&position).is_ok();
+                position += 1;
+                if kept {
+                    // Deleted pages go right before the next kept page.
+                    while let Some(page) =
+                        deleted.next_if(|page| page.index < image.index)
+                    {
+                        changes.push(PageChange {
+                            change: Change::Deleted,
+                            old: Some(page.clone()),
+                            new: None,
+                        });
+                    }
+                    Change::Kept
+                } else {
+                    Change::Reordered
+                }
+            },
+            None => Change::Inserted,
+        };
+        changes.push(PageChange {
+            change,
+            old: m.dst.as_ref().map(|(image, _)| image.clone()),
+            new: Some(m.src.clone()),
+        });
+    }
+    changes.extend(deleted.map(|page| PageChange {
+        change: Change::Deleted,
+        old: Some(page.clone()),
+        new: None,
+    }));
+
+    changes
+}
+
+/// Return the positions of a longest strictly increasing subsequence of
+/// `values`, in order.
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    // `tails[k]` is the position of the smallest value ending an increasing
+    // subsequence of length `k + 1`.
+    let mut tails = Vec::<usize>::new();
+    let mut previous = vec![None; values.len()];
+
+    for (i, &value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&j| values[j] < value);
+        if length > 0 {
+            previous[i] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(i);
+        } else {
+            tails[length] = i;
+        }
+    }
+
+    let mut positions = Vec::with_capacity(tails.len());
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        positions.push(i);
+        current = previous[i];
+    }
+    positions.reverse();
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Match;
+    use img_hash::ImageHash;
+    use std::time::Duration;
+
+    /// Build the page at the given position of a version.
+    fn page(index: usize) -> HashedImage {
+        HashedImage {
+            path: format!("p{}", index).into(),
+            index,
+            size: 0,
+            hash: ImageHash::from_bytes(&[0; 8]).expect("hash"),
+            mirror_hash: None,
+            inverted_hash: None,
+            thumbnail: None,
+            digest: None,
+            dimensions: None,
+        }
+    }
+
+    /// Build the report of the new pages matching the old pages at the given
+    /// positions (`None` for a new page), out of `old` pages.
+    fn report(matches: &[Option<usize>], old: usize) -> MatchReport {
+        let mapping = matches
+            .iter()
+            .enumerate()
+            .map(|(index, matched)| Match {
+                src: page(index),
+                dst: matched.map(|old| (page(old), 0)),
+                uncertain: false,
+                filename_match: false,
+                mirrored: false,
+                inverted: false,
+                duplicate: false,
+                identical: false,
+                runner_up: None,
+            })
+            .collect();
+        let missing = (0..old)
+            .filter(|index| !matches.contains(&Some(*index)))
+            .map(page)
+            .collect();
+        MatchReport {
+            mapping,
+            missing,
+            cost: 0.,
+            offset: 0,
+            indexing: Duration::default(),
+            duration: Duration::default(),
+        }
+    }
+
+    /// Summarize the diff as its changes, with the old and new positions.
+    fn changes(
+        report: &MatchReport,
+    ) -> Vec<(Change, Option<usize>, Option<usize>)> {
+        diff_pages(report)
+            .iter()
+            .map(|page| {
+                let index = |image: &Option<HashedImage>| {
+                    image.as_ref().map(|image| image.index)
+                };
+                (page.change, index(&page.old), index(&page.new))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longest_subsequence() {
+        let lis = longest_increasing_subsequence;
+        assert_eq!(lis(&[]), Vec::<usize>::new());
+        assert_eq!(lis(&[3]), [0]);
+        assert_eq!(lis(&[0, 1, 2, 3]), [0, 1, 2, 3]);
+        assert_eq!(lis(&[3, 2, 1, 0]).len(), 1);
+        // Strictly increasing: the repeated values are left out.
+        assert_eq!(lis(&[1, 1, 1]).len(), 1);
+        assert_eq!(lis(&[0, 5, 1, 2, 6, 3]), [0, 2, 3, 5]);
+        assert_eq!(lis(&[4, 0, 1, 5, 2, 3]), [1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn moved_page() {
+        // The last page of the old version moved to the front.
+        let report = report(&[Some(3), Some(0), Some(1), Some(2)], 4);
+        assert_eq!(
+            changes(&report),
+            [
+                (Change::Reordered, Some(3), Some(0)),
+                (Change::Kept, Some(0), Some(1)),
+                (Change::Kept, Some(1), Some(2)),
+                (Change::Kept, Some(2), Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn inserted_and_deleted_pages() {
+        // The second page was replaced, and the last one removed.
+        let report = report(&[Some(0), None, Some(2), Some(3)], 5);
+        assert_eq!(
+            changes(&report),
+            [
+                (Change::Kept, Some(0), Some(0)),
+                (Change::Inserted, None, Some(1)),
+                (Change::Deleted, Some(1), None),
+                (Change::Kept, Some(2), Some(2)),
+                (Change::Kept, Some(3), Some(3)),
+                (Change::Deleted, Some(4), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Fast, non-cryptographic, digest of file contents.
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Compute the 64-bit FNV-1a digest of the data.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut digest = Fnv1a::new();
+    digest.update(data);
+    digest.finish()
+}
+
+/// FNV-1a digest computed a part of the data at a time (see [`fnv1a`]).
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self(OFFSET_BASIS)
+    }
+
+    /// Add the next part of the data.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |digest, &byte| {
+            (digest ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,107 @@
+//! Extraction of pages into a directory.
+use crate::{archive, pdf, HashOptions, HashedImage};
+use eyre::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// Copy the given pages, found under `root`, into the `destination`
+/// directory.
+///
+/// Pages keep their path relative to `root`, and are extracted first when
+/// `root` is an archive or a PDF document. Returns the number of pages copied.
+pub fn extract_pages(
+    root: &Path,
+    images: &[&HashedImage],
+    options: &HashOptions,
+    destination: &Path,
+) -> Result<usize> {
+    if images.is_empty() {
+        return Ok(0);
+    }
+    fs::create_dir_all(destination)
+        .wrap_err_with(|| format!("create {}", destination.display()))?;
+
+    if !archive::is_archive(root)? && !pdf::is_pdf(root)? {
+        for image in images {
+            let source = root.join(&image.path);
+            let target = target_path(destination, &image.path)?;
+            fs::copy(&source, &target).wrap_err_with(|| {
+                format!("copy {} to {}", source.display(), target.display())
+            })?;
+        }
+        return Ok(images.len());
+    }
+
+    let pages = read_pages(root, images, options)?;
+    for (filename, contents) in &pages {
+        let target = target_path(destination, filename)?;
+        fs::write(&target, contents)
+            .wrap_err_with(|| format!("write {}", target.display()))?;
+    }
+    Ok(pages.len())
+}
+
+/// Read the content of the given pages, found under `root`, by filename.
+///
+/// Pages are extracted first when `root` is an archive or a PDF document.
+pub fn read_pages(
+    root: &Path,
+    images: &[&HashedImage],
+    options: &HashOptions,
+) -> Result<HashMap<OsString, Vec<u8>>> {
+    if images.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let entries = if archive::is_archive(root)? {
+        archive::read_entries(root).wrap_err_with(|| {
+            format!("extract pages from {}", root.display())
+        })?
+    } else if pdf::is_pdf(root)? {
+        pdf::rasterize(root, options.pdf_dpi)
+            .wrap_err_with(|| format!("rasterize {}", root.display()))?
+    } else {
+        return images
+            .iter()
+            .map(|image| {
+                let path = root.join(&image.path);
+                let contents = fs::read(&path)
+                    .wrap_err_with(|| format!("read {}", path.display()))?;
+                Ok((image.path.clone(), contents))
+            })
+            .collect();
+    };
+
+    let filenames = images
+        .iter()
+        .map(|image| image.path.as_os_str())
+        .collect::<HashSet<_>>();
+    Ok(entries
+        .into_iter()
+        .filter(|entry| filenames.contains(entry.name.as_os_str()))
+        .map(|entry| (entry.name.into_os_string(), entry.contents))
+        .collect())
+}
+
+/// Return where to copy the page with the given filename, creating its parent
+/// directories.
+///
+/// Only the normal components of the filename are kept, so that the pages
+/// listed by absolute path (or outside of their root) cannot escape from the
+/// destination directory.
+fn target_path(destination: &Path, filename: &OsStr) -> Result<PathBuf> {
+    let relative = Path::new(filename)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect::<PathBuf>();
+    let target = destination.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("create {}", parent.display()))?;
+    }
+    Ok(target)
+}
@@ -0,0 +1,174 @@
+//! Workarounds for the image formats that are only partially supported by the
+//! decoders.
+use std::convert::TryInto;
+
+/// Size of a RIFF (or PNG) chunk header: FourCC and length.
+const CHUNK_HEADER_SIZE: usize = 8;
+/// Size of the header of an animation frame, before its data.
+const FRAME_HEADER_SIZE: usize = 16;
+/// EXIF tag of the orientation of the image.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Name the format of the content when the decoders cannot handle it at all:
+/// AVIF (ISO-BMFF with an AVIF brand), or lossless WebP.
+pub fn unsupported_format(contents: &[u8]) -> Option<&'static str> {
+    if contents.get(4..8) == Some(b"ftyp")
+        && matches!(contents.get(8..12), Some(b"avif") | Some(b"avis"))
+    {
+        return Some("AVIF");
+    }
+    match webp_bitstream(contents) {
+        Some((b"VP8L", _)) => Some("lossless WebP"),
+        _ => None,
+    }
+}
+
+/// Extract the first frame of an extended WebP image, as a simple WebP image.
+///
+/// The decoder only handles simple lossy WebP images, so extended ones (with
+/// an alpha channel or an animation) are reduced to the lossy bitstream of
+/// their first frame: the transparency is dropped, the transparent areas
+/// keeping whatever color the encoder left there. Returns `None` for the other
+/// images, that are left as is.
+pub fn simple_webp(contents: &[u8]) -> Option<Vec<u8>> {
+    if contents.get(12..16) != Some(b"VP8X") {
+        return None;
+    }
+    let bitstream = match webp_bitstream(contents)? {
+        (b"VP8 ", bitstream) => bitstream,
+        _ => return None,
+    };
+
+    let padding = bitstream.len() % 2;
+    let mut webp = Vec::with_capacity(20 + bitstream.len() + padding);
+    webp.extend_from_slice(b"RIFF");
+    webp.extend_from_slice(
+        &((12 + bitstream.len() + padding) as u32).to_le_bytes(),
+    );
+    webp.extend_from_slice(b"WEBPVP8 ");
+    webp.extend_from_slice(&(bitstream.len() as u32).to_le_bytes());
+    webp.extend_from_slice(bitstream);
+    webp.resize(webp.len() + padding, 0);
+
+    Some(webp)
+}
+
+/// Find the bitstream of the first frame of a WebP image, with its FourCC:
+/// `VP8 ` when lossy, `VP8L` when lossless.
+fn webp_bitstream(contents: &[u8]) -> Option<(&[u8], &[u8])> {
+    if contents.get(0..4) != Some(b"RIFF")
+        || contents.get(8..12) != Some(b"WEBP")
+    {
+        return None;
+    }
+    let is_bitstream = |fourcc: &[u8]| matches!(fourcc, b"VP8 " | b"VP8L");
+
+    riff_chunks(&contents[12..])
+        .into_iter()
+        .find_map(|(fourcc, data)| match fourcc {
+            _ if is_bitstream(fourcc) => Some((fourcc, data)),
+            b"ANMF" => riff_chunks(data.get(FRAME_HEADER_SIZE..)?)
+                .into_iter()
+                .find(|(fourcc, _)| is_bitstream(fourcc)),
+            _ => None,
+        })
+}
+
+/// Return the EXIF orientation of a JPEG or PNG image, from 1 (upright) to 8,
+/// if any.
+pub fn exif_orientation(contents: &[u8]) -> Option<u16> {
+    let exif = jpeg_exif(contents).or_else(|| png_exif(contents))?;
+    let orientation = tiff_orientation(exif)?;
+    (1..=8).contains(&orientation).then_some(orientation)
+}
+
+/// Find the EXIF data (a TIFF structure) in the APP1 segment of a JPEG image.
+fn jpeg_exif(contents: &[u8]) -> Option<&[u8]> {
+    let mut data = contents.strip_prefix(&[0xFF, 0xD8])?;
+    // The metadata segments come first, up to the start of the scan.
+    while let [0xFF, marker, rest @ ..] = data {
+        if matches!(marker, 0xD9 | 0xDA) {
+            break;
+        }
+        let len =
+            usize::from(u16::from_be_bytes([*rest.first()?, *rest.get(1)?]));
+        let segment = rest.get(2..len)?;
+        if *marker == 0xE1 {
+            if let Some(exif) = segment.strip_prefix(b"Exif\0\0") {
+                return Some(exif);
+            }
+        }
+        data = rest.get(len..)?;
+    }
+    None
+}
+
+/// Find the EXIF data (a TIFF structure) in the `eXIf` chunk of a PNG image.
+fn png_exif(contents: &[u8]) -> Option<&[u8]> {
+    let mut data = contents.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    while data.len() >= CHUNK_HEADER_SIZE {
+        let len =
+            u32::from_be_bytes(data[..4].try_into().expect("4 bytes")) as usize;
+        let (kind, rest) = data[4..].split_at(4);
+        // The chunk data is followed by its CRC.
+        let chunk = rest.get(..len)?;
+        match kind {
+            b"eXIf" => return Some(chunk),
+            b"IDAT" | b"IEND" => break,
+            _ => data = rest.get(len + 4..)?,
+        }
+    }
+    None
+}
+
+/// Read the orientation tag from the first IFD of a TIFF structure.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let count = usize::from(u16_at(ifd)?);
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        // The value is a single SHORT, stored in place.
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+/// Split the data into RIFF chunks, stopping at the first truncated one.
+fn riff_chunks(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    while data.len() >= CHUNK_HEADER_SIZE {
+        let (fourcc, len) = data.split_at(4);
+        let len =
+            u32::from_le_bytes(len[..4].try_into().expect("4 bytes")) as usize;
+        let chunk = match data.get(CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + len) {
+            Some(chunk) => chunk,
+            None => break,
+        };
+        chunks.push((fourcc, chunk));
+        // Chunks are padded to an even size.
+        let next = CHUNK_HEADER_SIZE + len + len % 2;
+        data = data.get(next..).unwrap_or_default();
+    }
+    chunks
+}
@@ -0,0 +1,2147 @@
+//! Loading and hashing of the pages.
+use crate::{
+    archive,
+    debug,
+    formats,
+    http,
+    info,
+    mmap::{self, Contents, Streamed},
+    natural,
+    pdf,
+    trace,
+    warn,
+    Cache,
+    ProgressBar,
+    Task,
+};
+use eyre::{bail, ensure, eyre, Context, Report, Result};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder},
+    imageops,
+    io::Reader as ImageReader,
+    AnimationDecoder,
+    DynamicImage,
+    GenericImageView,
+    ImageFormat,
+    ImageOutputFormat,
+    ImageResult,
+    Rgba,
+    RgbaImage,
+};
+use img_hash::{HashAlg, Hasher, HasherConfig, ImageHash};
+use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::{self, Write as _},
+    fs,
+    io::{Cursor, Read, Seek},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Perceptual hash algorithm.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    Mean,
+    Gradient,
+    VertGradient,
+    DoubleGradient,
+    Blockhash,
+}
+
+impl Algorithm {
+    /// Name of every algorithm, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &[
+        "mean",
+        "gradient",
+        "vert-gradient",
+        "double-gradient",
+        "blockhash",
+    ];
+}
+
+impl FromStr for Algorithm {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "mean" => Self::Mean,
+            "gradient" => Self::Gradient,
+            "vert-gradient" => Self::VertGradient,
+            "double-gradient" => Self::DoubleGradient,
+            "blockhash" => Self::Blockhash,
+            _ => bail!("invalid algorithm {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mean => "mean",
+            Self::Gradient => "gradient",
+            Self::VertGradient => "vert-gradient",
+            Self::DoubleGradient => "double-gradient",
+            Self::Blockhash => "blockhash",
+        })
+    }
+}
+
+impl From<Algorithm> for HashAlg {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Mean => Self::Mean,
+            Algorithm::Gradient => Self::Gradient,
+            Algorithm::VertGradient => Self::VertGradient,
+            Algorithm::DoubleGradient => Self::DoubleGradient,
+            Algorithm::Blockhash => Self::Blockhash,
+        }
+    }
+}
+
+/// Order of the pages, which gives them their index.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SortKey {
+    /// By path, as is.
+    Path,
+    /// By path, ignoring the case.
+    NameCi,
+    /// By path, comparing the numbers by value.
+    Natural,
+}
+
+impl SortKey {
+    /// Name of every order, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &["path", "name-ci", "natural"];
+
+    /// Compare two paths in this order.
+    ///
+    /// Paths are compared component by component, so that the pages of a
+    /// directory stay together.
+    pub fn compare(self, a: &Path, b: &Path) -> Ordering {
+        match self {
+            Self::Path => a.cmp(b),
+            Self::NameCi => natural::compare_paths_ci(a, b),
+            Self::Natural => natural::compare_paths(a, b),
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "path" => Self::Path,
+            "name-ci" => Self::NameCi,
+            "natural" => Self::Natural,
+            _ => bail!("invalid sort key {}", s),
+        })
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Path => "path",
+            Self::NameCi => "name-ci",
+            Self::Natural => "natural",
+        })
+    }
+}
+
+/// A range of pages, by position in their order, that are the only ones hashed.
+///
+/// Given as `FIRST:LAST`, counting from 1 and both included; either end can be
+/// left out to start from the first page, or stop at the last one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PageRange {
+    /// Index of the first page.
+    start: usize,
+    /// Index past the last page, if any.
+    end: Option<usize>,
+}
+
+impl PageRange {
+    /// Check if the page at the given index is in the range.
+    pub fn contains(self, index: usize) -> bool {
+        index >= self.start && self.end.map_or(true, |end| index < end)
+    }
+}
+
+impl FromStr for PageRange {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (first, last) = s.split_once(':').ok_or_else(|| {
+            eyre!("invalid page range {}, expected FIRST:LAST", s)
+        })?;
+        let position = |value: &str| -> Result<Option<usize>> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            let position = value
+                .parse::<usize>()
+                .wrap_err_with(|| format!("invalid page number {}", value))?;
+            ensure!(position > 0, "pages are numbered from 1");
+            Ok(Some(position))
+        };
+        let (first, last) = (position(first)?, position(last)?);
+        if let (Some(first), Some(last)) = (first, last) {
+            ensure!(first <= last, "the page range {} is empty", s);
+        }
+
+        Ok(Self {
+            start: first.map_or(0, |first| first - 1),
+            end: last,
+        })
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.start + 1)?;
+        match self.end {
+            Some(end) => write!(f, "{}", end),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A pattern matched against the filenames of the pages, ignoring the case.
+///
+/// `*` stands for any sequence of characters, and `?` for any single one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Glob {
+    /// Characters of the pattern, lowercased.
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    /// Check if the whole name matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase().chars().collect::<Vec<_>>();
+        let (mut p, mut n) = (0, 0);
+        // Positions right after the last star, in the pattern and the name,
+        // to backtrack to when the rest doesn't match.
+        let mut star = None;
+        while n < name.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    p += 1;
+                    star = Some((p, n));
+                },
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                },
+                _ => match star {
+                    // Let the star match one more character.
+                    Some((after, start)) => {
+                        p = after;
+                        n = start + 1;
+                        star = Some((after, n));
+                    },
+                    None => return false,
+                },
+            }
+        }
+        self.pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+impl FromStr for Glob {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ensure!(!s.is_empty(), "invalid glob, the pattern is empty");
+        Ok(Self {
+            pattern: s.to_lowercase().chars().collect(),
+        })
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern.iter().collect::<String>())
+    }
+}
+
+/// Settings of the perceptual hash.
+///
+/// Hashes are only comparable when computed with the same settings.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HashConfig {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) dct: bool,
+    pub(crate) trim_borders: bool,
+    pub(crate) grayscale: bool,
+    pub(crate) equalize: bool,
+    pub(crate) exif_orientation: bool,
+    pub(crate) pad_to_square: bool,
+    /// Standard deviation of the blur, as bits to keep the settings
+    /// comparable.
+    pub(crate) preblur: Option<u32>,
+    /// Frame of the animated pages to hash.
+    pub(crate) frame: u32,
+    /// Largest width or height of the pages to hash, beyond which they are
+    /// downscaled.
+    pub(crate) max_dimension: Option<u32>,
+}
+
+impl HashConfig {
+    /// Initialize a new configuration.
+    pub fn new(algorithm: Algorithm, width: u32, height: u32) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "hash size cannot be null");
+
+        Ok(Self {
+            algorithm,
+            width,
+            height,
+            ..Self::default()
+        })
+    }
+
+    /// Enable or disable the DCT preprocessing (enabled by default).
+    pub fn dct(mut self, enabled: bool) -> Self {
+        self.dct = enabled;
+        self
+    }
+
+    /// Enable or disable the cropping of the uniform borders of the pages
+    /// before hashing them (disabled by default), so that the same page with
+    /// different margins gets a similar hash.
+    pub fn trim_borders(mut self, enabled: bool) -> Self {
+        self.trim_borders = enabled;
+        self
+    }
+
+    /// Enable or disable the conversion of the pages to grayscale (their
+    /// luminance) before hashing them (disabled by default), so that a color
+    /// page and its grayscale reprint get a similar hash.
+    pub fn grayscale(mut self, enabled: bool) -> Self {
+        self.grayscale = enabled;
+        self
+    }
+
+    /// Enable or disable the equalization of the histogram of the luminance
+    /// of the pages before hashing them (disabled by default), so that the
+    /// pages reprinted with other tones get a similar hash.
+    ///
+    /// The conversion to grayscale of a reprint rarely gives the same tones
+    /// as the original: equalizing the luminance evens the tones out, but
+    /// also strengthens the noise of the scans.
+    pub fn equalize(mut self, enabled: bool) -> Self {
+        self.equalize = enabled;
+        self
+    }
+
+    /// Enable or disable the rotation of the pages as told by their EXIF
+    /// orientation, if any, before hashing them (disabled by default), so that
+    /// a page stored rotated gets the same hash as its upright twin.
+    pub fn exif_orientation(mut self, enabled: bool) -> Self {
+        self.exif_orientation = enabled;
+        self
+    }
+
+    /// Enable or disable the padding of the pages into a square before
+    /// hashing them (disabled by default), so that the pages whose aspect
+    /// ratio changed slightly are not squashed differently when downscaled.
+    pub fn pad_to_square(mut self, enabled: bool) -> Self {
+        self.pad_to_square = enabled;
+        self
+    }
+
+    /// Blur the pages with a Gaussian of the given standard deviation before
+    /// hashing them (0, the default, to disable), so that the pages
+    /// re-compressed with blocking artifacts still get a similar hash.
+    pub fn preblur(mut self, sigma: f32) -> Self {
+        self.preblur = (sigma > 0.).then(|| sigma.to_bits());
+        self
+    }
+
+    /// Pick the frame of the animated pages (GIF or APNG) to hash, counting
+    /// from 0 (the first one, by default).
+    ///
+    /// The frames are composited as they would be displayed, and the last
+    /// one is hashed when there are fewer frames, so that an animated page
+    /// always gets the same hash.
+    pub fn frame(mut self, index: u32) -> Self {
+        self.frame = index;
+        self
+    }
+
+    /// Downscale the decoded pages larger than this many pixels (in width or
+    /// height) before hashing them (0, the default, to disable).
+    ///
+    /// The hashers shrink the pages anyway, so the hashes barely change, but
+    /// the preprocessing of huge scans gets much faster and lighter.
+    pub fn max_dimension(mut self, pixels: u32) -> Self {
+        self.max_dimension = (pixels > 0).then_some(pixels);
+        self
+    }
+
+    /// Number of bits of the hashes, i.e. the maximum distance between two
+    /// pages.
+    pub fn bits(&self) -> u32 {
+        let round =
+            |value: u32, multiple: u32| value.div_ceil(multiple) * multiple;
+        match self.algorithm {
+            Algorithm::Mean | Algorithm::Gradient | Algorithm::VertGradient => {
+                self.width * self.height
+            },
+            // The image is shrunk to half the size (plus one) before taking
+            // the gradients along both axes.
+            Algorithm::DoubleGradient => {
+                let width = round(self.width, 2) / 2 + 1;
+                let height = round(self.height, 2) / 2 + 1;
+                (width - 1) * height + width * (height - 1)
+            },
+            Algorithm::Blockhash => {
+                round(self.width, 4) * round(self.height, 4)
+            },
+        }
+    }
+
+    /// Build a hasher from this configuration.
+    pub(crate) fn to_hasher(self) -> Hasher {
+        let config = HasherConfig::new()
+            .hash_size(self.width, self.height)
+            .hash_alg(self.algorithm.into());
+
+        if self.dct {
+            config.preproc_dct().to_hasher()
+        } else {
+            config.to_hasher()
+        }
+    }
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::DoubleGradient,
+            width: 8,
+            height: 8,
+            dct: true,
+            trim_borders: false,
+            grayscale: false,
+            equalize: false,
+            exif_orientation: false,
+            pad_to_square: false,
+            preblur: None,
+            frame: 0,
+            max_dimension: None,
+        }
+    }
+}
+
+/// A hashed page.
+#[derive(Clone, Debug)]
+pub struct HashedImage {
+    /// Path of the page, relative to the root of its version.
+    pub path: OsString,
+    /// Position of the page in its version.
+    pub index: usize,
+    /// Size of the page, in bytes.
+    pub size: usize,
+    /// Perceptual hash of the page.
+    pub hash: ImageHash,
+    /// Perceptual hash of the horizontally mirrored page, when requested.
+    pub mirror_hash: Option<ImageHash>,
+    /// Perceptual hash of the page with its colors inverted, when requested.
+    pub inverted_hash: Option<ImageHash>,
+    /// PNG-encoded thumbnail of the page, when requested.
+    ///
+    /// Shared, as the images are cloned around while matching.
+    pub thumbnail: Option<Arc<[u8]>>,
+    /// Digest of the file content, when it has been read.
+    pub digest: Option<u64>,
+    /// Width and height of the page, in pixels, when it has been decoded (or
+    /// when requested, see [`HashOptions::dimensions`]).
+    pub dimensions: Option<(u32, u32)>,
+}
+
+#[derive(Debug)]
+struct Page {
+    path: PathBuf,
+    size: usize,
+}
+
+/// Return a list of page found under the given path.
+///
+/// Only the files with an accepted extension are returned, and subdirectories
+/// are only walked when looking for pages recursively. Symbolic links are
+/// ignored, unless they are followed.
+///
+/// A warning is printed when no page is found, telling an empty directory
+/// from a directory without images.
+fn list_pages(path: &Path, options: &HashOptions) -> Result<Vec<Page>> {
+    let mut pages = Vec::new();
+    // Files left out because of their extension, or their name.
+    let mut ignored = 0;
+    let mut excluded = 0;
+    let mut directories = vec![path.to_owned()];
+    // Directories already walked, so that links cannot make a loop.
+    let mut visited = HashSet::new();
+
+    while let Some(directory) = directories.pop() {
+        if options.follow_symlinks {
+            let canonical = fs::canonicalize(&directory)
+                .wrap_err_with(|| format!("resolve {}", directory.display()))?;
+            if !visited.insert(canonical) {
+                debug!("skipping {}, already walked", directory.display());
+                continue;
+            }
+        }
+
+        let entries = fs::read_dir(&directory).wrap_err_with(|| {
+            format!("list pages in {}", directory.display())
+        })?;
+        for entry in entries {
+            let entry = entry.wrap_err("access directory entry")?;
+            if options.ignore_hidden && is_hidden(&entry.file_name()) {
+                trace!("skipping hidden {}", entry.path().display());
+                continue;
+            }
+            // The metadata of the entry itself doesn't follow the links.
+            let metadata = if options.follow_symlinks {
+                fs::metadata(entry.path())
+            } else {
+                entry.metadata()
+            }
+            .wrap_err_with(|| {
+                format!("read metadata for {}", entry.path().display())
+            })?;
+
+            if metadata.is_file() {
+                let path = entry.path();
+                if !options.has_image_extension(&path) {
+                    ignored += 1;
+                } else if options.is_excluded(&path) {
+                    trace!("excluding {}", path.display());
+                    excluded += 1;
+                } else {
+                    pages.push(Page {
+                        path,
+                        size: metadata.len() as usize,
+                    });
+                }
+            } else if options.recursive && metadata.is_dir() {
+                directories.push(entry.path());
+            }
+        }
+    }
+
+    if excluded > 0 {
+        info!("excluded {} pages by name in {}", excluded, path.display());
+    }
+    if pages.is_empty() {
+        warn_no_pages(path, ignored, excluded);
+    }
+    Ok(pages)
+}
+
+/// Check if the file is hidden, as are the metadata left by the OS (e.g.
+/// `.DS_Store`, or the `._` AppleDouble files from macOS).
+fn is_hidden(filename: &OsStr) -> bool {
+    filename.to_string_lossy().starts_with('.')
+}
+
+/// Warn that no page was found under `path`, where `ignored` files were left
+/// out because of their extension, and `excluded` pages by name.
+fn warn_no_pages(path: &Path, ignored: usize, excluded: usize) {
+    if excluded > 0 {
+        warn!(
+            "no pages found under {}: {} page(s) excluded by name",
+            path.display(),
+            excluded
+        );
+    } else if ignored == 0 {
+        warn!("no pages found under {}: it is empty", path.display());
+    } else {
+        warn!(
+            "no pages found under {}: {} file(s) without an image extension",
+            path.display(),
+            ignored
+        );
+    }
+}
+
+/// Maximum variation of luminance of the borders trimmed from the pages.
+const BORDER_TOLERANCE: u8 = 32;
+
+/// Color of the padding added to make the pages square: a neutral gray.
+const PADDING_COLOR: Rgba<u8> = Rgba([128, 128, 128, 255]);
+
+/// Extensions of the image formats that can be decoded.
+pub const IMAGE_EXTENSIONS: &[&str] =
+    &["bmp", "gif", "ico", "jpeg", "jpg", "png", "webp"];
+
+/// Options controlling how the pages are collected and hashed.
+#[derive(Clone, Debug)]
+pub struct HashOptions {
+    /// Settings of the perceptual hash.
+    pub config: HashConfig,
+    /// Look for pages in subdirectories as well.
+    pub recursive: bool,
+    /// Follow the symbolic links to files and directories, instead of
+    /// ignoring them.
+    pub follow_symlinks: bool,
+    /// Skip the pages that cannot be read or decoded, instead of failing.
+    pub skip_errors: bool,
+    /// Extensions (lowercase, without the dot) of the files to consider.
+    pub extensions: Vec<String>,
+    /// Leave out the pages whose filename matches any of these patterns
+    /// (e.g. the covers, credits or ads that clutter the report).
+    pub exclude: Vec<Glob>,
+    /// Order of the pages, in directories and archives.
+    pub sort_key: SortKey,
+    /// Generate a thumbnail of the pages, fitting in a square of this size.
+    ///
+    /// Pages must be decoded for that, even when their hash is cached.
+    pub thumbnail_size: Option<u32>,
+    /// Look the pages up in the cache by content rather than by path, so that
+    /// renamed pages are still found.
+    ///
+    /// Pages must be read for that, even when their hash is cached.
+    pub cache_by_content: bool,
+    /// Resolution used to rasterize the pages of PDF documents, in dots per
+    /// inch.
+    pub pdf_dpi: u32,
+    /// Also hash the horizontally mirrored pages, to match the pages that were
+    /// flipped.
+    ///
+    /// Pages must be decoded for that, even when their hash is cached.
+    pub mirror: bool,
+    /// Also hash the pages with their colors inverted, to match the pages
+    /// that were inverted (e.g. white text on black).
+    ///
+    /// Pages must be decoded for that, even when their hash is cached.
+    pub invert: bool,
+    /// Only hash one page out of `sample`, for quick previews.
+    ///
+    /// The sampled pages keep their index among all the pages.
+    pub sample: usize,
+    /// Pick the sampled pages pseudo-randomly from this seed, rather than
+    /// every `sample` pages.
+    ///
+    /// The same seed always picks the same positions, in both versions.
+    pub seed: Option<u64>,
+    /// Only hash the pages in this range, for spot checks.
+    ///
+    /// Like when sampling, these pages keep their index among all the pages.
+    pub range: Option<PageRange>,
+    /// Skip the pages narrower than this, in pixels.
+    ///
+    /// Pages must be decoded for that, even when their hash is cached.
+    pub min_width: u32,
+    /// Skip the pages shorter than this, in pixels.
+    ///
+    /// Pages must be decoded for that, even when their hash is cached.
+    pub min_height: u32,
+    /// Map the page files in memory instead of streaming them through a
+    /// buffered reader.
+    ///
+    /// The files must not be truncated while they are hashed: reading a
+    /// mapping past the end of its file kills the process (with `SIGBUS`).
+    pub mmap: bool,
+    /// Fail when a version holds more pages than this, before hashing them
+    /// (e.g. when pointed at a whole library by mistake).
+    ///
+    /// This applies to every source of pages: directories, archives, PDF
+    /// documents, listings, and lists of pages. The PDF documents are
+    /// rasterized first.
+    pub max_pages: Option<usize>,
+    /// Skip the hidden files and directories, those whose name starts with a
+    /// dot.
+    pub ignore_hidden: bool,
+    /// Read the dimensions of the pages whose hash is cached, from the header
+    /// of their file (the decoded pages always have them).
+    pub dimensions: bool,
+}
+
+impl HashOptions {
+    /// Check if the given path looks like an image, according to its
+    /// extension.
+    fn has_image_extension(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| {
+            let extension = extension.to_string_lossy().to_lowercase();
+            self.extensions.contains(&extension)
+        })
+    }
+
+    /// Check if the page at the given path is left out because of its
+    /// filename (see [`HashOptions::exclude`]).
+    fn is_excluded(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            self.exclude.iter().any(|glob| glob.matches(&name))
+        })
+    }
+
+    /// Check if the page at the given index is hashed, when sampling or only
+    /// hashing a range.
+    fn is_sampled(&self, index: usize) -> bool {
+        let position = match self.seed {
+            Some(seed) => scramble(seed, index as u64),
+            None => index as u64,
+        };
+        position % self.sample.max(1) as u64 == 0
+            && self.range.map_or(true, |range| range.contains(index))
+    }
+
+    /// Return how many pages out of `count` are hashed, when sampling or only
+    /// hashing a range.
+    fn sampled_count(&self, count: usize) -> usize {
+        if self.range.is_some() || self.seed.is_some() {
+            (0..count).filter(|&index| self.is_sampled(index)).count()
+        } else {
+            count.div_ceil(self.sample.max(1))
+        }
+    }
+
+    /// Fail when the `source` of the pages holds more than the maximum number
+    /// of pages (see [`max_pages`](Self::max_pages)).
+    fn check_page_count(
+        &self,
+        count: usize,
+        source: impl fmt::Display,
+    ) -> Result<()> {
+        if let Some(max) = self.max_pages {
+            ensure!(
+                count <= max,
+                "found {} pages in {}, more than the maximum of {}",
+                count,
+                source,
+                max
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Mix the seed with the given value, into a pseudo-random number (with the
+/// SplitMix64 finalizer).
+fn scramble(seed: u64, value: u64) -> u64 {
+    let mut z = seed.wrapping_add(
+        value.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+    );
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        Self {
+            config: HashConfig::default(),
+            recursive: false,
+            follow_symlinks: false,
+            skip_errors: false,
+            extensions: IMAGE_EXTENSIONS
+                .iter()
+                .map(|&extension| extension.to_owned())
+                .collect(),
+            exclude: Vec::new(),
+            sort_key: SortKey::Natural,
+            thumbnail_size: None,
+            cache_by_content: false,
+            pdf_dpi: 150,
+            mirror: false,
+            invert: false,
+            sample: 1,
+            seed: None,
+            range: None,
+            min_width: 0,
+            min_height: 0,
+            mmap: false,
+            max_pages: None,
+            ignore_hidden: true,
+            dimensions: false,
+        }
+    }
+}
+
+/// A page that could not be hashed.
+#[derive(Clone, Debug)]
+pub struct SkippedPage {
+    /// Path of the page.
+    pub path: PathBuf,
+    /// Why the page was skipped.
+    pub reason: String,
+    /// Whether the page was skipped for being smaller than the minimum size,
+    /// rather than because of an error.
+    pub too_small: bool,
+}
+
+/// Error of a page smaller than the minimum size.
+#[derive(Debug)]
+struct TooSmall {
+    width: u32,
+    height: u32,
+}
+
+impl fmt::Display for TooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} pixels, below the minimum size",
+            self.width, self.height
+        )
+    }
+}
+
+impl std::error::Error for TooSmall {}
+
+/// Time spent reading, decoding and hashing a page (or looking it up in the
+/// cache).
+#[derive(Clone, Debug)]
+pub struct PageTiming {
+    /// Path of the page.
+    pub path: PathBuf,
+    /// Time spent on the page.
+    pub duration: Duration,
+}
+
+/// Pages hashed from one version.
+#[derive(Clone, Debug, Default)]
+pub struct HashedPages {
+    /// Hashed pages, in order.
+    pub images: Vec<HashedImage>,
+    /// Pages that could not be hashed, when errors are skipped.
+    pub skipped: Vec<SkippedPage>,
+    /// Time spent on each page (hashed or skipped), in order.
+    pub timings: Vec<PageTiming>,
+}
+
+impl HashedPages {
+    fn from_outcomes(
+        outcomes: Vec<(Result<HashedImage, SkippedPage>, PageTiming)>,
+    ) -> Self {
+        let mut pages = Self::default();
+        for (outcome, timing) in outcomes {
+            pages.timings.push(timing);
+            match outcome {
+                Ok(image) => pages.images.push(image),
+                Err(skipped) => pages.skipped.push(skipped),
+            }
+        }
+        pages
+    }
+}
+
+/// Hash every image under the given path.
+///
+/// The path can either be a directory, a ZIP archive (e.g. a CBZ file), a
+/// PDF document (with the `pdf` feature) or the HTTP(S) URL of a listing of
+/// pages (with the `http` feature).
+///
+/// Pages are identified by their path relative to `path`, and are ordered
+/// over the whole tree when looking into subdirectories. Numbers in the names
+/// are compared by value, so `page2` comes before `page10`.
+///
+/// Pages found in the cache are not hashed again.
+pub fn hash_images(
+    path: impl Into<PathBuf>,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+) -> Result<HashedPages> {
+    let path = path.into();
+    let message = format!("Hashing pages from {}…", path.display());
+    let start = Instant::now();
+
+    let pages = if http::is_url(&path) {
+        hash_remote(&path, options, cache, progress, message)
+    } else if archive::is_archive(&path)? {
+        hash_archive(&path, options, cache, progress, message)
+    } else if pdf::is_pdf(&path)? {
+        hash_pdf(&path, options, cache, progress, message)
+    } else {
+        hash_directory(&path, options, cache, progress, message)
+    }?;
+    info!(
+        "hashed {} pages from {} in {:.2?}",
+        pages.images.len(),
+        path.display(),
+        start.elapsed()
+    );
+
+    Ok(pages)
+}
+
+/// List the pages under the given path, without hashing them.
+///
+/// Pages are returned in the order they would be hashed in, identified by
+/// their path relative to `path`: the page at position `i` gets index `i`.
+pub fn list_images(
+    path: impl Into<PathBuf>,
+    options: &HashOptions,
+) -> Result<Vec<PathBuf>> {
+    let path = path.into();
+
+    let names = if http::is_url(&path) {
+        remote_pages(&path, options)?
+            .into_iter()
+            .map(|page| page.name)
+            .collect()
+    } else if archive::is_archive(&path)? {
+        archive_entries(&path, options)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect()
+    } else if pdf::is_pdf(&path)? {
+        pdf_entries(&path, options)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect()
+    } else {
+        directory_pages(&path, options)?
+            .into_iter()
+            .map(|page| {
+                page.path
+                    .strip_prefix(&path)
+                    .expect("page outside of the root directory")
+                    .to_owned()
+            })
+            .collect()
+    };
+
+    Ok(names)
+}
+
+/// Hash every image in the directory at the given path.
+fn hash_directory(
+    path: &Path,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    let pages = directory_pages(path, options)?;
+    hash_pages(path, pages, options, cache, progress, message)
+}
+
+/// List the pages of the directory at the given path, in order.
+fn directory_pages(path: &Path, options: &HashOptions) -> Result<Vec<Page>> {
+    let mut pages = list_pages(path, options)?;
+    options.check_page_count(pages.len(), path.display())?;
+    pages.sort_by(|a, b| options.sort_key.compare(&a.path, &b.path));
+    debug!("found {} pages in {}", pages.len(), path.display());
+    Ok(pages)
+}
+
+/// Hash the images at the given paths, in order.
+///
+/// The pages are neither filtered by extension nor sorted: each page is
+/// identified by its path as given, and positioned according to its order in
+/// `paths`.
+///
+/// Pages found in the cache are not hashed again.
+pub fn hash_page_list(
+    paths: Vec<PathBuf>,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+) -> Result<HashedPages> {
+    let start = Instant::now();
+    let count = paths.len();
+    options.check_page_count(count, "the list of pages")?;
+    let pages = paths
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path).wrap_err_with(|| {
+                format!("read metadata for {}", path.display())
+            })?;
+            Ok(Page {
+                path,
+                size: metadata.len() as usize,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let message = format!("Hashing {} listed pages…", count);
+    let pages =
+        hash_pages(Path::new(""), pages, options, cache, progress, message)?;
+    info!(
+        "hashed {} listed pages in {:.2?}",
+        pages.images.len(),
+        start.elapsed()
+    );
+
+    Ok(pages)
+}
+
+/// Hash the pages of the directory, archive or PDF document at the given path
+/// that are listed in the manifest, in its order.
+///
+/// Unlike [`hash_images`], the pages are neither looked for nor sorted: each
+/// name of `manifest` is a path relative to `path`, and the page listed at
+/// position `i` gets index `i`.
+///
+/// Pages found in the cache are not hashed again.
+pub fn hash_manifest(
+    path: impl Into<PathBuf>,
+    manifest: &[PathBuf],
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+) -> Result<HashedPages> {
+    let path = path.into();
+    let message = format!("Hashing pages from {}…", path.display());
+    let start = Instant::now();
+    options.check_page_count(manifest.len(), path.display())?;
+    for name in manifest {
+        ensure!(
+            name.is_relative(),
+            "listed page {} is not relative to {}",
+            name.display(),
+            path.display()
+        );
+    }
+
+    let pages = if archive::is_archive(&path)? || pdf::is_pdf(&path)? {
+        let entries = if archive::is_archive(&path)? {
+            archive::read_entries(&path).wrap_err_with(|| {
+                format!("extract pages from {}", path.display())
+            })?
+        } else {
+            pdf_entries(&path, options)?
+        };
+        let mut entries = entries
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect::<HashMap<_, _>>();
+        let entries = manifest
+            .iter()
+            .map(|name| {
+                entries.remove(name).ok_or_else(|| {
+                    eyre!(
+                        "listed page {} not found in {}",
+                        name.display(),
+                        path.display()
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        hash_entries(&path, entries, options, cache, progress, message)
+    } else {
+        let pages = manifest
+            .iter()
+            .map(|name| {
+                let page = path.join(name);
+                let metadata = fs::metadata(&page).wrap_err_with(|| {
+                    format!("read metadata for {}", page.display())
+                })?;
+                Ok(Page {
+                    path: page,
+                    size: metadata.len() as usize,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        hash_pages(&path, pages, options, cache, progress, message)
+    }?;
+    info!(
+        "hashed {} listed pages from {} in {:.2?}",
+        pages.images.len(),
+        path.display(),
+        start.elapsed()
+    );
+
+    Ok(pages)
+}
+
+/// Hash the given in-memory pages (name and content), in order.
+///
+/// Unlike [`hash_images`], nothing is read from the filesystem (not even the
+/// cache) and the pages are hashed on the current thread, so that it can run
+/// where neither are available (e.g. in WebAssembly). The pages are neither
+/// filtered by extension nor sorted.
+pub fn hash_in_memory(
+    pages: Vec<(String, Vec<u8>)>,
+    options: &HashOptions,
+) -> Result<HashedPages> {
+    let outcomes = pages
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| options.is_sampled(*index))
+        .map(|(index, (name, contents))| {
+            let start = Instant::now();
+            let outcome = hash_bytes(&name, index, contents, options);
+            let timing = PageTiming {
+                path: name.into(),
+                duration: start.elapsed(),
+            };
+
+            skip_error(outcome, &timing.path, options.skip_errors)
+                .map(|outcome| (outcome, timing))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(HashedPages::from_outcomes(outcomes))
+}
+
+/// Decode and hash the content of a page, identified by `filename` and
+/// positioned at `index`.
+pub fn hash_bytes(
+    filename: impl Into<OsString>,
+    index: usize,
+    contents: Vec<u8>,
+    options: &HashOptions,
+) -> Result<HashedImage> {
+    let filename = filename.into();
+    let size = contents.len();
+    let contents = Contents::Read(contents);
+    let digest = contents.digest();
+    let hash = hash_image(&filename, &contents, options)?;
+
+    Ok(HashedImage {
+        path: filename,
+        index,
+        size,
+        hash: hash.hash,
+        mirror_hash: hash.mirror_hash,
+        inverted_hash: hash.inverted_hash,
+        thumbnail: hash.thumbnail,
+        digest: Some(digest),
+        dimensions: hash.dimensions,
+    })
+}
+
+/// Hash the given pages, located under `root`.
+fn hash_pages(
+    root: &Path,
+    pages: Vec<Page>,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    progress.start(Task::Hashing, message, options.sampled_count(pages.len()));
+
+    let outcomes = pages
+        .into_par_iter()
+        .enumerate()
+        .filter(|(index, _)| options.is_sampled(*index))
+        .map(|(index, page)| {
+            let start = Instant::now();
+            let outcome = hash_page(root, index, &page, options, cache);
+            let timing = PageTiming {
+                path: page.path,
+                duration: start.elapsed(),
+            };
+            progress.inc();
+
+            skip_error(outcome, &timing.path, options.skip_errors)
+                .map(|outcome| (outcome, timing))
+        })
+        .collect::<Result<Vec<_>>>();
+    progress.finish();
+
+    outcomes.map(HashedPages::from_outcomes)
+}
+
+/// Hash every image stored in the archive at the given path.
+///
+/// Entries are ordered by name, like the pages of a directory. They are all
+/// extracted first, then hashed in parallel: the archive must fit in memory
+/// once uncompressed.
+fn hash_archive(
+    path: &Path,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    let entries = archive_entries(path, options)?;
+    hash_entries(path, entries, options, cache, progress, message)
+}
+
+/// Extract the pages of the archive at the given path, in order.
+fn archive_entries(
+    path: &Path,
+    options: &HashOptions,
+) -> Result<Vec<archive::Entry>> {
+    let mut entries = archive::read_entries(path)
+        .wrap_err_with(|| format!("extract pages from {}", path.display()))?;
+    let count = entries.len();
+    entries.retain(|entry| options.has_image_extension(&entry.name));
+    let ignored = count - entries.len();
+    entries.retain(|entry| !options.is_excluded(&entry.name));
+    let excluded = count - ignored - entries.len();
+    if excluded > 0 {
+        info!("excluded {} pages by name in {}", excluded, path.display());
+    }
+    if entries.is_empty() {
+        warn_no_pages(path, ignored, excluded);
+    }
+    options.check_page_count(entries.len(), path.display())?;
+    entries.sort_by(|a, b| options.sort_key.compare(&a.name, &b.name));
+    debug!("found {} pages in {}", entries.len(), path.display());
+    Ok(entries)
+}
+
+/// Hash every page listed at the given URL, once downloaded.
+///
+/// Pages are in the order of the listing. They are all downloaded first, in
+/// parallel, then hashed: like with archives, they must fit in memory.
+fn hash_remote(
+    path: &Path,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    let pages = remote_pages(path, options)?;
+    let start = Instant::now();
+    let count = pages.len();
+    let entries = http::fetch_pages(pages)
+        .wrap_err_with(|| format!("download pages from {}", path.display()))?;
+    debug!(
+        "downloaded {} pages from {} in {:.2?}",
+        count,
+        path.display(),
+        start.elapsed()
+    );
+    hash_entries(path, entries, options, cache, progress, message)
+}
+
+/// List the pages at the given URL, in order.
+fn remote_pages(path: &Path, options: &HashOptions) -> Result<Vec<http::Page>> {
+    let mut pages = http::list_pages(&path.to_string_lossy())
+        .wrap_err_with(|| format!("list pages from {}", path.display()))?;
+    let count = pages.len();
+    pages.retain(|page| !options.is_excluded(&page.name));
+    let excluded = count - pages.len();
+    if excluded > 0 {
+        info!("excluded {} pages by name in {}", excluded, path.display());
+    }
+    if pages.is_empty() {
+        warn_no_pages(path, 0, excluded);
+    }
+    options.check_page_count(pages.len(), path.display())?;
+    debug!("found {} pages in {}", pages.len(), path.display());
+    Ok(pages)
+}
+
+/// Hash every page of the PDF document at the given path, once rasterized.
+fn hash_pdf(
+    path: &Path,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    let entries = pdf_entries(path, options)?;
+    hash_entries(path, entries, options, cache, progress, message)
+}
+
+/// Rasterize the pages of the PDF document at the given path, in order.
+fn pdf_entries(
+    path: &Path,
+    options: &HashOptions,
+) -> Result<Vec<archive::Entry>> {
+    let start = Instant::now();
+    let entries = pdf::rasterize(path, options.pdf_dpi)
+        .wrap_err_with(|| format!("rasterize {}", path.display()))?;
+    debug!(
+        "rasterized {} pages from {} in {:.2?}",
+        entries.len(),
+        path.display(),
+        start.elapsed()
+    );
+    options.check_page_count(entries.len(), path.display())?;
+    Ok(entries)
+}
+
+/// Hash the given entries, extracted from the file at `path`.
+fn hash_entries(
+    path: &Path,
+    entries: Vec<archive::Entry>,
+    options: &HashOptions,
+    cache: &Cache,
+    progress: &ProgressBar,
+    message: String,
+) -> Result<HashedPages> {
+    progress.start(
+        Task::Hashing,
+        message,
+        options.sampled_count(entries.len()),
+    );
+
+    let outcomes = entries
+        .into_par_iter()
+        .enumerate()
+        .filter(|(index, _)| options.is_sampled(*index))
+        .map(|(index, archive::Entry { name, contents })| {
+            let start = Instant::now();
+            let entry_path = path.join(&name);
+            let size = contents.len();
+            let hash = lookup_or_hash(
+                name.as_os_str(),
+                &entry_path,
+                index,
+                size,
+                || Ok(Contents::Read(contents)),
+                options,
+                cache,
+            );
+            progress.inc();
+
+            let outcome = hash.map(|page| HashedImage {
+                path: name.into_os_string(),
+                index,
+                size,
+                hash: page.hash,
+                mirror_hash: page.mirror_hash,
+                inverted_hash: page.inverted_hash,
+                thumbnail: page.thumbnail,
+                digest: page.digest,
+                dimensions: page.dimensions,
+            });
+            let timing = PageTiming {
+                path: entry_path,
+                duration: start.elapsed(),
+            };
+
+            skip_error(outcome, &timing.path, options.skip_errors)
+                .map(|outcome| (outcome, timing))
+        })
+        .collect::<Result<Vec<_>>>();
+    progress.finish();
+
+    outcomes.map(HashedPages::from_outcomes)
+}
+
+/// Load and hash the given page, located under `root`.
+fn hash_page(
+    root: &Path,
+    index: usize,
+    page: &Page,
+    options: &HashOptions,
+    cache: &Cache,
+) -> Result<HashedImage> {
+    let filename = page
+        .path
+        .strip_prefix(root)
+        .expect("page outside of the root directory")
+        .as_os_str()
+        .to_owned();
+
+    let read = || mmap::read_page(&page.path, page.size, options.mmap);
+    let hash = lookup_or_hash(
+        &filename, &page.path, index, page.size, read, options, cache,
+    )?;
+
+    Ok(HashedImage {
+        path: filename,
+        index,
+        size: page.size,
+        hash: hash.hash,
+        mirror_hash: hash.mirror_hash,
+        inverted_hash: hash.inverted_hash,
+        thumbnail: hash.thumbnail,
+        digest: hash.digest,
+        dimensions: hash.dimensions,
+    })
+}
+
+/// Hash of a page, with its by-products.
+struct PageHash {
+    hash: ImageHash,
+    mirror_hash: Option<ImageHash>,
+    inverted_hash: Option<ImageHash>,
+    thumbnail: Option<Arc<[u8]>>,
+    /// Digest of the content, unless the page was not read.
+    digest: Option<u64>,
+    dimensions: Option<(u32, u32)>,
+}
+
+/// Look the page at `path` up in the cache, or hash it (and record it in the
+/// cache file, when appending to it).
+///
+/// `read` loads the content of the page, and is only called when needed.
+fn lookup_or_hash(
+    filename: &OsStr,
+    path: &Path,
+    index: usize,
+    size: usize,
+    read: impl FnOnce() -> Result<Contents>,
+    options: &HashOptions,
+    cache: &Cache,
+) -> Result<PageHash> {
+    // Thumbnails, mirrored and inverted hashes can only be made from the
+    // decoded page, which also gives its size.
+    let decode = options.thumbnail_size.is_some()
+        || options.mirror
+        || options.invert
+        || options.min_width > 0
+        || options.min_height > 0;
+
+    if !decode && !options.cache_by_content {
+        if let Some(hash) = cache.get(path, size)? {
+            trace!("found {} in cache", path.display());
+            let dimensions = match options.dimensions {
+                true => read_dimensions(read()?.header()),
+                false => None,
+            };
+            return Ok(PageHash {
+                hash,
+                mirror_hash: None,
+                inverted_hash: None,
+                thumbnail: None,
+                digest: None,
+                dimensions,
+            });
+        }
+    }
+
+    let contents = read()?;
+    let digest = contents.digest();
+    if !decode && options.cache_by_content {
+        if let Some(hash) = cache.get_by_digest(digest)? {
+            trace!("found {} in cache, by content", path.display());
+            return Ok(PageHash {
+                hash,
+                mirror_hash: None,
+                inverted_hash: None,
+                thumbnail: None,
+                digest: Some(digest),
+                dimensions: options
+                    .dimensions
+                    .then(|| read_dimensions(contents.header()))
+                    .flatten(),
+            });
+        }
+    }
+
+    let hash = hash_image(filename, &contents, options)?;
+    cache.record(path, index, size, Some(digest), &hash.hash)?;
+    Ok(PageHash {
+        digest: Some(digest),
+        ..hash
+    })
+}
+
+/// Turn the error into a skipped page, if errors are skipped (or the page is
+/// too small).
+fn skip_error(
+    outcome: Result<HashedImage>,
+    path: &Path,
+    skip_errors: bool,
+) -> Result<Result<HashedImage, SkippedPage>> {
+    match outcome {
+        Ok(image) => Ok(Ok(image)),
+        Err(err) if err.downcast_ref::<TooSmall>().is_some() => {
+            Ok(Err(SkippedPage {
+                path: path.to_owned(),
+                reason: err.root_cause().to_string(),
+                too_small: true,
+            }))
+        },
+        Err(err) if skip_errors => Ok(Err(SkippedPage {
+            path: path.to_owned(),
+            reason: format!("{:#}", err),
+            too_small: false,
+        })),
+        Err(err) => Err(err),
+    }
+}
+
+/// Decode the given image content and compute its hash (and mirrored hash,
+/// inverted hash and thumbnail, if requested).
+fn hash_image(
+    filename: &OsStr,
+    contents: &Contents,
+    options: &HashOptions,
+) -> Result<PageHash> {
+    let start = Instant::now();
+
+    let image = match contents {
+        Contents::Streamed(streamed) => {
+            decode_streamed(filename, streamed, &options.config)?
+        },
+        _ => decode_image(filename, contents.header(), &options.config)?,
+    };
+    let (width, height) = image.dimensions();
+    if width < options.min_width || height < options.min_height {
+        return Err(Report::new(TooSmall { width, height }));
+    }
+
+    let hasher = options.config.to_hasher();
+    let hashed = preprocess(&image, &options.config);
+    let hash = hasher.hash_image(hashed.as_ref());
+    let mirror_hash =
+        options.mirror.then(|| hasher.hash_image(&hashed.fliph()));
+    let inverted_hash = options.invert.then(|| {
+        let mut inverted = hashed.as_ref().clone();
+        inverted.invert();
+        hasher.hash_image(&inverted)
+    });
+    let thumbnail = options
+        .thumbnail_size
+        .map(|size| make_thumbnail(&image, size))
+        .transpose()
+        .wrap_err_with(|| {
+            format!("make thumbnail of {}", filename.to_string_lossy())
+        })?;
+    debug!(
+        "hashed {} in {:.2?}",
+        filename.to_string_lossy(),
+        start.elapsed()
+    );
+
+    Ok(PageHash {
+        hash,
+        mirror_hash,
+        inverted_hash,
+        thumbnail,
+        digest: None,
+        dimensions: Some((width, height)),
+    })
+}
+
+/// Read the dimensions of an image from its header, without decoding it.
+fn read_dimensions(contents: &[u8]) -> Option<(u32, u32)> {
+    let webp = formats::simple_webp(contents);
+    let contents = webp.as_deref().unwrap_or(contents);
+    ImageReader::new(Cursor::new(contents))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Decode the given image content, guessing its format.
+///
+/// The image is rotated according to its EXIF orientation, if requested by the
+/// settings.
+pub(crate) fn decode_image(
+    filename: &OsStr,
+    contents: &[u8],
+    config: &HashConfig,
+) -> Result<DynamicImage> {
+    if let Some(format) = formats::unsupported_format(contents) {
+        bail!(
+            "decode {}: {} images are not supported",
+            filename.to_string_lossy(),
+            format
+        );
+    }
+    let original = contents;
+    let webp = formats::simple_webp(contents);
+    let contents = webp.as_deref().unwrap_or(contents);
+    let context = || {
+        format!(
+            "decode {} ({})",
+            filename.to_string_lossy(),
+            describe_content(filename, original)
+        )
+    };
+
+    let reader = ImageReader::new(Cursor::new(contents))
+        .with_guessed_format()
+        .wrap_err_with(|| format!("identify {}", filename.to_string_lossy()))?;
+    let frame = match reader.format() {
+        Some(format) => {
+            decode_frame(Cursor::new(contents), format, config.frame)
+                .wrap_err_with(context)?
+        },
+        None => None,
+    };
+    let image = match frame {
+        Some(image) => image,
+        None => reader.decode().wrap_err_with(context)?,
+    };
+
+    Ok(orient_as_told(filename, image, contents, config))
+}
+
+/// Decode the image streamed from its file, whose format is known from its
+/// header (see [`mmap::read_page`]).
+fn decode_streamed(
+    filename: &OsStr,
+    streamed: &Streamed,
+    config: &HashConfig,
+) -> Result<DynamicImage> {
+    let header = streamed.header();
+    let decode = || -> ImageResult<DynamicImage> {
+        let format = image::guess_format(header)?;
+        let mut reader = streamed.reader()?;
+        if let Some(frame) = decode_frame(&mut reader, format, config.frame)? {
+            return Ok(frame);
+        }
+        reader.rewind()?;
+        ImageReader::with_format(reader, format).decode()
+    };
+    let image = decode().wrap_err_with(|| {
+        // Only read whole to describe it.
+        let contents = streamed.read_all().unwrap_or_default();
+        format!(
+            "decode {} ({})",
+            filename.to_string_lossy(),
+            describe_content(filename, &contents)
+        )
+    })?;
+
+    Ok(orient_as_told(filename, image, header, config))
+}
+
+/// Rotate the image according to the EXIF orientation found in `contents` (or
+/// its header), if requested by the settings.
+fn orient_as_told(
+    filename: &OsStr,
+    image: DynamicImage,
+    contents: &[u8],
+    config: &HashConfig,
+) -> DynamicImage {
+    match formats::exif_orientation(contents) {
+        Some(orientation) if config.exif_orientation => {
+            trace!(
+                "orienting {} (EXIF orientation {})",
+                filename.to_string_lossy(),
+                orientation
+            );
+            orient(image, orientation)
+        },
+        _ => image,
+    }
+}
+
+/// Number of leading bytes shown when an image cannot be decoded.
+const MAGIC_BYTES: usize = 8;
+
+/// Describe an image content that cannot be decoded, to tell a truncated file
+/// from a wrong extension or an unsupported codec: its format guessed from
+/// the content, its dimensions (when the header is readable), its size and
+/// its first bytes.
+fn describe_content(filename: &OsStr, contents: &[u8]) -> String {
+    let name = |format: ImageFormat| format!("{:?}", format).to_uppercase();
+    let guessed = image::guess_format(contents).ok();
+    let mut description = match guessed {
+        Some(format) => format!("{} content", name(format)),
+        None => "unknown format".to_owned(),
+    };
+    // The content doesn't match the extension.
+    if let Ok(expected) = ImageFormat::from_path(filename) {
+        if guessed.is_some_and(|format| format != expected) {
+            write!(description, " named as {}", name(expected))
+                .expect("write to string");
+        }
+    }
+    if let Some((width, height)) = read_dimensions(contents) {
+        write!(description, ", {}x{} pixels", width, height)
+            .expect("write to string");
+    }
+    write!(description, ", {} bytes", contents.len()).expect("write to string");
+    if !contents.is_empty() {
+        let magic = contents
+            .iter()
+            .take(MAGIC_BYTES)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>();
+        write!(description, " starting with {}", magic.join(" "))
+            .expect("write to string");
+    }
+    description
+}
+
+/// Decode the frame `index` of an animated image (or its last frame, when
+/// there are fewer), or return `None` when the image is not animated.
+///
+/// The default image of an APNG is left out when not part of the animation.
+fn decode_frame<'a>(
+    reader: impl Read + 'a,
+    format: ImageFormat,
+    index: u32,
+) -> ImageResult<Option<DynamicImage>> {
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(reader)?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        },
+        _ => return Ok(None),
+    };
+
+    let mut last = None;
+    for frame in frames.take(index as usize + 1) {
+        last = Some(frame?);
+    }
+    Ok(last.map(|frame| DynamicImage::ImageRgba8(frame.into_buffer())))
+}
+
+/// Apply an EXIF orientation (from 1 to 8) to the image, to get it upright.
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        // Transpose.
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        // Transverse.
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Transform the image as requested by the settings, before hashing it.
+pub(crate) fn preprocess<'a>(
+    image: &'a DynamicImage,
+    config: &HashConfig,
+) -> Cow<'a, DynamicImage> {
+    let mut image = Cow::Borrowed(image);
+    if let Some(max) = config.max_dimension {
+        let (width, height) = image.dimensions();
+        if width > max || height > max {
+            image = Cow::Owned(image.thumbnail(max, max));
+        }
+    }
+    if config.trim_borders {
+        image = Cow::Owned(trim_borders(&image));
+    }
+    if config.pad_to_square {
+        image = Cow::Owned(pad_to_square(&image));
+    }
+    if let Some(sigma) = config.preblur {
+        image = Cow::Owned(image.blur(f32::from_bits(sigma)));
+    }
+    if config.equalize {
+        image = Cow::Owned(equalize_luma(&image));
+    } else if config.grayscale {
+        image = Cow::Owned(DynamicImage::ImageLuma8(image.to_luma8()));
+    }
+    image
+}
+
+/// Crop the uniform rows and columns on the edges of the image.
+///
+/// A row (or column) is uniform when its luminance varies by less than
+/// `BORDER_TOLERANCE`, so that the noise of scans doesn't prevent the
+/// trimming. The image is left as is when it is uniform as a whole.
+fn trim_borders(image: &DynamicImage) -> DynamicImage {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    let uniform = |pixels: &mut dyn Iterator<Item = u8>| {
+        let (min, max) = pixels
+            .fold((u8::MAX, u8::MIN), |(min, max), value| {
+                (min.min(value), max.max(value))
+            });
+        max.saturating_sub(min) < BORDER_TOLERANCE
+    };
+    let row = |y| uniform(&mut (0..width).map(|x| luma.get_pixel(x, y)[0]));
+    let column = |x| uniform(&mut (0..height).map(|y| luma.get_pixel(x, y)[0]));
+
+    let top = match (0..height).find(|&y| !row(y)) {
+        Some(top) => top,
+        None => return image.clone(),
+    };
+    let bottom = (top..height).rev().find(|&y| !row(y)).unwrap_or(top);
+    let left = (0..width).find(|&x| !column(x)).unwrap_or(0);
+    let right = (left..width).rev().find(|&x| !column(x)).unwrap_or(left);
+
+    trace!(
+        "trimmed borders to {}x{} at ({}, {})",
+        right - left + 1,
+        bottom - top + 1,
+        left,
+        top
+    );
+    image.crop_imm(left, top, right - left + 1, bottom - top + 1)
+}
+
+/// Center the image in a square as large as its longest side.
+fn pad_to_square(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let size = width.max(height);
+    if width == height {
+        return image.clone();
+    }
+
+    let mut canvas = RgbaImage::from_pixel(size, size, PADDING_COLOR);
+    imageops::replace(
+        &mut canvas,
+        &image.to_rgba8(),
+        (size - width) / 2,
+        (size - height) / 2,
+    );
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Convert the image to grayscale, with its histogram equalized.
+///
+/// The tones are spread evenly over the whole range of luminance, whatever
+/// the weights of the color channels (or the curve) used by the conversion.
+fn equalize_luma(image: &DynamicImage) -> DynamicImage {
+    let mut luma = image.to_luma8();
+    let mut histogram = [0_u64; 256];
+    for pixel in luma.pixels() {
+        histogram[usize::from(pixel[0])] += 1;
+    }
+
+    let total = u64::from(luma.width()) * u64::from(luma.height());
+    // The darkest tone maps to black, and the lightest to white.
+    let darkest = histogram.iter().copied().find(|&count| count > 0);
+    let darkest = match darkest.filter(|&darkest| darkest < total) {
+        Some(darkest) => darkest,
+        // Uniform (or empty) images have no tones to spread.
+        None => return DynamicImage::ImageLuma8(luma),
+    };
+    let mut cumulative = 0;
+    let mut mapping = [0_u8; 256];
+    for (value, count) in histogram.iter().enumerate() {
+        cumulative += count;
+        mapping[value] = (cumulative.saturating_sub(darkest) * 255
+            / (total - darkest)) as u8;
+    }
+    for pixel in luma.pixels_mut() {
+        pixel[0] = mapping[usize::from(pixel[0])];
+    }
+
+    DynamicImage::ImageLuma8(luma)
+}
+
+/// Downscale the image to fit in a square of `size` pixels, as a PNG.
+fn make_thumbnail(image: &DynamicImage, size: u32) -> Result<Arc<[u8]>> {
+    let mut png = Vec::new();
+    image
+        .thumbnail(size, size)
+        .write_to(&mut png, ImageOutputFormat::Png)?;
+
+    Ok(png.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest;
+    use image::{Rgb, RgbImage};
+
+    /// Read a fixture of `tests/fixtures`.
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        fs::read(&path).expect("read fixture")
+    }
+
+    /// Hash a fixture of `tests/fixtures`, with the given settings.
+    fn hash_fixture(name: &str, options: &HashOptions) -> Result<HashedImage> {
+        let pages = vec![(name.to_owned(), fixture(name))];
+        let mut pages = hash_in_memory(pages, options)?;
+        assert_eq!(pages.images.len(), 1);
+        Ok(pages.images.remove(0))
+    }
+
+    #[test]
+    fn webp_lossy() {
+        let image = hash_fixture("lossy.webp", &HashOptions::default())
+            .expect("hash lossy WebP");
+        assert_eq!(image.dimensions, Some((16, 16)));
+    }
+
+    #[test]
+    fn webp_alpha() {
+        // Same bitstream as the lossy fixture, with an alpha channel.
+        let options = HashOptions::default();
+        let image = hash_fixture("alpha.webp", &options).expect("hash WebP");
+        let lossy = hash_fixture("lossy.webp", &options).expect("hash WebP");
+        assert_eq!(image.hash, lossy.hash);
+    }
+
+    #[test]
+    fn webp_animated() {
+        let image = hash_fixture("anim.webp", &HashOptions::default())
+            .expect("hash animated WebP");
+        assert_eq!(image.dimensions, Some((16, 16)));
+    }
+
+    #[test]
+    fn webp_lossless() {
+        let error = hash_fixture("lossless.webp", &HashOptions::default())
+            .expect_err("lossless WebP is not supported");
+        assert!(format!("{:#}", error)
+            .contains("lossless WebP images are not supported"));
+    }
+
+    /// Hash the given image, encoded as a PNG.
+    fn hash_image(image: &DynamicImage, config: HashConfig) -> ImageHash {
+        let mut png = Vec::new();
+        image
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .expect("encode PNG");
+        let options = HashOptions {
+            config,
+            ..HashOptions::default()
+        };
+        let mut pages =
+            hash_in_memory(vec![("page.png".to_owned(), png)], &options)
+                .expect("hash page");
+        pages.images.remove(0).hash
+    }
+
+    /// Draw a color page: stripes of colors of various luminance over a
+    /// gradient.
+    fn color_page() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let shade = (x * 2 + y) as u8;
+            match (x / 8 + y / 16) % 4 {
+                0 => image::Rgb([255, shade, 0]),
+                1 => image::Rgb([shade, 40, 255]),
+                2 => image::Rgb([0, 255 - shade, shade]),
+                _ => image::Rgb([shade, shade, shade]),
+            }
+        }))
+    }
+
+    /// Convert a page to grayscale like a reprint would: with lighter tones.
+    fn reprint(page: &DynamicImage) -> DynamicImage {
+        let mut gray = page.to_luma8();
+        for pixel in gray.pixels_mut() {
+            let dark = 255 - u32::from(pixel[0]);
+            pixel[0] = (255 - dark * dark / 255) as u8;
+        }
+        DynamicImage::ImageLuma8(gray)
+    }
+
+    #[test]
+    fn grayscale() {
+        let page = color_page();
+        let gray = DynamicImage::ImageLuma8(page.to_luma8());
+        let config = HashConfig::default().grayscale(true);
+        assert_eq!(hash_image(&page, config), hash_image(&gray, config));
+
+        // Unlike the other algorithms, the block hash works on the channels
+        // of the color pages rather than on their luminance: there, the
+        // conversion brings the page and its grayscale version closer.
+        let distance =
+            |config| hash_image(&page, config).dist(&hash_image(&gray, config));
+        let config =
+            HashConfig::new(Algorithm::Blockhash, 8, 8).expect("config");
+        let plain = distance(config);
+        let converted = distance(config.grayscale(true));
+        assert!(converted < plain, "{} >= {}", converted, plain);
+    }
+
+    #[test]
+    fn equalize() {
+        let page = color_page();
+        let gray = reprint(&page);
+        let distance =
+            |config| hash_image(&page, config).dist(&hash_image(&gray, config));
+        // The mean hash depends on the tones, unlike the gradients.
+        let config = HashConfig::new(Algorithm::Mean, 8, 8).expect("config");
+        let plain = distance(config.grayscale(true));
+        let equalized = distance(config.equalize(true));
+        assert!(equalized < plain, "{} >= {}", equalized, plain);
+    }
+
+    /// Hash a fixture of `tests/fixtures`, rotated as told by its EXIF
+    /// orientation or not.
+    fn hash_oriented(name: &str, enabled: bool) -> HashedImage {
+        let options = HashOptions {
+            config: HashConfig::default().exif_orientation(enabled),
+            ..HashOptions::default()
+        };
+        hash_fixture(name, &options).expect("hash page")
+    }
+
+    #[test]
+    fn exif_orientation() {
+        // The pixels of the rotated fixtures are stored sideways, with an
+        // orientation telling to rotate them upright.
+        for extension in ["jpg", "png"] {
+            let upright =
+                hash_oriented(&format!("upright.{}", extension), true);
+            let rotated =
+                hash_oriented(&format!("rotated.{}", extension), true);
+            assert_eq!(rotated.hash, upright.hash);
+            assert_eq!(rotated.dimensions, upright.dimensions);
+
+            let rotated =
+                hash_oriented(&format!("rotated.{}", extension), false);
+            assert_ne!(rotated.hash, upright.hash);
+        }
+    }
+
+    /// Draw a page of the given width, with the same drawing of 64x96 pixels
+    /// centered between gray margins.
+    fn framed_page(width: u32) -> DynamicImage {
+        let margin = (width - 64) / 2;
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, 96, |x, y| match x
+            .checked_sub(margin)
+            .filter(|&x| x < 64)
+        {
+            Some(x) if (x / 8 + y / 12) % 2 == 0 => Rgba([20, 20, 20, 255]),
+            Some(x) => Rgba([(x * 4) as u8, 240, 240, 255]),
+            None => PADDING_COLOR,
+        }))
+    }
+
+    #[test]
+    fn pad_to_square_sizes() {
+        for (width, height) in [(64, 96), (96, 64), (80, 80), (1, 7)] {
+            let image = DynamicImage::new_rgb8(width, height);
+            let size = width.max(height);
+            assert_eq!(pad_to_square(&image).dimensions(), (size, size));
+        }
+    }
+
+    #[test]
+    fn pad_to_square_aspect_ratio() {
+        // The same page, with margins that change its aspect ratio.
+        let narrow = framed_page(64);
+        let wide = framed_page(80);
+        let config = HashConfig::default();
+        assert_ne!(hash_image(&narrow, config), hash_image(&wide, config));
+
+        let config = config.pad_to_square(true);
+        assert_eq!(hash_image(&narrow, config), hash_image(&wide, config));
+    }
+
+    /// Hash the given frame of the animated GIF fixture.
+    fn hash_gif_frame(frame: u32) -> ImageHash {
+        let options = HashOptions {
+            config: HashConfig::default().frame(frame),
+            ..HashOptions::default()
+        };
+        hash_fixture("anim.gif", &options).expect("hash GIF").hash
+    }
+
+    #[test]
+    fn gif_frame() {
+        // Every frame of the fixture differs.
+        let frames = (0..3).map(hash_gif_frame).collect::<Vec<_>>();
+        assert_ne!(frames[0], frames[1]);
+        assert_ne!(frames[1], frames[2]);
+        assert_ne!(frames[0], frames[2]);
+        assert_eq!(hash_gif_frame(1), frames[1]);
+    }
+
+    #[test]
+    fn gif_frame_past_the_end() {
+        // The fixture has 3 frames: the last one is used instead.
+        assert_eq!(hash_gif_frame(3), hash_gif_frame(2));
+        assert_eq!(hash_gif_frame(100), hash_gif_frame(2));
+    }
+
+    #[test]
+    fn avif() {
+        let error = hash_fixture("page.avif", &HashOptions::default())
+            .expect_err("AVIF is not supported");
+        assert!(
+            format!("{:#}", error).contains("AVIF images are not supported")
+        );
+    }
+
+    #[test]
+    fn streamed_page() {
+        // Noise, so that the file is much larger than the header.
+        let mut state = 1_u32;
+        let image = RgbImage::from_fn(512, 512, |_, _| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let [r, g, b, _] = state.to_be_bytes();
+            Rgb([r, g, b])
+        });
+        let mut contents = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut contents, ImageOutputFormat::Png)
+            .expect("encode page");
+        assert!(contents.len() > 2 * mmap::HEADER_SIZE);
+        let path = std::env::temp_dir()
+            .join(format!("img-match-streamed-{}.png", std::process::id()));
+        fs::write(&path, &contents).expect("write page");
+
+        let streamed = mmap::read_page(&path, contents.len(), false);
+        fs::remove_file(&path).expect("remove page");
+        let streamed = streamed.expect("read page");
+        // Only the header is held in memory.
+        assert!(streamed.bytes().is_none());
+        assert_eq!(streamed.header().len(), mmap::HEADER_SIZE);
+        assert_eq!(streamed.digest(), digest::fnv1a(&contents));
+
+        let options = HashOptions::default();
+        let name = OsStr::new("page.png");
+        let streamed =
+            super::hash_image(name, &streamed, &options).expect("hash page");
+        let read = super::hash_image(name, &Contents::Read(contents), &options)
+            .expect("hash page");
+        assert_eq!(streamed.hash, read.hash);
+        assert_eq!(streamed.dimensions, Some((512, 512)));
+    }
+
+    #[test]
+    fn page_range() {
+        let range = |s: &str| s.parse::<PageRange>();
+        let pages = |s: &str| {
+            let range = range(s).expect("parse page range");
+            (0..6)
+                .filter(|&index| range.contains(index))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pages("2:4"), [1, 2, 3]);
+        assert_eq!(pages("3:3"), [2]);
+        assert_eq!(pages(":2"), [0, 1]);
+        assert_eq!(pages("5:"), [4, 5]);
+        assert_eq!(pages(":"), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(pages("9:12"), []);
+
+        for s in ["2:4", "1:", "5:"] {
+            assert_eq!(range(s).expect("parse page range").to_string(), s);
+        }
+        assert_eq!(range(":7").expect("parse page range").to_string(), "1:7");
+
+        for s in ["", "4", "0:3", "2:0", "5:2", "a:3", "1:b", "-1:3", "1:2:3"] {
+            assert!(range(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn glob() {
+        let matches = |pattern: &str, name: &str| {
+            pattern.parse::<Glob>().expect("parse glob").matches(name)
+        };
+        assert!(matches("*", ""));
+        assert!(matches("*", "cover.png"));
+        assert!(matches("*.png", ".png"));
+        assert!(matches("*.PNG", "Page1.png"));
+        assert!(!matches("*.png", "page1.png.bak"));
+        assert!(matches("cover*", "cover"));
+        assert!(matches("a*b*c", "axbybzc"));
+        assert!(!matches("a*b*c", "axbybzcd"));
+        assert!(matches("**x", "x"));
+
+        assert!(matches("p?.png", "p1.png"));
+        assert!(matches("p?.png", "pé.png"));
+        assert!(!matches("p?.png", "p.png"));
+        assert!(!matches("p?.png", "p10.png"));
+        assert!(matches("?*", "a"));
+        assert!(!matches("?*", ""));
+
+        // The other characters only match themselves, on the whole name.
+        assert!(matches("[1].png", "[1].png"));
+        assert!(!matches("[1].png", "1.png"));
+        assert!(!matches("cover", "cover.png"));
+        assert!(!matches("cover", "my cover"));
+        assert!(!matches("p.png", "pxpng"));
+
+        assert!("".parse::<Glob>().is_err());
+        assert_eq!(
+            "*.PNG".parse::<Glob>().expect("parse").to_string(),
+            "*.png"
+        );
+    }
+}
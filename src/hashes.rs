@@ -0,0 +1,133 @@
+//! Export and import of the hashes of the pages, as CSV.
+use crate::report::csv_field;
+use eyre::{bail, ensure, eyre, Context, Result};
+use img_hash::ImageHash;
+use img_match::HashedImage;
+use std::{convert::TryInto, ffi::OsString, io::Write, path::Path};
+
+/// Header of the CSV files.
+const HEADER: &str = "version,filename,index,hash";
+
+/// Write the hashes of the pages of the given versions (name and pages) as
+/// CSV, for external processing.
+///
+/// The hashes are encoded in base64, as by `ImageHash::to_base64`.
+pub fn write_hashes(
+    out: &mut impl Write,
+    versions: &[(&str, &[HashedImage])],
+) -> Result<()> {
+    writeln!(out, "{}", HEADER)?;
+    for (version, images) in versions {
+        for image in images.iter() {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                version,
+                csv_field(Path::new(&image.path)),
+                image.index,
+                image.hash.to_base64()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the hashes written by [`write_hashes`], returning the pages of the old
+/// and new versions.
+///
+/// Every hash must have the given number of bits, i.e. be computed with the
+/// current hash settings.
+pub fn read_hashes(
+    content: &str,
+    bits: u32,
+) -> Result<(Vec<HashedImage>, Vec<HashedImage>)> {
+    let mut lines = content.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim_end() == HEADER => (),
+        _ => bail!("missing header {:?}", HEADER),
+    }
+
+    let mut old = Vec::new();
+    let mut new = Vec::new();
+    for (number, line) in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let (version, image) = parse_line(line, bits)
+            .wrap_err_with(|| format!("invalid line {}", number + 1))?;
+        match version.as_str() {
+            "old" => old.push(image),
+            "new" => new.push(image),
+            _ => bail!("invalid version {:?} on line {}", version, number + 1),
+        }
+    }
+
+    Ok((old, new))
+}
+
+/// Parse a line of hashes, returning the version with its page.
+fn parse_line(line: &str, bits: u32) -> Result<(String, HashedImage)> {
+    let fields = split_fields(line)?;
+    let [version, filename, index, hash]: [String; 4] =
+        fields.try_into().map_err(|fields: Vec<_>| {
+            eyre!("expected 4 fields, got {}", fields.len())
+        })?;
+
+    let index = index
+        .parse()
+        .wrap_err_with(|| format!("invalid index {:?}", index))?;
+    let hash = ImageHash::from_base64(&hash)
+        .map_err(|err| eyre!("invalid hash: {:?}", err))?;
+    let found = hash.as_bytes().len() * 8;
+    ensure!(
+        found == bits.div_ceil(8) as usize * 8,
+        "hash of {} bits, but the hash settings give {} bits (use the same \
+         algorithm and size as when dumping the hashes)",
+        found,
+        bits
+    );
+
+    Ok((
+        version,
+        HashedImage {
+            path: OsString::from(filename),
+            index,
+            size: 0,
+            hash,
+            mirror_hash: None,
+            inverted_hash: None,
+            thumbnail: None,
+            digest: None,
+            dimensions: None,
+        },
+    ))
+}
+
+/// Split a CSV line into its fields, unquoting them.
+fn split_fields(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => {
+                        field.push('"');
+                    },
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => bail!("unterminated quoted field"),
+                }
+            }
+        }
+        while let Some(c) = chars.next_if(|&c| c != ',') {
+            field.push(c);
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
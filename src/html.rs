@@ -0,0 +1,106 @@
+//! Rendering of the matching report as a standalone HTML page.
+use crate::report::{confidence, Report};
+use eyre::Result;
+use img_match::HashedImage;
+use std::{io::Write, path::Path};
+
+/// Size of the thumbnails, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 200;
+
+const STYLE: &str = "body { font-family: sans-serif; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 4px; text-align: center; }
+td .path { font-family: monospace; font-size: small; }
+img { max-width: 200px; max-height: 200px; }
+tr.uncertain, tr.path-match, tr.duplicate, tr.ambiguous {
+  background-color: #fec;
+}";
+
+/// Write the report as an HTML page, with the thumbnails of the pages
+/// embedded.
+///
+/// Matched pages are displayed side-by-side, followed by the pages that only
+/// exist in one of the versions.
+pub fn render_html(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>img-match report</title>\n<style>\n{}\n</style>\n</head>\n\
+         <body>",
+        STYLE
+    )?;
+
+    writeln!(out, "<h1>Page mapping</h1>\n<table>")?;
+    writeln!(out, "<tr><th>New</th><th>Old</th><th>Distance</th></tr>")?;
+    for m in report.mapping.iter() {
+        if let Some((image, distance)) = &m.dst {
+            writeln!(
+                out,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{} ({:.0}%)</td>\
+                 </tr>",
+                m.status(),
+                page_cell(report.new, &m.src),
+                page_cell(report.old, image),
+                distance,
+                confidence(m, report.hash_bits) * 100.
+            )?;
+        }
+    }
+    writeln!(out, "</table>")?;
+
+    let new = report
+        .mapping
+        .iter()
+        .filter(|m| m.dst.is_none())
+        .map(|m| &m.src)
+        .collect::<Vec<_>>();
+    if !new.is_empty() {
+        writeln!(out, "<h1>New pages</h1>\n<table>\n<tr>")?;
+        for image in new {
+            writeln!(out, "<td>{}</td>", page_cell(report.new, image))?;
+        }
+        writeln!(out, "</tr>\n</table>")?;
+    }
+
+    if !report.missing.is_empty() {
+        writeln!(out, "<h1>Missing pages</h1>\n<table>\n<tr>")?;
+        for image in report.missing {
+            writeln!(out, "<td>{}</td>", page_cell(report.old, image))?;
+        }
+        writeln!(out, "</tr>\n</table>")?;
+    }
+
+    writeln!(out, "</body>\n</html>")?;
+    Ok(())
+}
+
+/// Format the thumbnail and path of a page, found under `root`.
+fn page_cell(root: &Path, image: &HashedImage) -> String {
+    let path = escape(&root.join(&image.path).to_string_lossy());
+    match &image.thumbnail {
+        Some(png) => format!(
+            "<img src=\"data:image/png;base64,{}\" alt=\"{}\"><br>\
+             <span class=\"path\">{}</span>",
+            base64::encode(png),
+            path,
+            path
+        ),
+        None => format!("<span class=\"path\">{}</span>", path),
+    }
+}
+
+/// Escape the special HTML characters.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,150 @@
+//! Download of the pages published on a web server, from a listing.
+//!
+//! Pages are downloaded by `curl`, which must be installed, and only when the
+//! `http` feature is enabled.
+use crate::archive::Entry;
+use eyre::Result;
+use std::path::{Path, PathBuf};
+
+/// A page listed on a web server.
+#[derive(Clone, Debug)]
+pub struct Page {
+    /// Name of the page: its URL relative to the listing, when under the same
+    /// location, or its whole URL without the scheme.
+    pub name: PathBuf,
+    /// Absolute URL of the page.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub url: String,
+}
+
+/// Check if the given path is an HTTP(S) URL.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| {
+        path.starts_with("http://") || path.starts_with("https://")
+    })
+}
+
+/// List the pages at the given URL, in the order of the listing.
+///
+/// The listing is either a JSON array of URLs, or a text file with one URL per
+/// line (the empty lines and those starting with `#` are ignored). The URLs
+/// can be relative to the listing: those that do not start with `http://` or
+/// `https://` are.
+#[cfg(feature = "http")]
+pub fn list_pages(url: &str) -> Result<Vec<Page>> {
+    use crate::json::parse_strings;
+    use eyre::{bail, Context};
+
+    let content = fetch(url)?;
+    let content =
+        String::from_utf8(content).wrap_err("listing is not valid UTF-8")?;
+    let links = if content.trim_start().starts_with('[') {
+        parse_strings(&content).wrap_err("parse listing")?
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect()
+    };
+
+    // Everything up to the last slash of the path (the query is left out).
+    let listing = url.split(['?', '#']).next().unwrap_or(url);
+    let path_start = scheme_len(listing).unwrap_or_default();
+    let base = match listing[path_start..].rfind('/') {
+        Some(end) => listing[..path_start + end + 1].to_owned(),
+        None => format!("{}/", listing),
+    };
+    let origin_end = base[path_start..].find('/').unwrap_or_default();
+    let origin = &base[..path_start + origin_end];
+
+    links
+        .into_iter()
+        .map(|link| {
+            let url = if scheme_len(&link).is_some() {
+                link
+            } else if link.starts_with('/') {
+                format!("{}{}", origin, link)
+            } else {
+                format!("{}{}", base, link)
+            };
+            let name = match url.strip_prefix(&base) {
+                Some(name) => name,
+                None => match scheme_len(&url) {
+                    Some(len) => &url[len..],
+                    None => bail!("unsupported URL {}", url),
+                },
+            };
+            Ok(Page {
+                name: PathBuf::from(name),
+                url,
+            })
+        })
+        .collect()
+}
+
+/// Download the given pages, in parallel.
+///
+/// Every page is kept in memory until returned.
+#[cfg(feature = "http")]
+pub fn fetch_pages(pages: Vec<Page>) -> Result<Vec<Entry>> {
+    use eyre::Context;
+    use rayon::prelude::*;
+
+    pages
+        .into_par_iter()
+        .map(|Page { name, url }| {
+            let contents =
+                fetch(&url).wrap_err_with(|| format!("download {}", url))?;
+            Ok(Entry { name, contents })
+        })
+        .collect()
+}
+
+/// Download the content at the given URL.
+#[cfg(feature = "http")]
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    use eyre::{ensure, Context};
+    use std::process::Command;
+
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        // Neither the listing nor its redirections can reach local files.
+        .args(["--proto", "=http,https", "--proto-redir", "=http,https"])
+        .arg(url)
+        .output()
+        .wrap_err("run curl (is it installed?)")?;
+    ensure!(
+        output.status.success(),
+        "curl failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(output.stdout)
+}
+
+/// Return the length of the HTTP(S) scheme of the URL, separator included.
+#[cfg(feature = "http")]
+fn scheme_len(url: &str) -> Option<usize> {
+    ["http://", "https://"]
+        .iter()
+        .find(|scheme| url.starts_with(*scheme))
+        .map(|scheme| scheme.len())
+}
+
+/// List the pages at the given URL.
+///
+/// Always fails, as the support of HTTP is disabled.
+#[cfg(not(feature = "http"))]
+pub fn list_pages(_url: &str) -> Result<Vec<Page>> {
+    eyre::bail!("HTTP support is disabled (enable the `http` feature)")
+}
+
+/// Download the given pages.
+///
+/// Always fails, as the support of HTTP is disabled.
+#[cfg(not(feature = "http"))]
+pub fn fetch_pages(_pages: Vec<Page>) -> Result<Vec<Entry>> {
+    eyre::bail!("HTTP support is disabled (enable the `http` feature)")
+}
@@ -0,0 +1,163 @@
+//! Minimum-cost assignment, using the Hungarian algorithm.
+
+/// Assign each row of the cost matrix to a distinct column, minimizing the
+/// total cost.
+///
+/// The matrix must have at least as many columns as rows. Returns the column
+/// assigned to each row.
+pub fn solve(costs: &[Vec<i64>]) -> Vec<usize> {
+    if costs.is_empty() {
+        return Vec::new();
+    }
+    let rows = costs.len();
+    let columns = costs[0].len();
+    assert!(rows <= columns, "more rows than columns");
+
+    // Potentials of the rows and columns, and the row assigned to each column.
+    // Index 0 is a sentinel, the actual rows and columns start at 1.
+    let mut u = vec![0; rows + 1];
+    let mut v = vec![0; columns + 1];
+    let mut assigned = vec![0; columns + 1];
+    let mut way = vec![0; columns + 1];
+
+    for row in 1..=rows {
+        // Look for an augmenting path starting from `row`.
+        assigned[0] = row;
+        let mut column = 0;
+        let mut min = vec![i64::MAX; columns + 1];
+        let mut used = vec![false; columns + 1];
+        loop {
+            used[column] = true;
+            let current = assigned[column];
+            let mut delta = i64::MAX;
+            let mut next = 0;
+            for j in 1..=columns {
+                if used[j] {
+                    continue;
+                }
+                let cost = costs[current - 1][j - 1] - u[current] - v[j];
+                if cost < min[j] {
+                    min[j] = cost;
+                    way[j] = column;
+                }
+                if min[j] < delta {
+                    delta = min[j];
+                    next = j;
+                }
+            }
+            for j in 0..=columns {
+                if used[j] {
+                    u[assigned[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min[j] -= delta;
+                }
+            }
+            column = next;
+            if assigned[column] == 0 {
+                break;
+            }
+        }
+
+        // Flip the assignments along the path.
+        while column != 0 {
+            let previous = way[column];
+            assigned[column] = assigned[previous];
+            column = previous;
+        }
+    }
+
+    let mut solution = vec![0; rows];
+    for (column, &row) in assigned.iter().enumerate().skip(1) {
+        if row != 0 {
+            solution[row - 1] = column - 1;
+        }
+    }
+    solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Total cost of an assignment.
+    fn total(costs: &[Vec<i64>], solution: &[usize]) -> i64 {
+        solution
+            .iter()
+            .enumerate()
+            .map(|(row, &column)| costs[row][column])
+            .sum()
+    }
+
+    /// Lowest total cost of an assignment, trying them all.
+    fn brute_force(costs: &[Vec<i64>], row: usize, used: &mut [bool]) -> i64 {
+        if row == costs.len() {
+            return 0;
+        }
+        let mut best = i64::MAX;
+        for column in 0..used.len() {
+            if used[column] {
+                continue;
+            }
+            used[column] = true;
+            let cost = costs[row][column] + brute_force(costs, row + 1, used);
+            best = best.min(cost);
+            used[column] = false;
+        }
+        best
+    }
+
+    /// Check that the solution assigns distinct columns, at the lowest cost.
+    fn check(costs: &[Vec<i64>]) -> Vec<usize> {
+        let solution = solve(costs);
+        assert_eq!(solution.len(), costs.len());
+        let mut columns = solution.clone();
+        columns.sort_unstable();
+        columns.dedup();
+        assert_eq!(columns.len(), solution.len(), "column assigned twice");
+        let mut used = vec![false; costs[0].len()];
+        assert_eq!(total(costs, &solution), brute_force(costs, 0, &mut used));
+        solution
+    }
+
+    #[test]
+    fn empty() {
+        assert!(solve(&[]).is_empty());
+    }
+
+    #[test]
+    fn square() {
+        // The greedy choice (0 for the first row) would cost 1 + 10.
+        let costs = vec![vec![1, 2], vec![3, 10]];
+        assert_eq!(check(&costs), [1, 0]);
+
+        let costs = vec![vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]];
+        assert_eq!(check(&costs), [1, 0, 2]);
+    }
+
+    #[test]
+    fn rectangular() {
+        let costs = vec![vec![7, 3, 9, 1], vec![8, 2, 6, 1]];
+        assert_eq!(check(&costs), [3, 1]);
+
+        // A single row takes its cheapest column.
+        assert_eq!(check(&[vec![5, 4, 8]]), [1]);
+    }
+
+    #[test]
+    fn ties() {
+        // Every assignment costs the same.
+        let costs = vec![vec![3; 3]; 3];
+        check(&costs);
+
+        let costs = vec![vec![0, 0, 1], vec![0, 0, 1]];
+        let solution = check(&costs);
+        assert!(solution.iter().all(|&column| column < 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "more rows than columns")]
+    fn more_rows() {
+        solve(&[vec![1], vec![2]]);
+    }
+}
@@ -0,0 +1,293 @@
+//! Minimal JSON support, shared by the reports, the manifests and the listings.
+//!
+//! Documents are written by hand, with [`push_string`] to escape the strings,
+//! and read back into a [`Value`] tree.
+use eyre::{bail, ensure, eyre, Context, Result};
+use std::{collections::HashMap, fmt::Write as _};
+
+/// Append the value as a JSON string.
+pub fn push_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                write!(json, "\\u{:04x}", c as u32).expect("write to string");
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// A JSON value.
+///
+/// Numbers are kept as written, to be parsed into the expected type.
+#[derive(Debug)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+}
+
+impl Value {
+    pub(crate) fn to_bool(&self) -> Result<bool> {
+        match self {
+            Self::Bool(value) => Ok(*value),
+            _ => bail!("expected a boolean"),
+        }
+    }
+
+    pub(crate) fn to_number(&self) -> Result<&str> {
+        match self {
+            Self::Number(value) => Ok(value),
+            _ => bail!("expected a number"),
+        }
+    }
+
+    pub(crate) fn to_u32(&self) -> Result<u32> {
+        self.to_number()?.parse().wrap_err("expected an integer")
+    }
+
+    pub(crate) fn to_str(&self) -> Result<&str> {
+        match self {
+            Self::String(value) => Ok(value),
+            _ => bail!("expected a string"),
+        }
+    }
+
+    pub(crate) fn to_array(&self) -> Result<&[Value]> {
+        match self {
+            Self::Array(values) => Ok(values),
+            _ => bail!("expected an array"),
+        }
+    }
+}
+
+/// Parse a JSON array of strings (e.g. a listing of pages).
+#[cfg(feature = "http")]
+pub(crate) fn parse_strings(content: &str) -> Result<Vec<String>> {
+    parse(content)?
+        .to_array()?
+        .iter()
+        .map(|value| value.to_str().map(str::to_owned))
+        .collect()
+}
+
+/// Parse a whole JSON document.
+pub(crate) fn parse(content: &str) -> Result<Value> {
+    let mut parser = Parser {
+        input: content,
+        position: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    ensure!(
+        parser.position == content.len(),
+        "trailing characters at offset {}",
+        parser.position
+    );
+    Ok(value)
+}
+
+/// Return the field of a JSON object.
+pub(crate) fn field<'a>(object: &'a Value, name: &str) -> Result<&'a Value> {
+    optional_field(object, name)?
+        .ok_or_else(|| eyre!("missing field {:?}", name))
+}
+
+/// Get the field of an object, if present.
+pub(crate) fn optional_field<'a>(
+    object: &'a Value,
+    name: &str,
+) -> Result<Option<&'a Value>> {
+    match object {
+        Value::Object(fields) => Ok(fields.get(name)),
+        _ => bail!("expected an object"),
+    }
+}
+
+/// A minimal JSON parser, enough to read the manifests and the listings back.
+struct Parser<'a> {
+    input: &'a str,
+    /// Offset of the next character.
+    position: usize,
+}
+
+impl Parser<'_> {
+    /// Parse the value at the current position.
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => Ok(self.number()),
+            Some(c) => bail!("unexpected {:?} at offset {}", c, self.position),
+            None => bail!("unexpected end of input"),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut fields = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            fields.insert(name, value);
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => bail!("expected ',' or '}}' at offset {}", self.position),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => bail!("expected ',' or ']' at offset {}", self.position),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(value),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => {
+                            bail!("invalid escape at offset {}", self.position)
+                        },
+                    };
+                    value.push(c);
+                },
+                Some(c) => value.push(c),
+                None => bail!("unterminated string"),
+            }
+        }
+    }
+
+    /// Parse the code point of a `\u` escape, which may be a surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            ensure!(
+                self.input[self.position..].starts_with("\\u"),
+                "unpaired surrogate at offset {}",
+                self.position
+            );
+            self.position += 2;
+            let low = self.hex4()?;
+            ensure!(
+                (0xdc00..0xe000).contains(&low),
+                "invalid surrogate at offset {}",
+                self.position
+            );
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| {
+            eyre!("invalid code point at offset {}", self.position)
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .ok_or_else(|| eyre!("truncated escape"))?;
+        let code = u32::from_str_radix(digits, 16).wrap_err_with(|| {
+            format!("invalid escape at offset {}", self.position)
+        })?;
+        self.position += 4;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Value {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        {
+            self.position += 1;
+        }
+        Value::Number(self.input[start..self.position].to_owned())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value> {
+        ensure!(
+            self.input[self.position..].starts_with(word),
+            "unexpected token at offset {}",
+            self.position
+        );
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            _ => bail!("expected {:?} at offset {}", expected, self.position),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+}
@@ -0,0 +1,75 @@
+//! Compare and match two sets of images.
+//!
+//! Pages from each version are hashed using a perceptual hash, then each page
+//! of the new version is matched against the closest page of the old one.
+mod archive;
+mod cache;
+mod db;
+mod dedup;
+mod diff;
+mod digest;
+mod extract;
+mod formats;
+mod hash;
+mod http;
+mod hungarian;
+pub mod json;
+pub mod log;
+mod manifest;
+mod matching;
+mod mmap;
+mod natural;
+mod pdf;
+mod progress;
+mod spreads;
+
+pub use bktree::BkTree;
+pub use cache::Cache;
+pub use dedup::find_duplicates;
+pub use diff::{diff_pages, Change, PageChange};
+pub use extract::extract_pages;
+pub use hash::{
+    hash_bytes,
+    hash_images,
+    hash_in_memory,
+    hash_manifest,
+    hash_page_list,
+    list_images,
+    Algorithm,
+    Glob,
+    HashConfig,
+    HashOptions,
+    HashedImage,
+    HashedPages,
+    PageRange,
+    PageTiming,
+    SkippedPage,
+    SortKey,
+    IMAGE_EXTENSIONS,
+};
+pub use manifest::{Manifest, ManifestEntry, Mismatch, MANIFEST_SCHEMA};
+pub use matching::{
+    align_versions,
+    best_distances,
+    find_offset,
+    image_distance,
+    index_pages,
+    match_in_memory,
+    match_one,
+    match_pages,
+    match_pages_indexed,
+    match_pages_with_progress,
+    stream_matches,
+    Assignment,
+    Match,
+    MatchCost,
+    MatchOptions,
+    MatchReport,
+    MatchStream,
+    Metric,
+    PageIndex,
+    Prefer,
+    Summary,
+};
+pub use progress::{Progress, ProgressBar, Task};
+pub use spreads::{detect_spreads, Spread, SpreadKind};
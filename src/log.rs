@@ -0,0 +1,94 @@
+//! Minimal logging of diagnostics on the standard error.
+//!
+//! The standard output is kept for the reports.
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Importance of a log message, from the most to the least important.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Return the level matching the number of `-v` flags.
+    pub fn from_verbosity(verbosity: u8) -> Self {
+        match verbosity {
+            0 => Self::Warn,
+            1 => Self::Info,
+            2 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warn => "warning",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        })
+    }
+}
+
+/// Least important level that is printed.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+/// Set the least important level that is printed (warnings by default).
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Check if the messages of the given level are printed.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Print the message, if its level is enabled.
+///
+/// Use the `warn!`, `info!`, `debug!` and `trace!` macros instead.
+#[doc(hidden)]
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    if enabled(level) {
+        eprintln!("{}: {}", level, args);
+    }
+}
+
+/// Log a warning.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Warn, format_args!($($arg)+))
+    };
+}
+
+/// Log an informative message (printed with `-v`).
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Info, format_args!($($arg)+))
+    };
+}
+
+/// Log a debug message (printed with `-vv`).
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Debug, format_args!($($arg)+))
+    };
+}
+
+/// Log a very detailed message (printed with `-vvv`).
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Trace, format_args!($($arg)+))
+    };
+}
@@ -1,244 +1,1767 @@
-use bktree::BkTree;
-use eyre::{Context, Result};
-use image::io::Reader as ImageReader;
-use img_hash::{HashAlg, HasherConfig, ImageHash};
-use rayon::prelude::*;
+use eyre::{ensure, Context, Result};
+use img_match::{
+    align_versions,
+    best_distances,
+    debug,
+    detect_spreads,
+    diff_pages,
+    extract_pages,
+    find_duplicates,
+    hash_images,
+    hash_manifest,
+    hash_page_list,
+    info,
+    list_images,
+    log::{self, Level},
+    match_pages,
+    match_pages_indexed,
+    match_pages_with_progress,
+    stream_matches,
+    warn,
+    Algorithm,
+    Assignment,
+    Cache,
+    Glob,
+    HashConfig,
+    HashOptions,
+    HashedImage,
+    HashedPages,
+    Manifest,
+    Match,
+    MatchOptions,
+    MatchReport,
+    Metric,
+    PageChange,
+    PageIndex,
+    PageRange,
+    PageTiming,
+    Prefer,
+    ProgressBar,
+    SkippedPage,
+    SortKey,
+    Summary,
+    IMAGE_EXTENSIONS,
+};
+use rayon::ThreadPoolBuilder;
 use std::{
-    cmp::Ordering,
-    collections::HashSet,
-    ffi::OsString,
+    borrow::Cow,
+    cmp::Reverse,
     fs::{self, File},
-    io::{Cursor, Read},
+    io::{self, BufRead, BufWriter, Write},
+    mem,
     path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 
+mod config;
+mod hashes;
+mod html;
+mod report;
+mod review;
+mod script;
+mod watch;
+
+use hashes::{read_hashes, write_hashes};
+use html::{render_html, THUMBNAIL_SIZE};
+use report::{
+    render_by_old,
+    render_by_quality,
+    render_diff,
+    render_duplicates,
+    render_report,
+    render_sweep,
+    render_versions,
+    write_json_line,
+    write_json_lines_footer,
+    write_text_footer,
+    write_text_header,
+    write_text_match,
+    ColorChoice,
+    Format,
+    GroupBy,
+    Report,
+};
+use review::review_matches;
+use script::{
+    render_script,
+    write_script_footer,
+    write_script_header,
+    write_script_match,
+    Shell,
+};
+use watch::watch;
+
 #[derive(Debug, StructOpt)]
 pub struct Opts {
-    #[structopt(short, long, parse(from_os_str))]
-    old: PathBuf,
+    /// Configuration file setting the default options (`img-match.toml` in
+    /// the current directory, if any).
+    ///
+    /// Each line is a `key = value` pair, where the key is the long name of an
+    /// option (e.g. `hash-size = [8, 8]` or `no-dct = true`). The options
+    /// given on the command line take precedence.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Directory (or ZIP/CBZ archive, PDF document, or URL of a listing of
+    /// pages) of the old version.
+    ///
+    /// Use `-` to read the paths of the pages from the standard input (one per
+    /// line), instead.
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        required_unless_one = &["version", "load-hashes"]
+    )]
+    old: Option<PathBuf>,
+
+    /// Directory (or ZIP/CBZ archive, PDF document, or URL of a listing of
+    /// pages) of the new version, or `-` to read the paths of the pages from
+    /// the standard input.
+    ///
+    /// Can be repeated to match the old version against several new ones,
+    /// with one report per new version: the old version is only hashed once.
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        required_unless_one = &["dedup", "list-only", "version", "load-hashes"]
+    )]
+    new: Vec<PathBuf>,
+
+    /// File listing the pages of the old version in order, one path (relative
+    /// to the old version) per line, instead of looking for and sorting them.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        requires = "old",
+        conflicts_with_all = &["version", "load-hashes"]
+    )]
+    old_manifest: Option<PathBuf>,
+
+    /// File listing the pages of the new version in order, like
+    /// `--old-manifest`.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        requires = "new",
+        conflicts_with_all = &["version", "load-hashes"]
+    )]
+    new_manifest: Option<PathBuf>,
+
+    /// Load the hashes of both versions from a file written by
+    /// `--dump-hashes`, instead of hashing the pages.
+    ///
+    /// The pages themselves are not needed, but the hash settings must be the
+    /// same as when dumping the hashes.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        conflicts_with_all = &[
+            "old", "new", "version", "list-only", "html", "interactive",
+            "extract-new", "extract-missing"
+        ]
+    )]
+    load_hashes: Option<PathBuf>,
+
+    /// Directory (or ZIP/CBZ archive) of a version to align with the others,
+    /// instead of comparing an old and a new version.
+    ///
+    /// The pages of every version are printed as a table, with one row per
+    /// page and one column per version.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        min_values = 2,
+        conflicts_with_all = &["old", "new", "dedup", "script"]
+    )]
+    version: Vec<PathBuf>,
+
+    /// Maximum distance between two matching pages (not needed with `--sweep`
+    /// or `--list-only`).
+    #[structopt(short, long)]
+    distance: Option<u8>,
+
+    /// Match the pages with every maximum distance from MIN to MAX, and only
+    /// print how many pages are matched for each of them.
+    ///
+    /// This helps choosing `--distance`, as the pages are only hashed once.
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["MIN", "MAX"],
+        conflicts_with_all = &[
+            "version", "dedup", "script", "diff", "html", "interactive",
+            "extract-new", "extract-missing", "summary", "group-by",
+            "grouped"
+        ]
+    )]
+    sweep: Vec<u8>,
+
+    /// Print the histogram of the distances between each page of the new
+    /// version and its closest page in the old one, whatever `--distance`, to
+    /// tell the true matches from the false positives.
+    #[structopt(long, conflicts_with_all = &["version", "dedup"])]
+    histogram: bool,
+
+    /// After matching the hashes, pair the remaining pages that have the same
+    /// filename in both versions.
+    #[structopt(long)]
+    filename_fallback: bool,
+
+    /// Compare the filenames without their directories with
+    /// `--filename-fallback`, for versions organized differently (e.g. in
+    /// subdirectories and flat).
+    #[structopt(long, requires = "filename-fallback")]
+    flatten_for_matching: bool,
+
+    /// Let several pages of the new version match the same page of the old
+    /// one, and flag them as duplicates (to spot the pages duplicated by
+    /// mistake).
+    #[structopt(long)]
+    report_duplicates: bool,
+
+    /// Before matching, find how many pages were added or removed at the
+    /// front of the new version (cover, ads…), so that the page positions are
+    /// compared once shifted.
+    #[structopt(long)]
+    align: bool,
+
+    /// Flag the matches as ambiguous when the next best candidate costs at
+    /// most MARGIN more (defaults to 1, i.e. a bit of hash distance), and
+    /// report that candidate.
+    #[structopt(long, value_name = "MARGIN")]
+    report_ambiguous: Option<Option<f64>>,
+
+    /// Look for the pages split in two, or merged into one, among the new and
+    /// missing pages (experimental).
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "version", "dedup", "sweep", "stream", "load-hashes", "diff",
+            "script", "group-by", "grouped"
+        ]
+    )]
+    detect_spreads: bool,
+
+    /// Flag the matches above this distance as uncertain, to be reviewed
+    /// (defaults to the maximum distance).
+    #[structopt(long)]
+    accept_distance: Option<u8>,
+
+    /// How much the page positions weigh when choosing between several
+    /// matches (0 to only rely on the hash distance).
+    #[structopt(long, default_value = "0.2")]
+    position_weight: f64,
+
+    /// Stop penalizing the difference of position of the pages beyond W
+    /// pages, so that the pages moved far away (e.g. reordered chapters) still
+    /// match when their hashes agree.
+    #[structopt(long, value_name = "W")]
+    position_window: Option<usize>,
+
+    /// Strategy used to pair the pages: greedy (fast) or optimal (minimize
+    /// the total cost of the matches).
+    #[structopt(
+        long,
+        default_value = "greedy",
+        possible_values = Assignment::NAMES
+    )]
+    assignment: Assignment,
+
+    /// Distance between the pages: hamming (between their hashes) or
+    /// hamming-plus-size (also counting how many times their file sizes
+    /// double from one to the other).
+    #[structopt(long, default_value = "hamming", possible_values = Metric::NAMES)]
+    metric: Metric,
+
+    /// Page to prefer when several pages of the old version match equally:
+    /// the larger one in pixels (e.g. a full page over its thumbnail), the
+    /// smaller one, or none (the first one).
+    ///
+    /// The dimensions of the pages found in the cache are read from the
+    /// header of their file. Only with the greedy assignment.
+    #[structopt(long, default_value = "none", possible_values = Prefer::NAMES)]
+    prefer: Prefer,
 
+    /// Compare every pair of pages instead of indexing them, which is slower
+    /// but doesn't need a true metric (and keeps the pages sharing the same
+    /// hash).
+    #[structopt(long)]
+    brute_force: bool,
+
+    /// Output format of the report (text, json, csv or json-lines).
+    #[structopt(short, long, default_value = "text")]
+    format: Format,
+
+    /// Color the text report: auto (when writing to a terminal), always or
+    /// never.
+    #[structopt(
+        long,
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    color: ColorChoice,
+
+    /// Do not color the text report, like `--color never`.
+    #[structopt(long)]
+    no_color: bool,
+
+    /// Key the report by the pages of the new version, with their match, by
+    /// the pages of the old version, with what they became, or group the
+    /// pages by match quality (defaults to new).
+    ///
+    /// By quality, the pages are split into exact matches (distance 0), close
+    /// ones (within `--accept-distance`), uncertain ones (beyond it), new and
+    /// missing pages. Without `--accept-distance`, the pages are then looked
+    /// up to twice the maximum distance, the matches beyond it being
+    /// uncertain.
+    #[structopt(
+        long,
+        possible_values = &["new", "old", "quality"],
+        conflicts_with_all = &["script", "diff", "version", "dedup"]
+    )]
+    group_by: Option<GroupBy>,
+
+    /// Group the report by match quality, like `--group-by quality`.
+    #[structopt(
+        long,
+        conflicts_with_all = &["group-by", "script", "diff", "version", "dedup"]
+    )]
+    grouped: bool,
+
+    /// Leave the confident matches within DISTANCE (defaults to 0) out of the
+    /// report, to only show what changed: the other matches, and the new and
+    /// missing pages.
+    #[structopt(
+        long,
+        value_name = "DISTANCE",
+        conflicts_with_all = &[
+            "version",
+            "dedup",
+            "sweep",
+            "script",
+            "extract-new",
+            "extract-missing",
+        ]
+    )]
+    only_changed: Option<Option<u8>>,
+
+    /// Leave every match out of the text report, to only show the new and
+    /// missing pages (e.g. with `--strict`, to fail when any page was added or
+    /// removed).
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "only-changed",
+            "version",
+            "dedup",
+            "sweep",
+            "script",
+            "extract-new",
+            "extract-missing",
+            "group-by",
+            "grouped",
+            "diff",
+            "watch",
+        ]
+    )]
+    changes_only: bool,
+
+    /// Look for pages in subdirectories as well.
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// Follow the symbolic links to pages and directories.
+    ///
+    /// By default, links are ignored: they could make the same pages appear
+    /// several times (shifting the index of the next ones), or even loop.
+    #[structopt(long, overrides_with = "no-follow-symlinks")]
+    follow_symlinks: bool,
+
+    /// Ignore the symbolic links (the default).
+    #[structopt(long, overrides_with = "follow-symlinks")]
+    no_follow_symlinks: bool,
+
+    /// Skip the hidden files and directories (the default).
+    ///
+    /// Their name starts with a dot, like the metadata left by the OS (e.g.
+    /// `.DS_Store`, or the `._` files from macOS) that cannot be decoded.
+    #[structopt(long, overrides_with = "include-hidden")]
+    ignore_hidden: bool,
+
+    /// Look for pages in the hidden files and directories as well.
+    #[structopt(long, overrides_with = "ignore-hidden")]
+    include_hidden: bool,
+
+    /// File where the report is written, instead of the standard output.
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// File where the page hashes are cached between runs.
     #[structopt(short, long, parse(from_os_str))]
-    new: PathBuf,
+    cache: Option<PathBuf>,
+
+    /// SQLite database where the page hashes are cached between runs,
+    /// instead of a file (requires the `sqlite` feature).
+    ///
+    /// Better suited to large libraries, as the pages are looked up one at a
+    /// time and only those hashed during the run are stored.
+    #[structopt(long, parse(from_os_str), conflicts_with = "cache")]
+    db: Option<PathBuf>,
+
+    /// Look the pages up in the cache by content instead of by path, so that
+    /// renamed pages are found (but every page must be read).
+    #[structopt(long, requires = "cache")]
+    cache_by_content: bool,
+
+    /// Pair the pages that are byte-for-byte identical in both versions first,
+    /// reusing the hash of the old page for the new one, then match the other
+    /// ones.
+    #[structopt(long, conflicts_with_all = &["stream", "load-hashes"])]
+    pair_identical: bool,
+
+    /// Print the N pages that took the longest to hash in each version
+    /// (defaults to 10), to spot the pathologically large ones.
+    #[structopt(long, value_name = "N")]
+    timings: Option<Option<usize>>,
+
+    /// Perceptual hash algorithm.
+    #[structopt(
+        short,
+        long,
+        default_value = "double-gradient",
+        possible_values = Algorithm::NAMES
+    )]
+    algorithm: Algorithm,
+
+    /// Width and height of the hash.
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["WIDTH", "HEIGHT"],
+        default_value = "8 8",
+        use_delimiter = true,
+        value_delimiter = " "
+    )]
+    hash_size: Vec<u32>,
+
+    /// Disable the DCT preprocessing of the images.
+    #[structopt(long)]
+    no_dct: bool,
+
+    /// Also compare the pages of the new version once flipped horizontally,
+    /// to match the pages that were mirrored (e.g. between right-to-left and
+    /// left-to-right releases).
+    ///
+    /// Every page of the new version must be decoded for that, even when its
+    /// hash is cached.
+    #[structopt(long)]
+    mirror: bool,
+
+    /// Also compare the pages of the new version with their colors inverted,
+    /// to match the pages that were inverted (e.g. white text on black).
+    ///
+    /// Every page of the new version must be decoded for that, even when its
+    /// hash is cached.
+    #[structopt(long)]
+    invert: bool,
+
+    /// Crop the uniform borders of the pages before hashing them, so that
+    /// pages with different margins still match.
+    #[structopt(long)]
+    trim_borders: bool,
+
+    /// Convert the pages to grayscale before hashing them, so that color
+    /// pages still match their grayscale reprint (and vice versa).
+    #[structopt(long)]
+    grayscale: bool,
+
+    /// Equalize the tones of the pages (in grayscale) before hashing them, so
+    /// that the pages reprinted with other tones still match.
+    #[structopt(long)]
+    equalize: bool,
+
+    /// Rotate the pages as told by their EXIF orientation (for JPEG and PNG
+    /// images) before hashing them, so that the pages stored rotated still
+    /// match their upright twin.
+    #[structopt(long)]
+    apply_exif_orientation: bool,
 
+    /// Pad the pages into a square before hashing them, so that the pages
+    /// whose aspect ratio changed slightly (e.g. rescaled releases) still
+    /// match.
+    #[structopt(long)]
+    pad_to_square: bool,
+
+    /// Blur the pages slightly before hashing them, so that heavily
+    /// re-compressed pages still match their original (0 to disable).
+    #[structopt(long, default_value = "0", value_name = "SIGMA")]
+    preblur: f32,
+
+    /// Frame of the animated pages (GIF or APNG) to hash, counting from 0.
+    ///
+    /// The last frame is hashed when a page has fewer frames.
+    #[structopt(long, default_value = "0", value_name = "N")]
+    gif_frame: u32,
+
+    /// Downscale the pages larger than this many pixels (in width or height)
+    /// once decoded, before hashing them (0 to disable).
+    ///
+    /// Much faster with huge scans, for a tiny loss of accuracy.
+    #[structopt(long, default_value = "0", value_name = "PIXELS")]
+    max_decode_dim: u32,
+
+    /// Do not display the progress while hashing.
     #[structopt(short, long)]
-    distance: u8,
-}
+    quiet: bool,
+
+    /// Maximum number of threads used to hash and match the pages (0 to use
+    /// every core).
+    #[structopt(short = "j", long, default_value = "0")]
+    threads: usize,
+
+    /// Print more diagnostics on the standard error (-vv for debug messages).
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    /// Skip the pages that cannot be read or decoded, instead of aborting.
+    #[structopt(short, long)]
+    skip_errors: bool,
+
+    /// Resolution used to rasterize the pages of PDF documents, in dots per
+    /// inch (requires the `pdf` feature).
+    #[structopt(long, default_value = "150")]
+    pdf_dpi: u32,
+
+    /// Only hash one page out of N in each version, for a quick approximate
+    /// mapping.
+    #[structopt(long, default_value = "1", value_name = "N")]
+    sample: usize,
+
+    /// Pick the sampled pages pseudo-randomly from this seed, rather than
+    /// every N pages.
+    ///
+    /// The same seed always samples the same positions, so that runs can be
+    /// compared.
+    #[structopt(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Only hash the pages of the old version in this range (e.g. `50:80`,
+    /// counting from 1 in the order of the pages, both included).
+    ///
+    /// The pages keep their position among all the pages, to be matched
+    /// with the pages at the same position in the new version.
+    #[structopt(
+        long,
+        value_name = "FIRST:LAST",
+        conflicts_with_all = &["version", "load-hashes"]
+    )]
+    old_range: Option<PageRange>,
+
+    /// Only hash the pages of the new version in this range (see
+    /// `--old-range`).
+    #[structopt(
+        long,
+        value_name = "FIRST:LAST",
+        conflicts_with_all = &["version", "load-hashes"]
+    )]
+    new_range: Option<PageRange>,
+
+    /// Skip the pages narrower than this (e.g. ad banners or credits), in
+    /// pixels.
+    #[structopt(long, default_value = "0", value_name = "PIXELS")]
+    min_width: u32,
+
+    /// Skip the pages shorter than this (e.g. cover thumbnails), in pixels.
+    #[structopt(long, default_value = "0", value_name = "PIXELS")]
+    min_height: u32,
+
+    /// Map the page files in memory instead of streaming them through a
+    /// buffered reader (the files must not be modified meanwhile).
+    #[structopt(long)]
+    mmap: bool,
+
+    /// Abort when a version holds more than N pages, before hashing them
+    /// (0, the default, for no limit).
+    #[structopt(long, default_value = "0", value_name = "N")]
+    max_pages: usize,
+
+    /// Order of the pages, which gives them their position: path (as is),
+    /// name-ci (ignoring the case) or natural (numbers compared by value).
+    #[structopt(
+        long,
+        default_value = "natural",
+        possible_values = SortKey::NAMES
+    )]
+    sort_key: SortKey,
+
+    /// Also consider the files with this extension as pages.
+    #[structopt(long, number_of_values = 1)]
+    include_ext: Vec<String>,
+
+    /// Ignore the files with this extension.
+    #[structopt(long, number_of_values = 1)]
+    exclude_ext: Vec<String>,
+
+    /// Leave out the pages whose filename matches this pattern, from both
+    /// versions (e.g. `cover*` or `*credits*`, ignoring the case).
+    ///
+    /// `*` stands for any sequence of characters, and `?` for any single one.
+    /// Useful for the covers, credits and ads that only clutter the report.
+    #[structopt(long, number_of_values = 1, value_name = "PATTERN")]
+    exclude_glob: Vec<Glob>,
+
+    /// Print a script copying the new pages over the old ones instead of the
+    /// report (bash or powershell).
+    #[structopt(long, possible_values = &["bash", "powershell"])]
+    script: Option<Shell>,
+
+    /// Also write the report as an HTML page, with the thumbnails of the
+    /// pages side-by-side.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["dedup", "version"]
+    )]
+    html: Option<PathBuf>,
+
+    /// Write the hash of every page of both versions to this CSV file, for
+    /// external processing.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        conflicts_with_all = &["dedup", "version"]
+    )]
+    dump_hashes: Option<PathBuf>,
+
+    /// Also write the page mapping to this file, as a JSON manifest recording
+    /// the hash settings, to check it again later with `--verify-manifest`.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        conflicts_with_all = &["dedup", "version", "stream", "sweep", "watch"]
+    )]
+    manifest: Option<PathBuf>,
+
+    /// Check that the pages still match as recorded in this manifest (written
+    /// by `--manifest`), e.g. once they are moved, and fail otherwise.
+    ///
+    /// The pages are hashed and compared with the settings recorded in the
+    /// manifest, whatever the hash settings given.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        conflicts_with_all = &["dedup", "version", "stream", "sweep", "watch"]
+    )]
+    verify_manifest: Option<PathBuf>,
+
+    /// Copy the new pages that have no match into this directory, keeping
+    /// their filenames.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["dedup", "version"]
+    )]
+    extract_new: Option<PathBuf>,
 
-#[derive(Debug, Eq)]
-struct Page {
-    path: PathBuf,
-    size: usize,
+    /// Copy the old pages that are missing from the new version into this
+    /// directory, keeping their filenames.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["dedup", "version"]
+    )]
+    extract_missing: Option<PathBuf>,
+
+    /// Print the changes between the versions, like a diff, instead of the
+    /// page mapping.
+    ///
+    /// The longest sequence of pages in the same order in both versions is
+    /// kept, and the other pages are flagged as inserted, deleted or
+    /// reordered.
+    #[structopt(long, conflicts_with_all = &["script", "version", "dedup"])]
+    diff: bool,
+
+    /// Look for duplicate pages in the old version, instead of comparing the
+    /// versions.
+    #[structopt(long, conflicts_with = "new")]
+    dedup: bool,
+
+    /// Only list the pages found in each version, with their index, without
+    /// hashing them.
+    #[structopt(long)]
+    list_only: bool,
+
+    /// Review the uncertain matches one by one: both pages are opened, and
+    /// the match is kept or rejected depending on the answer.
+    ///
+    /// Use `--accept-distance` to choose which matches are uncertain.
+    #[structopt(long, conflicts_with_all = &["version", "dedup"])]
+    interactive: bool,
+
+    /// Also print statistics of the matching (number of pages matched, missing
+    /// and added, and distance of the matches), in the text and JSON reports.
+    #[structopt(long, conflicts_with_all = &["script", "version", "dedup"])]
+    summary: bool,
+
+    /// Exit with a non-zero status when the versions differ.
+    ///
+    /// The exit status is 2 when pages are missing from the new version, 4
+    /// when the new version has extra pages and 6 when both happen (1 is used
+    /// for errors).
+    ///
+    /// When aligning several versions, the exit status is 2 when a page is
+    /// missing from any of them.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Print each match as soon as it is decided, instead of once every page
+    /// is matched (only for text reports and scripts, with the greedy
+    /// assignment).
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "version", "dedup", "sweep", "diff", "html", "interactive",
+            "extract-new", "extract-missing", "summary", "group-by", "grouped",
+            "report-duplicates", "filename-fallback"
+        ]
+    )]
+    stream: bool,
+
+    /// Keep watching the directory of the new version, and match its pages as
+    /// they are added or changed, until interrupted.
+    ///
+    /// The directory is checked SECONDS (1 by default) after a change is
+    /// notified, or every SECONDS on the systems other than Linux. The pages
+    /// are matched on their own, by hash distance only (mirrored and inverted
+    /// pages included, with `--mirror` and `--invert`), in a text report.
+    #[structopt(
+        long,
+        value_name = "SECONDS",
+        conflicts_with_all = &[
+            "version", "dedup", "load-hashes", "new-manifest", "script",
+            "stream", "sweep", "histogram", "interactive"
+        ]
+    )]
+    watch: Option<Option<u64>>,
 }
 
-impl Ord for Page {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.path.cmp(&other.path)
+/// Path standing for a list of pages read from the standard input.
+const STDIN: &str = "-";
+
+/// Exit status when pages are missing from the new version (with `--strict`).
+const EXIT_MISSING: u8 = 2;
+/// Exit status when the new version has extra pages (with `--strict`).
+const EXIT_NEW: u8 = 4;
+
+/// Number of pages printed by `--timings`, by default.
+const DEFAULT_TIMINGS: usize = 10;
+/// Seconds between the checks of `--watch`, by default.
+const DEFAULT_WATCH_INTERVAL: u64 = 1;
+
+fn main() -> Result<ExitCode> {
+    let matches = Opts::clap().get_matches();
+    let mut opts = Opts::from_clap(&matches);
+    config::apply_defaults(&mut opts, &matches)?;
+    ensure!(
+        opts.distance.is_some() || !opts.sweep.is_empty() || opts.list_only,
+        "--distance is required (unless set by the configuration file, or \
+         with --sweep or --list-only)"
+    );
+    log::set_max_level(Level::from_verbosity(opts.verbose));
+
+    match opts.threads {
+        0 => run(opts),
+        threads => ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .wrap_err("create thread pool")?
+            .install(|| run(opts)),
     }
 }
 
-impl PartialOrd for Page {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+fn run(opts: Opts) -> Result<ExitCode> {
+    let start = Instant::now();
+    let mut phases = Phases::default();
+    if let Some(path) = &opts.config {
+        info!("using default options from {}", path.display());
     }
-}
 
-impl PartialEq for Page {
-    fn eq(&self, other: &Self) -> bool {
-        self.path == other.path
+    let verified = opts
+        .verify_manifest
+        .as_deref()
+        .map(Manifest::load)
+        .transpose()?;
+
+    // Both versions must be hashed with the same settings, otherwise the
+    // distances are meaningless.
+    let config = match &verified {
+        Some(manifest) => manifest.config,
+        None => HashConfig::new(
+            opts.algorithm,
+            opts.hash_size[0],
+            opts.hash_size[1],
+        )?
+        .dct(!opts.no_dct)
+        .trim_borders(opts.trim_borders)
+        .grayscale(opts.grayscale)
+        .equalize(opts.equalize)
+        .exif_orientation(opts.apply_exif_orientation)
+        .pad_to_square(opts.pad_to_square)
+        .preblur(opts.preblur)
+        .frame(opts.gif_frame)
+        .max_dimension(opts.max_decode_dim),
+    };
+    let options = HashOptions {
+        config,
+        recursive: opts.recursive,
+        follow_symlinks: opts.follow_symlinks && !opts.no_follow_symlinks,
+        skip_errors: opts.skip_errors,
+        extensions: page_extensions(&opts.include_ext, &opts.exclude_ext),
+        exclude: opts.exclude_glob.clone(),
+        sort_key: opts.sort_key,
+        thumbnail_size: opts.html.as_ref().map(|_| THUMBNAIL_SIZE),
+        // The identical pages are found in the cache by content, once the old
+        // version is hashed.
+        cache_by_content: opts.cache_by_content || opts.pair_identical,
+        pdf_dpi: opts.pdf_dpi,
+        mirror: false,
+        invert: false,
+        sample: opts.sample,
+        seed: opts.seed,
+        range: opts.old_range,
+        min_width: opts.min_width,
+        min_height: opts.min_height,
+        mmap: opts.mmap,
+        max_pages: Some(opts.max_pages).filter(|&max| max > 0),
+        ignore_hidden: opts.ignore_hidden || !opts.include_hidden,
+        dimensions: opts.prefer != Prefer::None,
+    };
+    let mut cache = match (&opts.cache, &opts.db) {
+        (Some(path), _) => Cache::load(path, &options)
+            .wrap_err_with(|| format!("load cache {}", path.display()))?,
+        (None, Some(path)) => Cache::load_db(path, &options)
+            .wrap_err_with(|| format!("load cache {}", path.display()))?,
+        (None, None) => Cache::new(&options),
+    };
+    debug!("loaded {} cached hashes", cache.len());
+    // Record the pages as they are hashed, in case the run is interrupted.
+    if let Some(path) = &opts.cache {
+        cache
+            .append_to(path)
+            .wrap_err_with(|| format!("save cache {}", path.display()))?;
     }
-}
 
-#[derive(Clone, Debug)]
-struct HashedImage {
-    filename: OsString,
-    index: usize,
-    hash: ImageHash,
-}
+    // Only the pages being matched need their mirrored and inverted hashes.
+    let mirror_options = HashOptions {
+        mirror: opts.mirror,
+        invert: opts.invert,
+        range: opts.new_range,
+        ..options.clone()
+    };
 
-struct Match {
-    src: HashedImage,
-    dst: Option<(HashedImage, isize)>,
-}
+    let progress = if opts.quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new()
+    };
 
-fn image_distance(img1: &HashedImage, img2: &HashedImage) -> isize {
-    img1.hash.dist(&img2.hash) as isize
-}
+    // Only missing when unused.
+    let distance = opts.distance.unwrap_or_default();
+    // Grouping by quality looks further than the accepted matches, for the
+    // uncertain ones.
+    let search_distance = match opts.accept_distance {
+        None if group_by(&opts) == GroupBy::Quality => {
+            distance.saturating_mul(2)
+        },
+        _ => distance,
+    };
+    let match_options = MatchOptions {
+        position_weight: opts.position_weight,
+        position_window: opts.position_window,
+        assignment: opts.assignment,
+        metric: verified
+            .as_ref()
+            .map_or(opts.metric, |manifest| manifest.metric),
+        prefer: opts.prefer,
+        pair_identical: opts.pair_identical,
+        brute_force: opts.brute_force,
+        accept_distance: opts.accept_distance.unwrap_or(distance),
+        filename_fallback: opts.filename_fallback,
+        flatten: opts.flatten_for_matching,
+        report_duplicates: opts.report_duplicates,
+        align: opts.align,
+        ambiguity_margin: opts.report_ambiguous.map(|margin| {
+            margin.unwrap_or(MatchOptions::DEFAULT_AMBIGUITY_MARGIN)
+        }),
+        ..MatchOptions::new(search_distance)
+    };
+    ensure!(
+        opts.position_weight >= 0.,
+        "position weight cannot be negative"
+    );
+    ensure!(opts.sample > 0, "sampling interval cannot be null");
+    ensure!(
+        !opts.changes_only || opts.format == Format::Text,
+        "only the text report can leave every match out"
+    );
+    ensure!(
+        opts.seed.is_none() || opts.sample > 1,
+        "--seed only applies when sampling, with --sample"
+    );
+    ensure!(
+        opts.preblur.is_finite() && opts.preblur >= 0.,
+        "blur cannot be negative"
+    );
+    ensure!(
+        match_options
+            .ambiguity_margin
+            .map_or(true, |margin| margin >= 0.),
+        "ambiguity margin cannot be negative"
+    );
+    ensure!(
+        !opts.report_duplicates || opts.assignment == Assignment::Greedy,
+        "duplicates can only be reported with the greedy assignment"
+    );
+    ensure!(
+        opts.prefer == Prefer::None || opts.assignment == Assignment::Greedy,
+        "a page can only be preferred with the greedy assignment"
+    );
+    ensure!(
+        !opts.interactive
+            || opts.old.iter().chain(&opts.new).all(|path| path != STDIN),
+        "the matches cannot be reviewed when reading pages from the standard \
+         input"
+    );
+    ensure!(
+        !opts.stream
+            || (matches!(opts.format, Format::Text | Format::JsonLines)
+                && opts.assignment == Assignment::Greedy),
+        "only text and JSON lines reports (and scripts), with the greedy \
+         assignment, can be streamed"
+    );
+    ensure!(
+        !opts.summary || opts.format != Format::Csv,
+        "the summary cannot be included in CSV reports"
+    );
+    ensure!(
+        !opts.detect_spreads || opts.format != Format::Csv,
+        "the split and merged pages cannot be included in CSV reports"
+    );
+    ensure!(
+        !opts.detect_spreads
+            || opts.old.iter().chain(&opts.new).all(|path| path != STDIN),
+        "the split and merged pages cannot be detected when reading pages \
+         from the standard input"
+    );
+    ensure!(
+        opts.new.len() <= 1
+            || !(opts.stream
+                || !opts.sweep.is_empty()
+                || opts.interactive
+                || opts.html.is_some()
+                || opts.extract_new.is_some()
+                || opts.extract_missing.is_some()
+                || opts.dump_hashes.is_some()
+                || opts.manifest.is_some()
+                || opts.verify_manifest.is_some()),
+        "streaming, sweeping, reviewing, extracting pages, dumping hashes, \
+         manifests and HTML reports are only available with a single new \
+         version"
+    );
+    ensure!(
+        opts.new.len() <= 1 || opts.new_manifest.is_none(),
+        "a manifest can only be given for a single new version"
+    );
+    ensure!(
+        opts.new.len() <= 1 || opts.format != Format::Csv,
+        "CSV reports are only available with a single new version"
+    );
+    ensure!(
+        opts.old
+            .iter()
+            .chain(&opts.new)
+            .chain(&opts.version)
+            .filter(|path| path.as_os_str() == STDIN)
+            .count()
+            <= 1,
+        "only one version can be read from the standard input"
+    );
+    ensure!(
+        opts.old_manifest.is_none()
+            || opts.old.iter().all(|path| path != STDIN),
+        "a manifest cannot be given for pages read from the standard input"
+    );
+    ensure!(
+        opts.new_manifest.is_none()
+            || opts.new.iter().all(|path| path != STDIN),
+        "a manifest cannot be given for pages read from the standard input"
+    );
+
+    let timings = opts.timings.map(|count| count.unwrap_or(DEFAULT_TIMINGS));
+    let watch_interval = opts
+        .watch
+        .map(|interval| interval.unwrap_or(DEFAULT_WATCH_INTERVAL));
+    if let Some(interval) = watch_interval {
+        ensure!(interval > 0, "the watch interval cannot be null");
+        ensure!(
+            opts.format == Format::Text,
+            "only a text report can be written when watching"
+        );
+        ensure!(
+            matches!(&opts.new[..], [path] if path.is_dir()),
+            "only a single directory can be watched"
+        );
+    }
+    let shown = match opts.only_changed {
+        _ if opts.changes_only => Shown::NewOnly,
+        Some(distance) => Shown::Changed(distance.unwrap_or_default()),
+        None => Shown::All,
+    };
+    // A report written to a file is only colored when asked.
+    let color = !opts.no_color
+        && match opts.output {
+            Some(_) => opts.color == ColorChoice::Always,
+            None => opts.color.enabled(),
+        };
+    let old_manifest = opts.old_manifest.as_deref().map(read_manifest);
+    let old_manifest = old_manifest.transpose()?;
+    let new_manifest = opts.new_manifest.as_deref().map(read_manifest);
+    let new_manifest = new_manifest.transpose()?;
+
+    if opts.list_only {
+        let mut out = open_output(&opts)?;
+        let paths = opts.old.iter().chain(&opts.new).chain(&opts.version);
+        for (i, path) in paths.enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            let (root, pages) = if path.as_os_str() == STDIN {
+                (PathBuf::new(), read_page_list()?)
+            } else {
+                let pages = list_images(path, &options)
+                    .wrap_err_with(|| format!("listing {}", path.display()))?;
+                (path.to_owned(), pages)
+            };
+            for (index, page) in pages.iter().enumerate() {
+                writeln!(out, "{}\t{}", index, root.join(page).display())?;
+            }
+        }
+        out.flush().wrap_err("write report")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !opts.version.is_empty() {
+        let mut roots = Vec::with_capacity(opts.version.len());
+        let mut versions = Vec::with_capacity(opts.version.len());
+        for path in &opts.version {
+            let (root, pages) = hash_version(
+                path,
+                None,
+                &mirror_options,
+                &mut cache,
+                &progress,
+                timings,
+            )?;
+            roots.push(root);
+            versions.push(pages.images);
+        }
+        save_cache(&opts, &cache)?;
+
+        let rows = align_versions(versions, &match_options);
+        let mut out = open_output(&opts)?;
+        render_versions(&mut out, opts.format, &roots, &rows)
+            .wrap_err("render versions")?;
+
+        let incomplete = rows.iter().flatten().any(Option::is_none);
+        out.flush().wrap_err("write report")?;
+        return Ok(ExitCode::from(if opts.strict && incomplete {
+            EXIT_MISSING
+        } else {
+            0
+        }));
+    }
+
+    let mut loaded = match &opts.load_hashes {
+        Some(path) => {
+            let content = fs::read_to_string(path)
+                .wrap_err_with(|| format!("read {}", path.display()))?;
+            let (old, new) = read_hashes(&content, options.config.bits())
+                .wrap_err_with(|| {
+                    format!("load hashes from {}", path.display())
+                })?;
+            info!(
+                "loaded {} old and {} new hashes from {}",
+                old.len(),
+                new.len(),
+                path.display()
+            );
+            Some((old, new))
+        },
+        None => None,
+    };
 
-fn main() -> Result<()> {
-    let opts = Opts::from_args();
     // Load and hash pages from the "old" version.
-    let old = hash_images(&opts.old)
-        .wrap_err_with(|| format!("hashing {}", opts.old.display()))?;
+    let (old_root, old) = match &mut loaded {
+        Some((old, _)) => (PathBuf::new(), loaded_pages(old)),
+        None => {
+            let old_path = opts.old.as_ref().expect("old version is required");
+            let hashing = Instant::now();
+            let old = hash_version(
+                old_path,
+                old_manifest.as_deref(),
+                &options,
+                &mut cache,
+                &progress,
+                timings,
+            )?;
+            phases.hash_old = hashing.elapsed();
+            old
+        },
+    };
+
+    if opts.dedup {
+        save_cache(&opts, &cache)?;
+
+        let duplicates = find_duplicates(old.images, distance);
+        let mut out = open_output(&opts)?;
+        render_duplicates(&mut out, opts.format, &old_root, &duplicates)
+            .wrap_err("render duplicates")?;
+        out.flush().wrap_err("write report")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(interval) = watch_interval {
+        save_cache(&opts, &cache)?;
+        let report = Report {
+            old: &old_root,
+            new: &opts.new[0],
+            mapping: &[],
+            missing: &[],
+            skipped: &old.skipped,
+            spreads: &[],
+            suggestions: &[],
+            cost: None,
+            hash_bits: options.config.bits(),
+            summary: None,
+            color,
+            changes_only: opts.changes_only,
+        };
+        watch(
+            &mut open_output(&opts)?,
+            old.images,
+            &mirror_options,
+            &match_options,
+            Duration::from_secs(interval),
+            &report,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if opts.new.len() > 1 {
+        let mut out = open_output(&opts)?;
+        let (mut missing, mut added) = (false, false);
+        // Every new version is matched against the same index.
+        let index = PageIndex::new(old.images.clone(), &match_options);
+        for (i, path) in opts.new.iter().enumerate() {
+            let hashing = Instant::now();
+            let (new_root, new) = hash_version(
+                path,
+                None,
+                &mirror_options,
+                &mut cache,
+                &progress,
+                timings,
+            )?;
+            phases.hash_new += hashing.elapsed();
+            save_cache(&opts, &cache)?;
+
+            let report = match_pages_indexed(
+                &index,
+                new.images,
+                &match_options,
+                &ProgressBar::hidden(),
+            );
+            phases.add_matching(&report);
+            let changes = opts.diff.then(|| diff_pages(&report));
+            let spreads = if opts.detect_spreads {
+                detect_spreads(
+                    &report, &old_root, &new_root, &options, distance,
+                )
+                .wrap_err("detect split and merged pages")?
+            } else {
+                Vec::new()
+            };
+            let skipped = old
+                .skipped
+                .iter()
+                .cloned()
+                .chain(new.skipped)
+                .collect::<Vec<_>>();
+            missing |= !report.missing.is_empty();
+            added |= report.mapping.iter().any(|m| m.dst.is_none());
+
+            let report = Report {
+                old: &old_root,
+                new: &new_root,
+                mapping: &shown_matches(&report.mapping, shown),
+                missing: &report.missing,
+                skipped: &skipped,
+                spreads: &spreads,
+                suggestions: &report.suggest_distances(&match_options),
+                cost: (opts.assignment == Assignment::Optimal)
+                    .then_some(report.cost),
+                hash_bits: options.config.bits(),
+                summary: opts
+                    .summary
+                    .then(|| Summary::new(&report.mapping, &report.missing)),
+                color,
+                changes_only: opts.changes_only,
+            };
+            // The JSON reports are on a single line, and carry the paths of
+            // the pages.
+            if i > 0 && (opts.script.is_some() || opts.format == Format::Text) {
+                writeln!(out)?;
+            }
+            if opts.script.is_some() {
+                writeln!(out, "# NEW VERSION: {}", path.display())?;
+            } else if opts.format == Format::Text {
+                writeln!(out, "NEW VERSION: {}", path.display())?;
+            }
+            write_report(&mut out, &opts, &report, changes.as_deref())
+                .wrap_err_with(|| {
+                    format!("render report of {}", path.display())
+                })?;
+        }
+        phases.log(start);
+        out.flush().wrap_err("write report")?;
+        return Ok(exit_status(opts.strict, missing, added));
+    }
+
     // Load and hash pages from the "new" version.
-    let new = hash_images(&opts.new)
-        .wrap_err_with(|| format!("hashing {}", opts.new.display()))?;
+    let (new_root, new) = match &mut loaded {
+        Some((_, new)) => (PathBuf::new(), loaded_pages(new)),
+        None => {
+            let new_path = opts.new.first().expect("new version is required");
+            let hashing = Instant::now();
+            let new = hash_version(
+                new_path,
+                new_manifest.as_deref(),
+                &mirror_options,
+                &mut cache,
+                &progress,
+                timings,
+            )?;
+            phases.hash_new = hashing.elapsed();
+            new
+        },
+    };
+    save_cache(&opts, &cache)?;
 
-    // Index the pages from the "old" version, using BK-Tree for quick lookup.
-    let mut hashes = BkTree::new(image_distance);
-    hashes.insert_all(old);
+    if let Some(path) = &opts.dump_hashes {
+        let mut file = BufWriter::new(
+            File::create(path)
+                .wrap_err_with(|| format!("create {}", path.display()))?,
+        );
+        write_hashes(&mut file, &[("old", &old.images), ("new", &new.images)])
+            .and_then(|_| file.flush().map_err(Into::into))
+            .wrap_err_with(|| format!("write {}", path.display()))?;
+    }
 
-    // Keep track of the pages presents in the "old" version but missing from
-    // the "new" one.
-    let mut missing = hashes
-        .iter()
-        .map(|image| &image.filename)
-        .collect::<HashSet<_>>();
+    if opts.histogram {
+        let distances =
+            best_distances(&old.images, &new.images, &match_options);
+        print_histogram(&distances, opts.distance);
+    }
 
-    // For each page of the "new" version, try to find a match in the "old" one.
-    let mapping = new
+    if let [min, max] = opts.sweep[..] {
+        ensure!(min <= max, "the sweep range is empty");
+        let sweep = (min..=max)
+            .map(|distance| {
+                let options = MatchOptions {
+                    distance,
+                    accept_distance: distance,
+                    ..match_options.clone()
+                };
+                let report = match_pages(
+                    old.images.clone(),
+                    new.images.clone(),
+                    &options,
+                );
+                (distance, Summary::new(&report.mapping, &report.missing))
+            })
+            .collect::<Vec<_>>();
+        let mut out = open_output(&opts)?;
+        render_sweep(&mut out, opts.format, &sweep).wrap_err("render sweep")?;
+        out.flush().wrap_err("write report")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let skipped = old
+        .skipped
         .into_iter()
-        .map(|image| {
-            let matches = hashes.find(image.clone(), opts.distance.into());
-            match matches
-                .into_iter()
-                // Only keep matching images that have no match yet.
-                .filter(|(image, _)| missing.contains(&image.filename))
-                // Order the match by distance first, then by "page number".
-                //
-                // i.e. two release of the same book should have the same page
-                // in the same order (barring 1-2 missing pages or page
-                // swapping), so a closer match in term of "page number" is more
-                // likely to be the right one, rather than a match at the
-                // opposite side of the book where it's likely a false positive…
-                .min_by_key(|(img, dist)| {
-                    *dist
-                        + (img.index as isize - image.index as isize).abs() / 5
-                }) {
-                // Cool, we got a match, remove from missing set and pair the
-                // two page together for the final report.
-                Some((matching, distance)) => {
-                    missing.remove(&matching.filename);
-                    Match {
-                        src: image,
-                        dst: Some((matching.clone(), distance)),
-                    }
+        .chain(new.skipped)
+        .collect::<Vec<_>>();
+    if opts.stream {
+        let mut out = open_output(&opts)?;
+        let report = Report {
+            old: &old_root,
+            new: &new_root,
+            mapping: &[],
+            missing: &[],
+            skipped: &skipped,
+            spreads: &[],
+            suggestions: &[],
+            cost: None,
+            hash_bits: options.config.bits(),
+            summary: None,
+            color,
+            changes_only: opts.changes_only,
+        };
+        let mut added = false;
+        let matching = Instant::now();
+        let mut matches =
+            stream_matches(old.images, new.images, &match_options);
+        match (opts.script, opts.format) {
+            (Some(shell), _) => write_script_header(&mut out, shell),
+            (None, Format::JsonLines) => Ok(()),
+            (None, _) => write_text_header(&mut out, &report),
+        }
+        .wrap_err("render report")?;
+        for m in matches.by_ref() {
+            added |= m.dst.is_none();
+            if !shown.includes(&m) {
+                continue;
+            }
+            match (opts.script, opts.format) {
+                (Some(shell), _) => {
+                    write_script_match(&mut out, shell, &report, &m)
                 },
-                // No match, the "new" release have an extra page (or the "old"
-                // release was incomplete)
-                None => {
-                    Match {
-                        src: image,
-                        dst: None,
-                    }
+                (None, Format::JsonLines) => {
+                    write_json_line(&mut out, &report, &m)
                 },
+                (None, _) => write_text_match(&mut out, &report, &m),
             }
-        })
-        .collect::<Vec<_>>();
+            .wrap_err("render report")?;
+        }
 
-    // Print the final report.
-    //
-    // TODO: find a clearer way to expose this, currently it's very noisy and
-    // need manual scrutiny…
-    println!("PAGE MAPPING:");
-    for m in mapping {
-        match m.dst {
-            Some((image, distance)) => {
-                println!(
-                    "\t{} MATCH {} (DISTANCE: {})",
-                    opts.new.join(m.src.filename).display(),
-                    opts.old.join(image.filename).display(),
-                    distance
-                )
+        // The candidates are looked up as the pages are matched.
+        phases.indexing = matches.indexing();
+        // The streamed matches are greedy: their cost is not reported.
+        let (missing, _) = matches.finish();
+        // The time spent writing the matches as they come is included.
+        phases.matching = matching.elapsed().saturating_sub(phases.indexing);
+        let report = Report {
+            missing: &missing,
+            ..report
+        };
+        match (opts.script, opts.format) {
+            (Some(_), _) => write_script_footer(&mut out, &report),
+            (None, Format::JsonLines) => {
+                write_json_lines_footer(&mut out, &report)
             },
-            None => {
-                println!(
-                    "\t{} (NEW PAGE)",
-                    opts.new.join(m.src.filename).display()
-                )
+            (None, _) => write_text_footer(&mut out, &report),
+        }
+        .wrap_err("render report")?;
+        phases.log(start);
+        out.flush().wrap_err("write report")?;
+        return Ok(exit_status(opts.strict, !missing.is_empty(), added));
+    }
+
+    let mut report = match_pages_with_progress(
+        old.images,
+        new.images,
+        &match_options,
+        &progress,
+    );
+    phases.add_matching(&report);
+    if opts.interactive {
+        review_matches(&mut report, &match_options, &old_root, &new_root)
+            .wrap_err("review matches")?;
+    }
+    let changes = opts.diff.then(|| diff_pages(&report));
+    let spreads = if opts.detect_spreads {
+        detect_spreads(&report, &old_root, &new_root, &options, distance)
+            .wrap_err("detect split and merged pages")?
+    } else {
+        Vec::new()
+    };
+    if let Some(path) = &opts.manifest {
+        Manifest::new(&report, options.config, match_options.metric)
+            .save(path)?;
+        info!("saved the page mapping to {}", path.display());
+    }
+    let mismatches = verified
+        .as_ref()
+        .map(|manifest| manifest.verify(&report))
+        .unwrap_or_default();
+
+    // Print the final report.
+    let mut out = open_output(&opts)?;
+    let report = Report {
+        old: &old_root,
+        new: &new_root,
+        mapping: &shown_matches(&report.mapping, shown),
+        missing: &report.missing,
+        skipped: &skipped,
+        spreads: &spreads,
+        suggestions: &report.suggest_distances(&match_options),
+        cost: (opts.assignment == Assignment::Optimal).then_some(report.cost),
+        hash_bits: options.config.bits(),
+        summary: opts
+            .summary
+            .then(|| Summary::new(&report.mapping, &report.missing)),
+        color,
+        changes_only: opts.changes_only,
+    };
+    write_report(&mut out, &opts, &report, changes.as_deref())
+        .and_then(|_| out.flush().map_err(Into::into))
+        .wrap_err("render report")?;
+
+    if let Some(path) = &opts.html {
+        let mut file = BufWriter::new(
+            File::create(path)
+                .wrap_err_with(|| format!("create {}", path.display()))?,
+        );
+        render_html(&mut file, &report)
+            .and_then(|_| file.flush().map_err(Into::into))
+            .wrap_err_with(|| format!("write {}", path.display()))?;
+    }
+
+    if let Some(directory) = &opts.extract_new {
+        let new = report
+            .mapping
+            .iter()
+            .filter(|m| m.dst.is_none())
+            .map(|m| &m.src)
+            .collect::<Vec<_>>();
+        let count = extract_pages(&new_root, &new, &options, directory)
+            .wrap_err("extract new pages")?;
+        info!("copied {} new pages to {}", count, directory.display());
+    }
+    if let Some(directory) = &opts.extract_missing {
+        let missing = report.missing.iter().collect::<Vec<_>>();
+        let count = extract_pages(&old_root, &missing, &options, directory)
+            .wrap_err("extract missing pages")?;
+        info!("copied {} missing pages to {}", count, directory.display());
+    }
+
+    phases.log(start);
+    if let Some(path) = &opts.verify_manifest {
+        for mismatch in &mismatches {
+            warn!("{}", mismatch);
+        }
+        ensure!(
+            mismatches.is_empty(),
+            "{} pages no longer match as recorded in {}",
+            mismatches.len(),
+            path.display()
+        );
+        info!("the pages still match as recorded in {}", path.display());
+    }
+    Ok(exit_status(
+        opts.strict,
+        !report.missing.is_empty(),
+        report.mapping.iter().any(|m| m.dst.is_none()),
+    ))
+}
+
+/// Matches shown in the report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shown {
+    /// Every match.
+    All,
+    /// Only the matches that changed, beyond the distance (see
+    /// [`is_changed`]).
+    Changed(u8),
+    /// Only the new pages, without a match.
+    NewOnly,
+}
+
+impl Shown {
+    /// Check if the match is shown in the report.
+    fn includes(self, m: &Match) -> bool {
+        match self {
+            Self::All => true,
+            Self::Changed(distance) => is_changed(m, distance),
+            Self::NewOnly => m.dst.is_none(),
+        }
+    }
+}
+
+/// Return the matches to report: all of them, or only some (see [`Shown`]).
+fn shown_matches(mapping: &[Match], shown: Shown) -> Cow<'_, [Match]> {
+    match shown {
+        Shown::All => Cow::Borrowed(mapping),
+        _ => Cow::Owned(
+            mapping
+                .iter()
+                .filter(|m| shown.includes(m))
+                .cloned()
+                .collect(),
+        ),
+    }
+}
+
+/// Check if a page changed between the versions: it is new, or its match is
+/// above `distance` or flagged in any way.
+fn is_changed(m: &Match, distance: u8) -> bool {
+    match &m.dst {
+        Some((_, dist)) => {
+            *dist > distance.into()
+                || m.uncertain
+                || m.filename_match
+                || m.mirrored
+                || m.inverted
+                || m.duplicate
+                || m.runner_up.is_some()
+        },
+        None => true,
+    }
+}
+
+/// Time spent in each phase of a run.
+#[derive(Debug, Default)]
+struct Phases {
+    hash_old: Duration,
+    hash_new: Duration,
+    indexing: Duration,
+    matching: Duration,
+}
+
+impl Phases {
+    /// Account for the time spent matching the pages.
+    fn add_matching(&mut self, report: &MatchReport) {
+        self.indexing += report.indexing;
+        self.matching += report.duration.saturating_sub(report.indexing);
+    }
+
+    /// Log the time spent in each phase, and in total since `start`.
+    fn log(&self, start: Instant) {
+        info!(
+            "done in {:.2?} (hashing old: {:.2?}, hashing new: {:.2?}, \
+             indexing: {:.2?}, matching: {:.2?})",
+            start.elapsed(),
+            self.hash_old,
+            self.hash_new,
+            self.indexing,
+            self.matching
+        );
+    }
+}
+
+/// Write the final report, as requested.
+fn write_report(
+    out: &mut impl Write,
+    opts: &Opts,
+    report: &Report<'_>,
+    changes: Option<&[PageChange]>,
+) -> Result<()> {
+    match (opts.script, changes) {
+        (Some(shell), _) => render_script(out, shell, report),
+        (None, Some(changes)) => render_diff(out, opts.format, report, changes),
+        (None, None) => match group_by(opts) {
+            GroupBy::New => render_report(out, opts.format, report),
+            GroupBy::Old => render_by_old(out, opts.format, report),
+            GroupBy::Quality => render_by_quality(out, opts.format, report),
+        },
+    }
+}
+
+/// Return how the final report is keyed.
+fn group_by(opts: &Opts) -> GroupBy {
+    match opts.group_by {
+        Some(group_by) => group_by,
+        None if opts.grouped => GroupBy::Quality,
+        None => GroupBy::New,
+    }
+}
+
+/// Compute the exit status, depending on whether pages are missing from the
+/// new version or added to it.
+fn exit_status(strict: bool, missing: bool, added: bool) -> ExitCode {
+    let mut status = 0;
+    if strict {
+        if missing {
+            status |= EXIT_MISSING;
+        }
+        if added {
+            status |= EXIT_NEW;
+        }
+    }
+    ExitCode::from(status)
+}
+
+/// Hash the pages of a version, and record them in the cache.
+///
+/// Returns the directory the pages are relative to, with the pages.
+fn hash_version(
+    path: &Path,
+    manifest: Option<&[PathBuf]>,
+    options: &HashOptions,
+    cache: &mut Cache,
+    progress: &ProgressBar,
+    timings: Option<usize>,
+) -> Result<(PathBuf, HashedPages)> {
+    let (root, pages) = if path.as_os_str() == STDIN {
+        let paths = read_page_list()?;
+        let pages = hash_page_list(paths, options, cache, progress)
+            .wrap_err("hashing listed pages")?;
+        (PathBuf::new(), pages)
+    } else if let Some(manifest) = manifest {
+        let pages = hash_manifest(path, manifest, options, cache, progress)
+            .wrap_err_with(|| format!("hashing {}", path.display()))?;
+        (path.to_owned(), pages)
+    } else {
+        let pages = hash_images(path, options, cache, progress)
+            .wrap_err_with(|| format!("hashing {}", path.display()))?;
+        (path.to_owned(), pages)
+    };
+    cache.update(&root, &pages.images)?;
+    warn_skipped(&pages.skipped);
+    if let Some(count) = timings {
+        print_timings(path, &pages.timings, count);
+    }
+
+    Ok((root, pages))
+}
+
+/// Print the `count` pages that took the longest to hash, on the standard
+/// error.
+fn print_timings(path: &Path, timings: &[PageTiming], count: usize) {
+    let mut timings = timings.iter().collect::<Vec<_>>();
+    timings.sort_by_key(|timing| Reverse(timing.duration));
+
+    eprintln!("SLOWEST PAGES OF {}:", path.display());
+    for timing in timings.into_iter().take(count) {
+        eprintln!("\t{:>10.2?}  {}", timing.duration, timing.path.display());
+    }
+}
+
+/// Width of the longest bar of the histogram, in characters.
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Print the histogram of the distances of the closest pages, on the standard
+/// error, with the maximum distance (if any) flagged.
+fn print_histogram(distances: &[Option<isize>], max_distance: Option<u8>) {
+    let mut counts = Vec::new();
+    let mut unmatched = 0;
+    for distance in distances {
+        match distance {
+            Some(distance) => {
+                let distance = *distance as usize;
+                if counts.len() <= distance {
+                    counts.resize(distance + 1, 0);
+                }
+                counts[distance] += 1;
             },
+            None => unmatched += 1,
         }
     }
+    let largest = counts.iter().copied().max().unwrap_or_default().max(1);
+
+    eprintln!("DISTANCE HISTOGRAM:");
+    for (distance, &count) in counts.iter().enumerate() {
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(largest));
+        let flag = if max_distance.map(usize::from) == Some(distance) {
+            " <- MAX DISTANCE"
+        } else {
+            ""
+        };
+        let line = format!("\t{:>4} {:>6} {}{}", distance, count, bar, flag);
+        eprintln!("{}", line.trim_end());
+    }
+    if unmatched > 0 {
+        eprintln!("\tNO CANDIDATE: {}", unmatched);
+    }
+}
+
+/// Take the pages loaded with `--load-hashes`.
+fn loaded_pages(images: &mut Vec<HashedImage>) -> HashedPages {
+    HashedPages {
+        images: mem::take(images),
+        ..HashedPages::default()
+    }
+}
+
+/// Read the paths of the pages listed in a manifest file, one per line.
+fn read_manifest(path: &Path) -> Result<Vec<PathBuf>> {
+    let content = fs::read_to_string(path)
+        .wrap_err_with(|| format!("read manifest {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Read the paths of the pages from the standard input, one per line.
+fn read_page_list() -> Result<Vec<PathBuf>> {
+    io::stdin()
+        .lock()
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.is_empty()))
+        .map(|line| line.map(PathBuf::from))
+        .collect::<io::Result<Vec<_>>>()
+        .wrap_err("read page list")
+}
+
+/// Open the file where the report is written, or the standard output.
+fn open_output(opts: &Opts) -> Result<Box<dyn Write>> {
+    Ok(match &opts.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path)
+                .wrap_err_with(|| format!("create {}", path.display()))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+/// Write the cache back, if enabled.
+fn save_cache(opts: &Opts, cache: &Cache) -> Result<()> {
+    match (&opts.cache, &opts.db) {
+        (Some(path), _) => cache
+            .save(path)
+            .wrap_err_with(|| format!("save cache {}", path.display())),
+        (None, Some(path)) => cache
+            .save_db()
+            .wrap_err_with(|| format!("save cache {}", path.display())),
+        (None, None) => Ok(()),
+    }
+}
 
-    if !missing.is_empty() {
-        println!("\nMISSING PAGES");
-        for filename in missing {
-            println!("\t{}", opts.old.join(filename).display())
+/// Print a warning for every page skipped because of an error.
+fn warn_skipped(skipped: &[SkippedPage]) {
+    for page in skipped {
+        if page.too_small {
+            debug!("skipped {}: {}", page.path.display(), page.reason);
+        } else {
+            warn!("skipped {}: {}", page.path.display(), page.reason);
         }
     }
-    Ok(())
-}
-
-/// Return a list of page found under the given path.
-fn list_pages(path: &Path) -> Result<Vec<Page>> {
-    fs::read_dir(path)
-        .wrap_err("list pages")?
-        .filter_map(|entry| {
-            entry
-                .wrap_err("access directory entry")
-                .and_then(|entry| {
-                    entry
-                        .metadata()
-                        .wrap_err_with(|| {
-                            format!(
-                                "read metadata for {}",
-                                entry.path().display()
-                            )
-                        })
-                        .map(|metadata| {
-                            metadata.is_file().then(|| {
-                                Page {
-                                    path: entry.path(),
-                                    size: metadata.len() as usize,
-                                }
-                            })
-                        })
-                })
-                .transpose()
-        })
-        .collect::<Result<Vec<_>>>()
-}
-
-/// Hash every image under the given path.
-fn hash_images(path: impl Into<PathBuf>) -> Result<Vec<HashedImage>> {
-    let path = path.into();
-    println!("Hashing pages from {}…", path.display());
-
-    let mut pages = list_pages(&path)?;
-    pages.sort();
-
-    pages
-        .into_par_iter()
-        .enumerate()
-        .try_fold(Vec::new, |mut acc, (index, page)| {
-            // Load the file content in-memory.
-            let filename =
-                page.path.file_name().expect("missing filename").to_owned();
-            let mut file = File::open(&page.path)?;
-            let mut contents = Vec::with_capacity(page.size);
-            file.read_to_end(&mut contents).wrap_err_with(|| {
-                format!("cannot read page {}", page.path.display())
-            })?;
-
-            // Decode the image (guess the format).
-            let image = ImageReader::new(Cursor::new(contents))
-                .with_guessed_format()
-                .wrap_err_with(|| {
-                    format!("identify {}", filename.to_string_lossy())
-                })?
-                .decode()
-                .wrap_err_with(|| {
-                    format!("decode {}", filename.to_string_lossy())
-                })?;
+}
+
+/// Compute the list of accepted page extensions.
+fn page_extensions(include: &[String], exclude: &[String]) -> Vec<String> {
+    let normalize =
+        |extension: &String| extension.trim_start_matches('.').to_lowercase();
+    let exclude = exclude.iter().map(normalize).collect::<Vec<_>>();
 
-            // Initialize the hasher.
-            let hasher = HasherConfig::new()
-                .hash_size(8, 8)
-                .hash_alg(HashAlg::DoubleGradient)
-                .preproc_dct()
-                .to_hasher();
-
-            // Compute the hash and save it for later use.
-            acc.push(HashedImage {
-                filename,
-                index,
-                hash: hasher.hash_image(&image),
-            });
-
-            Ok(acc)
-        })
-        .try_reduce(Vec::new, |mut v1, v2| {
-            v1.extend(v2.into_iter());
-            Ok(v1)
-        })
+    IMAGE_EXTENSIONS
+        .iter()
+        .map(|&extension| extension.to_owned())
+        .chain(include.iter().map(normalize))
+        .filter(|extension| !exclude.contains(extension))
+        .collect()
 }
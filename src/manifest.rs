@@ -0,0 +1,339 @@
+//! Durable record of the page mapping between two versions, as JSON.
+//!
+//! Unlike the reports, a manifest records the hash settings along with the
+//! mapping, so that the same matching can be done again later and checked
+//! against it (e.g. once the pages moved elsewhere).
+use crate::{
+    json::{field, optional_field, parse, push_string, Value},
+    HashConfig,
+    MatchReport,
+    Metric,
+};
+use eyre::{ensure, Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write as _},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Version of the manifest format, bumped on incompatible changes.
+pub const MANIFEST_SCHEMA: u32 = 1;
+
+/// A page of the new version recorded in a manifest, with its match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Path of the page, relative to the root of the new version.
+    pub new: PathBuf,
+    /// Path of the matching page, relative to the root of the old version.
+    ///
+    /// `None` when the page only exists in the new version.
+    pub old: Option<PathBuf>,
+    /// Distance between the pages, when matched.
+    pub distance: Option<isize>,
+    /// Status of the match (see [`Match::status`](crate::Match::status)), or
+    /// `new` when the page has no match.
+    pub status: String,
+}
+
+/// Page mapping between two versions, with the settings it was made with.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    /// Settings the pages were hashed with.
+    pub config: HashConfig,
+    /// Metric the pages were compared with.
+    pub metric: Metric,
+    /// Pages of the new version, in order, with their match if any.
+    pub pages: Vec<ManifestEntry>,
+    /// Pages of the old version that are missing from the new one, relative
+    /// to its root.
+    pub missing: Vec<PathBuf>,
+}
+
+/// Difference between a manifest and a new matching of the versions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The page of the new version is not matched with the recorded page.
+    Changed {
+        /// Path of the page, relative to the root of the new version.
+        page: PathBuf,
+        /// Page of the old version recorded in the manifest, if any.
+        expected: Option<PathBuf>,
+        /// Page of the old version it is now matched with, if any.
+        found: Option<PathBuf>,
+    },
+    /// The page of the new version is not recorded in the manifest.
+    Added(PathBuf),
+    /// The page recorded in the manifest is no longer in the new version.
+    Removed(PathBuf),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let page = |page: &Option<PathBuf>| {
+            page.as_ref().map_or_else(
+                || "nothing".to_owned(),
+                |page| page.display().to_string(),
+            )
+        };
+        match self {
+            Self::Changed {
+                page: new,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} now matches {} instead of {}",
+                new.display(),
+                page(found),
+                page(expected)
+            ),
+            Self::Added(page) => {
+                write!(f, "{} is not in the manifest", page.display())
+            },
+            Self::Removed(page) => {
+                write!(f, "{} is no longer in the new version", page.display())
+            },
+        }
+    }
+}
+
+impl Manifest {
+    /// Record the page mapping of a report, made with the given settings.
+    pub fn new(
+        report: &MatchReport,
+        config: HashConfig,
+        metric: Metric,
+    ) -> Self {
+        let pages = report
+            .mapping
+            .iter()
+            .map(|m| ManifestEntry {
+                new: PathBuf::from(&m.src.path),
+                old: m
+                    .dst
+                    .as_ref()
+                    .map(|(image, _)| PathBuf::from(&image.path)),
+                distance: m.dst.as_ref().map(|&(_, distance)| distance),
+                status: match m.dst {
+                    Some(_) => m.status().to_owned(),
+                    None => "new".to_owned(),
+                },
+            })
+            .collect();
+        let missing = report
+            .missing
+            .iter()
+            .map(|image| PathBuf::from(&image.path))
+            .collect();
+
+        Self {
+            config,
+            metric,
+            pages,
+            missing,
+        }
+    }
+
+    /// Load a manifest written by [`Manifest::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("read {}", path.display()))?;
+        Self::from_json(&content)
+            .wrap_err_with(|| format!("parse {}", path.display()))
+    }
+
+    /// Save the manifest as a JSON file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut json = self.to_json();
+        json.push('\n');
+        fs::write(path, json)
+            .wrap_err_with(|| format!("write {}", path.display()))
+    }
+
+    /// Render the manifest as a JSON document.
+    ///
+    /// The paths are relative to the root of their version, so that the
+    /// versions can be moved.
+    pub fn to_json(&self) -> String {
+        let config = &self.config;
+        let mut json = String::new();
+        write!(
+            json,
+            "{{\"schema\":{},\"hash\":{{\"algorithm\":\"{}\",\"width\":{},\
+             \"height\":{},\"dct\":{},\"trim_borders\":{},\"grayscale\":{},\
+             \"equalize\":{},\"exif_orientation\":{},\"pad_to_square\":{},\
+             \"preblur\":{},\"frame\":{},\"max_dimension\":{}}},\
+             \"metric\":\"{}\",\"pages\":[",
+            MANIFEST_SCHEMA,
+            config.algorithm,
+            config.width,
+            config.height,
+            config.dct,
+            config.trim_borders,
+            config.grayscale,
+            config.equalize,
+            config.exif_orientation,
+            config.pad_to_square,
+            config.preblur.map_or(0., f32::from_bits),
+            config.frame,
+            config.max_dimension.unwrap_or_default(),
+            self.metric
+        )
+        .expect("write to string");
+        for (i, page) in self.pages.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            json.push_str("{\"new\":");
+            push_path(&mut json, Some(&page.new));
+            json.push_str(",\"old\":");
+            push_path(&mut json, page.old.as_deref());
+            match page.distance {
+                Some(distance) => {
+                    write!(json, ",\"distance\":{}", distance)
+                        .expect("write to string");
+                },
+                None => json.push_str(",\"distance\":null"),
+            }
+            json.push_str(",\"status\":");
+            push_string(&mut json, &page.status);
+            json.push('}');
+        }
+        json.push_str("],\"missing\":[");
+        for (i, page) in self.missing.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            push_path(&mut json, Some(page));
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// Parse a manifest rendered by [`Manifest::to_json`].
+    pub fn from_json(content: &str) -> Result<Self> {
+        let manifest = parse(content)?;
+        let schema = field(&manifest, "schema")?.to_u32()?;
+        ensure!(
+            schema == MANIFEST_SCHEMA,
+            "unsupported schema {} (expected {})",
+            schema,
+            MANIFEST_SCHEMA
+        );
+
+        let hash = field(&manifest, "hash")?;
+        let flag = |name| field(hash, name).and_then(Value::to_bool);
+        let config = HashConfig::new(
+            field(hash, "algorithm")?.to_str()?.parse()?,
+            field(hash, "width")?.to_u32()?,
+            field(hash, "height")?.to_u32()?,
+        )?
+        .dct(flag("dct")?)
+        .trim_borders(flag("trim_borders")?)
+        .grayscale(flag("grayscale")?)
+        // Added later on: older manifests don't equalize.
+        .equalize(match optional_field(hash, "equalize")? {
+            Some(value) => value.to_bool()?,
+            None => false,
+        })
+        .exif_orientation(flag("exif_orientation")?)
+        .pad_to_square(flag("pad_to_square")?)
+        .preblur(field(hash, "preblur")?.to_number()?.parse()?)
+        .frame(field(hash, "frame")?.to_u32()?)
+        // Added later on: older manifests don't downscale (0).
+        .max_dimension(match optional_field(hash, "max_dimension")? {
+            Some(value) => value.to_u32()?,
+            None => 0,
+        });
+        let metric = field(&manifest, "metric")?.to_str()?.parse()?;
+
+        let pages = field(&manifest, "pages")?
+            .to_array()?
+            .iter()
+            .enumerate()
+            .map(|(i, page)| {
+                parse_entry(page)
+                    .wrap_err_with(|| format!("invalid page {}", i))
+            })
+            .collect::<Result<_>>()?;
+        let missing = field(&manifest, "missing")?
+            .to_array()?
+            .iter()
+            .map(|page| page.to_str().map(PathBuf::from))
+            .collect::<Result<_>>()
+            .wrap_err("invalid missing page")?;
+
+        Ok(Self {
+            config,
+            metric,
+            pages,
+            missing,
+        })
+    }
+
+    /// Compare the recorded mapping with that of a new matching of the
+    /// versions, returning how they differ.
+    ///
+    /// Only the pairs of pages are compared: the distances may change between
+    /// image decoders, and the statuses with the matching options.
+    pub fn verify(&self, report: &MatchReport) -> Vec<Mismatch> {
+        let expected = self
+            .pages
+            .iter()
+            .map(|page| (page.new.as_path(), page.old.as_deref()))
+            .collect::<HashMap<_, _>>();
+
+        let mut seen = HashSet::new();
+        let mut mismatches = Vec::new();
+        for m in &report.mapping {
+            let page = Path::new(&m.src.path);
+            let found = m.dst.as_ref().map(|(image, _)| Path::new(&image.path));
+            seen.insert(page);
+            match expected.get(page) {
+                Some(&expected) if expected == found => (),
+                Some(&expected) => mismatches.push(Mismatch::Changed {
+                    page: page.to_owned(),
+                    expected: expected.map(Path::to_owned),
+                    found: found.map(Path::to_owned),
+                }),
+                None => mismatches.push(Mismatch::Added(page.to_owned())),
+            }
+        }
+        mismatches.extend(
+            self.pages
+                .iter()
+                .filter(|page| !seen.contains(page.new.as_path()))
+                .map(|page| Mismatch::Removed(page.new.clone())),
+        );
+
+        mismatches
+    }
+}
+
+/// Parse a page of the manifest.
+fn parse_entry(page: &Value) -> Result<ManifestEntry> {
+    let optional = |name| match field(page, name)? {
+        Value::Null => Ok(None),
+        value => value.to_str().map(Some),
+    };
+    let distance = match field(page, "distance")? {
+        Value::Null => None,
+        value => Some(value.to_number()?.parse().wrap_err("invalid distance")?),
+    };
+
+    Ok(ManifestEntry {
+        new: PathBuf::from(field(page, "new")?.to_str()?),
+        old: optional("old")?.map(PathBuf::from),
+        distance,
+        status: field(page, "status")?.to_str()?.to_owned(),
+    })
+}
+
+/// Append the path as a JSON string, or `null`.
+fn push_path(json: &mut String, path: Option<&Path>) {
+    match path {
+        Some(path) => push_string(json, &path.to_string_lossy()),
+        None => json.push_str("null"),
+    }
+}
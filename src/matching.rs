@@ -0,0 +1,1740 @@
+//! Matching of the pages between two versions.
+use crate::{
+    debug,
+    hash_in_memory,
+    hungarian,
+    info,
+    HashOptions,
+    HashedImage,
+    ProgressBar,
+    Task,
+};
+use bktree::BkTree;
+use eyre::{bail, Report, Result};
+use img_hash::ImageHash;
+use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    cmp,
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    ffi::OsString,
+    fmt,
+    iter,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+    vec,
+};
+
+/// A page of the new version, with its counterpart in the old version.
+#[derive(Clone, Debug)]
+pub struct Match {
+    /// Page from the new version.
+    pub src: HashedImage,
+    /// Matching page from the old version, with its distance.
+    ///
+    /// `None` when the page only exists in the new version.
+    pub dst: Option<(HashedImage, isize)>,
+    /// Whether the match is above the acceptance distance, and should be
+    /// reviewed.
+    pub uncertain: bool,
+    /// Whether the pages were only paired because they share the same
+    /// filename, their hashes being too far apart.
+    pub filename_match: bool,
+    /// Whether the page of the new version matched once mirrored.
+    pub mirrored: bool,
+    /// Whether the page of the new version matched once its colors inverted.
+    pub inverted: bool,
+    /// Whether the page of the old version also matched other pages of the
+    /// new version (see [`MatchOptions::report_duplicates`]).
+    pub duplicate: bool,
+    /// Whether the pages are byte-for-byte identical, and were paired before
+    /// comparing their hashes (see [`MatchOptions::pair_identical`]).
+    pub identical: bool,
+    /// Next best page of the old version, with its distance, when its cost is
+    /// close to that of the match (see [`MatchOptions::ambiguity_margin`]).
+    ///
+    /// The match is then ambiguous.
+    pub runner_up: Option<(HashedImage, isize)>,
+}
+
+impl Match {
+    /// Confidence of the match, from 0 (every bit differs) to 1 (same hash),
+    /// for hashes of `bits` bits (see
+    /// [`HashConfig::bits`](crate::HashConfig::bits)).
+    ///
+    /// `None` when the page only exists in the new version.
+    pub fn confidence(&self, bits: u32) -> Option<f64> {
+        self.dst.as_ref().map(|(_, distance)| {
+            (1. - *distance as f64 / f64::from(bits.max(1))).max(0.)
+        })
+    }
+
+    /// Return the status of the match (`match`, `identical`, `uncertain`,
+    /// etc), when the page has a counterpart.
+    pub fn status(&self) -> &'static str {
+        if self.identical {
+            "identical"
+        } else if self.filename_match {
+            "filename-match"
+        } else if self.duplicate {
+            "duplicate"
+        } else if self.runner_up.is_some() {
+            "ambiguous"
+        } else if self.uncertain {
+            "uncertain"
+        } else {
+            "match"
+        }
+    }
+}
+
+/// Result of the matching between two versions.
+#[derive(Clone, Debug)]
+pub struct MatchReport {
+    /// Pages of the new version, in order, with their match if any.
+    pub mapping: Vec<Match>,
+    /// Pages of the old version that are missing from the new one.
+    pub missing: Vec<HashedImage>,
+    /// Total cost of the matches (see [`MatchCost`]).
+    pub cost: f64,
+    /// Shift of the pages of the new version taken into account, see
+    /// [`MatchOptions::offset`].
+    pub offset: isize,
+    /// Time spent looking the candidates of the pages up: building the
+    /// BK-trees, or the costs of the optimal assignment.
+    pub indexing: Duration,
+    /// Time spent matching the pages, indexing included.
+    pub duration: Duration,
+}
+
+impl MatchReport {
+    /// Undo the match of the `i`-th page of the new version: it becomes a new
+    /// page, and its counterpart is missing (unless it is still matched by
+    /// another page).
+    pub fn reject(&mut self, i: usize, options: &MatchOptions) {
+        let m = &mut self.mapping[i];
+        let dst = m.dst.take();
+        m.uncertain = false;
+        m.filename_match = false;
+        m.mirrored = false;
+        m.inverted = false;
+        m.duplicate = false;
+        m.identical = false;
+        m.runner_up = None;
+
+        if let Some((image, distance)) = dst {
+            let options = MatchOptions {
+                offset: self.offset,
+                ..options.clone()
+            };
+            self.cost -=
+                options.cost(&self.mapping[i].src, &image, distance).total();
+            let claimed = self.mapping.iter().any(|m| {
+                m.dst.as_ref().map(|(dst, _)| &dst.path) == Some(&image.path)
+            });
+            if claimed {
+                flag_duplicates(&mut self.mapping);
+            } else {
+                // The missing pages are kept in order.
+                let at = self
+                    .missing
+                    .binary_search_by_key(&image.index, |image| image.index)
+                    .unwrap_or_else(|at| at);
+                self.missing.insert(at, image);
+            }
+        }
+    }
+
+    /// Estimate which maximum distance would have matched the pages still
+    /// missing, with the new pages left without a match.
+    ///
+    /// Returns each distance above `options.distance` that matches more
+    /// pages, in increasing order, with how many more pages it would match.
+    /// Each missing page counts at the distance of its closest unmatched page,
+    /// up to the number of unmatched pages: the actual matching may still pair
+    /// them differently.
+    pub fn suggest_distances(
+        &self,
+        options: &MatchOptions,
+    ) -> Vec<(u8, usize)> {
+        let new = self
+            .mapping
+            .iter()
+            .filter(|m| m.dst.is_none())
+            .map(|m| &m.src)
+            .collect::<Vec<_>>();
+        let mut nearest = self
+            .missing
+            .par_iter()
+            .filter_map(|image| {
+                new.iter()
+                    .map(|page| variant_distance(page, image, options.metric).0)
+                    .min()
+            })
+            .filter(|&distance| distance > options.distance.into())
+            .filter_map(|distance| u8::try_from(distance).ok())
+            .collect::<Vec<_>>();
+        nearest.sort_unstable();
+
+        let mut suggestions = Vec::<(u8, usize)>::new();
+        for (i, &distance) in nearest.iter().enumerate() {
+            let matched = (i + 1).min(new.len());
+            match suggestions.last_mut() {
+                Some((last, count)) if *last == distance => *count = matched,
+                _ => suggestions.push((distance, matched)),
+            }
+        }
+        // Past the number of unmatched pages, no more pages would match.
+        suggestions.dedup_by_key(|&mut (_, matched)| matched);
+        suggestions
+    }
+}
+
+/// Aggregated statistics of a matching.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    /// Number of pages in the old version.
+    pub old_pages: usize,
+    /// Number of pages in the new version.
+    pub new_pages: usize,
+    /// Number of pages matched between the versions.
+    pub matched: usize,
+    /// Number of pages of the old version missing from the new one.
+    pub missing: usize,
+    /// Number of pages of the new version without a match.
+    pub added: usize,
+    /// Mean distance of the matches, if any.
+    pub mean_distance: Option<f64>,
+    /// Median distance of the matches, if any.
+    pub median_distance: Option<f64>,
+}
+
+impl Summary {
+    /// Compute the statistics of the given matching.
+    pub fn new(mapping: &[Match], missing: &[HashedImage]) -> Self {
+        let mut distances = mapping
+            .iter()
+            .filter_map(|m| m.dst.as_ref().map(|(_, distance)| *distance))
+            .collect::<Vec<_>>();
+        distances.sort_unstable();
+
+        let count = distances.len();
+        let mean_distance = (count > 0)
+            .then(|| distances.iter().sum::<isize>() as f64 / count as f64);
+        let median_distance = (count > 0).then(|| {
+            let middle = count / 2;
+            if count % 2 == 0 {
+                (distances[middle - 1] + distances[middle]) as f64 / 2.
+            } else {
+                distances[middle] as f64
+            }
+        });
+
+        Self {
+            old_pages: count + missing.len(),
+            new_pages: mapping.len(),
+            matched: count,
+            missing: missing.len(),
+            added: mapping.len() - count,
+            mean_distance,
+            median_distance,
+        }
+    }
+}
+
+/// Cost of matching two pages, used to rank the candidates.
+///
+/// Only the hash distance decides whether two pages can match at all, the
+/// position penalty merely breaks the ties between close candidates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchCost {
+    /// Distance between the hashes of the pages.
+    pub hash_dist: isize,
+    /// Difference of position between the pages, weighted by
+    /// [`MatchOptions::position_weight`].
+    pub position_penalty: f64,
+}
+
+impl MatchCost {
+    /// Combine the hash distance and the position penalty.
+    pub fn total(&self) -> f64 {
+        self.hash_dist as f64 + self.position_penalty
+    }
+}
+
+/// Strategy used to pair the pages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Assignment {
+    /// Each page of the new version, in order, takes its best match among the
+    /// pages not matched yet.
+    Greedy,
+    /// Minimize the total cost of the matches, at the expense of speed.
+    Optimal,
+}
+
+impl Assignment {
+    /// Name of every strategy, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &["greedy", "optimal"];
+}
+
+impl FromStr for Assignment {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "greedy" => Self::Greedy,
+            "optimal" => Self::Optimal,
+            _ => bail!("invalid assignment {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Greedy => "greedy",
+            Self::Optimal => "optimal",
+        })
+    }
+}
+
+/// Distance between two pages.
+///
+/// The pages of the old version are indexed in a BK-tree, whose search is
+/// only exact when the distance is a metric: symmetric, and satisfying the
+/// triangle inequality. Every metric here is; one that isn't must compare
+/// every pair of pages instead (see [`MatchOptions::brute_force`]).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Metric {
+    /// Number of differing bits of the hashes.
+    Hamming,
+    /// Hamming distance, plus the number of times the file size doubles from
+    /// one page to the other (rounded to the powers of two), so that pages of
+    /// similar sizes are favored.
+    HammingPlusSize,
+}
+
+impl Metric {
+    /// Name of every metric, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] =
+        &["hamming", "hamming-plus-size"];
+
+    /// Compute the distance between two pages.
+    pub fn distance(self, img1: &HashedImage, img2: &HashedImage) -> isize {
+        self.hash_distance(&img1.hash, img1, img2)
+    }
+
+    /// Compute the distance between two pages, the first one having the given
+    /// hash (e.g. its mirrored hash).
+    fn hash_distance(
+        self,
+        hash: &ImageHash,
+        img1: &HashedImage,
+        img2: &HashedImage,
+    ) -> isize {
+        let hamming = hash.dist(&img2.hash) as isize;
+        match self {
+            Self::Hamming => hamming,
+            Self::HammingPlusSize => {
+                // Number of bits of the size, i.e. its rounded logarithm.
+                let magnitude = |image: &HashedImage| {
+                    (usize::BITS - image.size.leading_zeros()) as isize
+                };
+                hamming + (magnitude(img1) - magnitude(img2)).abs()
+            },
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "hamming" => Self::Hamming,
+            "hamming-plus-size" => Self::HammingPlusSize,
+            _ => bail!("invalid metric {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hamming => "hamming",
+            Self::HammingPlusSize => "hamming-plus-size",
+        })
+    }
+}
+
+/// Which page to prefer among the candidates of equal cost.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Prefer {
+    /// The one with the most pixels (e.g. the full page over its thumbnail).
+    Larger,
+    /// The one with the fewest pixels.
+    Smaller,
+    /// The first one, in order.
+    None,
+}
+
+impl Prefer {
+    /// Name of every preference, as accepted by `from_str`.
+    pub const NAMES: &'static [&'static str] = &["larger", "smaller", "none"];
+
+    /// Compare two candidates, the preferred one first.
+    ///
+    /// The pages of unknown dimensions come last.
+    fn compare(self, a: &HashedImage, b: &HashedImage) -> cmp::Ordering {
+        let pixels = |image: &HashedImage| {
+            image
+                .dimensions
+                .map(|(width, height)| u64::from(width) * u64::from(height))
+        };
+        match self {
+            Self::Larger => pixels(b).cmp(&pixels(a)),
+            Self::Smaller => (pixels(a).is_none(), pixels(a))
+                .cmp(&(pixels(b).is_none(), pixels(b))),
+            Self::None => cmp::Ordering::Equal,
+        }
+    }
+}
+
+impl FromStr for Prefer {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "larger" => Self::Larger,
+            "smaller" => Self::Smaller,
+            "none" => Self::None,
+            _ => bail!("invalid preference {}", s),
+        })
+    }
+}
+
+impl fmt::Display for Prefer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Larger => "larger",
+            Self::Smaller => "smaller",
+            Self::None => "none",
+        })
+    }
+}
+
+/// Options controlling how the pages are matched.
+#[derive(Clone, Debug)]
+pub struct MatchOptions {
+    /// Maximum distance between two matching pages.
+    pub distance: u8,
+    /// Maximum distance of a confirmed match.
+    ///
+    /// Matches above this distance (but within `distance`) are kept, but
+    /// flagged as uncertain.
+    pub accept_distance: u8,
+    /// How much the difference of position between two pages weighs against
+    /// their hash distance, when choosing between several candidates.
+    ///
+    /// A weight of 0 ignores the position entirely.
+    pub position_weight: f64,
+    /// Largest difference of position that is penalized: pages further apart
+    /// cost as much as pages this far apart, so that the pages moved far
+    /// away (e.g. reordered chapters) can still match.
+    pub position_window: Option<usize>,
+    /// Strategy used to pair the pages.
+    ///
+    /// The optimal assignment pairs each page of the old version at most once:
+    /// when [`report_duplicates`](Self::report_duplicates) is set, the greedy
+    /// assignment is used instead.
+    pub assignment: Assignment,
+    /// Distance between the pages.
+    pub metric: Metric,
+    /// Page to prefer among the candidates of equal cost, with the greedy
+    /// assignment.
+    ///
+    /// Only the pages whose dimensions are known can be told apart (see
+    /// [`HashOptions::dimensions`]).
+    pub prefer: Prefer,
+    /// Pair the pages with the same content first, from their digest, then
+    /// match the other ones.
+    ///
+    /// Only the pages whose content was read have a digest.
+    pub pair_identical: bool,
+    /// Compare every pair of pages instead of looking them up in a BK-tree,
+    /// which is slower but works with any distance (the optimal assignment
+    /// always does).
+    pub brute_force: bool,
+    /// Pair the remaining pages that share the same filename, whatever their
+    /// distance.
+    pub filename_fallback: bool,
+    /// Compare the filenames without their directories, for the versions that
+    /// are organized differently (e.g. in chapters and flat).
+    pub flatten: bool,
+    /// Let several pages of the new version match the same page of the old
+    /// version, and flag these matches as duplicates.
+    ///
+    /// Each page then takes its best match, with the greedy assignment.
+    pub report_duplicates: bool,
+    /// Shift of the pages of the new version, relative to the old one (e.g. 2
+    /// when two pages were added at the front), followed by the position
+    /// penalty.
+    pub offset: isize,
+    /// Find the shift of the pages before matching them, instead of using
+    /// `offset` (see [`find_offset`]).
+    pub align: bool,
+    /// Keep the next best candidate of the matches when its cost is within
+    /// this margin of the best one, to flag the ambiguous matches.
+    pub ambiguity_margin: Option<f64>,
+}
+
+impl MatchOptions {
+    /// Default weight of the position: a page 5 positions away costs as much
+    /// as an extra bit of hash distance.
+    pub const DEFAULT_POSITION_WEIGHT: f64 = 0.2;
+    /// Default margin of the ambiguous matches: the next best candidate is at
+    /// most one bit of hash distance away.
+    pub const DEFAULT_AMBIGUITY_MARGIN: f64 = 1.;
+
+    /// Initialize the options with the given maximum distance.
+    pub fn new(distance: u8) -> Self {
+        Self {
+            distance,
+            accept_distance: distance,
+            position_weight: Self::DEFAULT_POSITION_WEIGHT,
+            position_window: None,
+            assignment: Assignment::Greedy,
+            metric: Metric::Hamming,
+            prefer: Prefer::None,
+            pair_identical: false,
+            brute_force: false,
+            filename_fallback: false,
+            flatten: false,
+            report_duplicates: false,
+            offset: 0,
+            align: false,
+            ambiguity_margin: None,
+        }
+    }
+
+    /// Check if a match at the given distance is uncertain.
+    fn is_uncertain(&self, distance: isize) -> bool {
+        distance > self.accept_distance.into()
+    }
+
+    /// Compute the cost of matching `candidate` with `image`.
+    fn cost(
+        &self,
+        image: &HashedImage,
+        candidate: &HashedImage,
+        dist: isize,
+    ) -> MatchCost {
+        let shift = (candidate.index as isize + self.offset
+            - image.index as isize)
+            .unsigned_abs();
+        let shift = self.position_window.map_or(shift, |max| shift.min(max));
+        MatchCost {
+            hash_dist: dist,
+            position_penalty: shift as f64 * self.position_weight,
+        }
+    }
+
+    /// Check if a candidate costing `cost` makes a match costing `best`
+    /// ambiguous.
+    fn is_ambiguous(&self, best: f64, cost: f64) -> bool {
+        self.ambiguity_margin
+            .is_some_and(|margin| cost - best <= margin)
+    }
+
+    /// Return these options with the shift of the pages found, if requested.
+    fn aligned(
+        &self,
+        old: &[HashedImage],
+        new: &[HashedImage],
+    ) -> Cow<'_, Self> {
+        if !self.align {
+            return Cow::Borrowed(self);
+        }
+        let offset = find_offset(old, new, self);
+        info!("shifted the pages of the new version by {}", offset);
+        Cow::Owned(Self {
+            offset,
+            align: false,
+            ..self.clone()
+        })
+    }
+}
+
+/// Largest shift of the pages tried when aligning the versions.
+const MAX_OFFSET: isize = 10;
+
+/// Find the shift of the pages of the new version relative to the old one
+/// (e.g. extra pages at the front), i.e. that pairs the most pages at the same
+/// position.
+///
+/// Every shift up to `MAX_OFFSET` pages either way is tried, and the pages at
+/// the same (shifted) position are paired when their distance is at most
+/// `options.distance`. Among the shifts pairing as many pages, the smallest
+/// one is kept.
+pub fn find_offset(
+    old: &[HashedImage],
+    new: &[HashedImage],
+    options: &MatchOptions,
+) -> isize {
+    let old = old
+        .iter()
+        .map(|image| (image.index as isize, image))
+        .collect::<HashMap<_, _>>();
+    let paired = |offset: isize| {
+        new.iter()
+            .filter(|image| {
+                old.get(&(image.index as isize - offset)).is_some_and(
+                    |candidate| {
+                        variant_distance(image, candidate, options.metric).0
+                            <= options.distance.into()
+                    },
+                )
+            })
+            .count()
+    };
+
+    let mut best = (0, paired(0));
+    for offset in (1..=MAX_OFFSET).flat_map(|offset| [offset, -offset]) {
+        let count = paired(offset);
+        if count > best.1 {
+            best = (offset, count);
+        }
+    }
+    debug!("found an offset of {} pages, pairing {}", best.0, best.1);
+
+    best.0
+}
+
+/// Scale of the costs given to the Hungarian algorithm, which works on
+/// integers: the fractional part of the costs is kept to 1/1000.
+const COST_SCALE: f64 = 1000.;
+
+/// Hash of the page of the new version that matched a candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variant {
+    /// The page as is.
+    Direct,
+    /// The horizontally mirrored page.
+    Mirrored,
+    /// The page with its colors inverted.
+    Inverted,
+}
+
+/// A possible match of a page: the page of the old version, its distance, and
+/// how the page of the new version must be transformed.
+type Candidate = (HashedImage, isize, Variant);
+
+/// List the hashes of a page: its own, then its mirrored and inverted hashes
+/// when they are known.
+fn variants(
+    image: &HashedImage,
+) -> impl Iterator<Item = (&ImageHash, Variant)> + '_ {
+    iter::once((&image.hash, Variant::Direct))
+        .chain(
+            image
+                .mirror_hash
+                .iter()
+                .map(|hash| (hash, Variant::Mirrored)),
+        )
+        .chain(
+            image
+                .inverted_hash
+                .iter()
+                .map(|hash| (hash, Variant::Inverted)),
+        )
+}
+
+/// Compute the distance between a page and a candidate, also comparing the
+/// mirrored and inverted pages when their hashes are known.
+///
+/// Returns the smallest distance, and the hash that gives it (the page's own
+/// on ties).
+fn variant_distance(
+    image: &HashedImage,
+    candidate: &HashedImage,
+    metric: Metric,
+) -> (isize, Variant) {
+    variants(image)
+        .map(|(hash, variant)| {
+            (metric.hash_distance(hash, image, candidate), variant)
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .expect("no hash")
+}
+
+/// Hash the in-memory pages (name and content) of both versions, then match
+/// them.
+///
+/// Nothing is read from the filesystem, see [`hash_in_memory`]. When the new
+/// version may contain mirrored pages, `hash_options.mirror` must be set (and
+/// likewise `hash_options.invert` for inverted pages).
+/// Pages that could not be hashed, when errors are skipped, are left out.
+pub fn match_in_memory(
+    old: Vec<(String, Vec<u8>)>,
+    new: Vec<(String, Vec<u8>)>,
+    hash_options: &HashOptions,
+    options: &MatchOptions,
+) -> Result<MatchReport> {
+    let old = hash_in_memory(old, hash_options)?;
+    let new = hash_in_memory(new, hash_options)?;
+    Ok(match_pages(old.images, new.images, options))
+}
+
+/// Index the given pages in a BK-tree, to look them up with [`match_one`].
+///
+/// The BK-tree only keeps one page per hash.
+pub fn index_pages(
+    pages: Vec<HashedImage>,
+    metric: Metric,
+) -> BkTree<HashedImage> {
+    let mut tree = BkTree::new(move |a, b| metric.distance(a, b));
+    tree.insert_all(pages);
+    tree
+}
+
+/// Pages of the old version, indexed by hash for the lookups of the pages of
+/// the new versions.
+///
+/// The index is built once, and can be shared between threads and reused to
+/// match several new versions against the same old one.
+pub struct PageIndex {
+    /// Indexed pages, in their original order.
+    pages: Vec<HashedImage>,
+    /// Nodes of the BK-tree, the root first.
+    nodes: Vec<Node>,
+    metric: Metric,
+    /// Time spent building the index.
+    duration: Duration,
+}
+
+/// Node of the BK-tree of a [`PageIndex`].
+struct Node {
+    /// Positions of the pages of this hash, the preferred one first.
+    pages: Vec<usize>,
+    /// Children, with their distance to this node.
+    children: Vec<(isize, usize)>,
+}
+
+impl PageIndex {
+    /// Index the given pages, with the metric and preference of `options`.
+    pub fn new(pages: Vec<HashedImage>, options: &MatchOptions) -> Self {
+        let start = Instant::now();
+        let metric = options.metric;
+        let mut order = (0..pages.len()).collect::<Vec<_>>();
+        // The candidates are looked up by hash: list the preferred page first.
+        order.sort_by(|&a, &b| options.prefer.compare(&pages[a], &pages[b]));
+
+        let mut nodes = Vec::<Node>::new();
+        for i in order {
+            let node = Node {
+                pages: vec![i],
+                children: Vec::new(),
+            };
+            if nodes.is_empty() {
+                nodes.push(node);
+                continue;
+            }
+            let mut current = 0;
+            loop {
+                let page = &pages[nodes[current].pages[0]];
+                let dist = metric.distance(&pages[i], page);
+                if dist == 0 {
+                    nodes[current].pages.push(i);
+                    break;
+                }
+                match nodes[current].children.iter().find(|(d, _)| *d == dist) {
+                    Some(&(_, child)) => current = child,
+                    None => {
+                        let child = nodes.len();
+                        nodes[current].children.push((dist, child));
+                        nodes.push(node);
+                        break;
+                    },
+                }
+            }
+        }
+        debug!(
+            "indexed {} distinct hashes out of {} pages",
+            nodes.len(),
+            pages.len()
+        );
+
+        Self {
+            pages,
+            nodes,
+            metric,
+            duration: start.elapsed(),
+        }
+    }
+
+    /// Return the indexed pages, in their original order.
+    pub fn pages(&self) -> &[HashedImage] {
+        &self.pages
+    }
+
+    /// Find the nodes within `max` of the given hash of a page, with their
+    /// distance.
+    ///
+    /// Also returns the number of hashes compared.
+    fn find(
+        &self,
+        hash: &ImageHash,
+        image: &HashedImage,
+        max: isize,
+    ) -> (Vec<(&Node, isize)>, usize) {
+        let mut found = Vec::new();
+        let mut comparisons = 0;
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            let page = &self.pages[node.pages[0]];
+            let dist = self.metric.hash_distance(hash, image, page);
+            comparisons += 1;
+            if dist <= max {
+                found.push((node, dist));
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| (d - dist).abs() <= max)
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        (found, comparisons)
+    }
+
+    /// Return the pages of the node among the `available` ones, the preferred
+    /// one first.
+    fn available_pages<'a>(
+        &'a self,
+        node: &'a Node,
+        available: &'a HashSet<OsString>,
+    ) -> impl Iterator<Item = &'a HashedImage> + 'a {
+        node.pages
+            .iter()
+            .map(move |&i| &self.pages[i])
+            .filter(move |page| available.contains(&page.path))
+    }
+
+    /// Find, for each page of the `new` version, the `available` pages within
+    /// `distance`.
+    ///
+    /// The pages sharing a hash are all listed, the preferred one first, so
+    /// that the copies of a page are matched (or reported missing) like the
+    /// other pages.
+    fn find_candidates(
+        &self,
+        available: &HashSet<OsString>,
+        new: &[HashedImage],
+        distance: u8,
+    ) -> Vec<Vec<Candidate>> {
+        let start = Instant::now();
+        let (candidates, comparisons): (Vec<_>, Vec<_>) = new
+            .par_iter()
+            .map(|image| {
+                // The old pages close to several hashes of the page are listed
+                // once per hash, the best option is picked later on.
+                let mut candidates = Vec::new();
+                let mut comparisons = 0;
+                for (hash, variant) in variants(image) {
+                    let (found, count) =
+                        self.find(hash, image, distance.into());
+                    candidates.extend(found.into_iter().flat_map(
+                        |(node, dist)| {
+                            self.available_pages(node, available)
+                                .map(move |page| (page.clone(), dist, variant))
+                        },
+                    ));
+                    comparisons += count;
+                }
+                (candidates, comparisons)
+            })
+            .unzip();
+        debug!(
+            "found {} candidates for {} pages in {:.2?}",
+            candidates.iter().map(Vec::len).sum::<usize>(),
+            new.len(),
+            start.elapsed()
+        );
+        if !comparisons.is_empty() {
+            debug!(
+                "compared {:.1} hashes per page on average, {} at most, out \
+                 of {} indexed",
+                comparisons.iter().sum::<usize>() as f64
+                    / comparisons.len() as f64,
+                comparisons.iter().max().copied().unwrap_or_default(),
+                self.nodes.len()
+            );
+        }
+
+        candidates
+    }
+
+    /// Match a single page against the indexed pages, by hash distance only
+    /// (the positions are left out), also comparing its mirrored and inverted
+    /// hashes when they are known.
+    ///
+    /// This is the lookup done for each page when matching, for one page at a
+    /// time: e.g. for the pages added one by one to a directory.
+    pub fn match_page(
+        &self,
+        image: HashedImage,
+        options: &MatchOptions,
+    ) -> Match {
+        let mut candidates = variants(&image)
+            .flat_map(|(hash, variant)| {
+                let (found, _) =
+                    self.find(hash, &image, options.distance.into());
+                found.into_iter().map(move |(node, dist)| {
+                    (&self.pages[node.pages[0]], dist, variant)
+                })
+            })
+            .collect::<Vec<_>>();
+        // Stable sort: the page's own hash wins the ties.
+        candidates.sort_by(|(a, a_dist, _), (b, b_dist, _)| {
+            a_dist
+                .cmp(b_dist)
+                .then_with(|| options.prefer.compare(a, b))
+                .then_with(|| a.index.cmp(&b.index))
+        });
+
+        let mut candidates = candidates.into_iter();
+        let best = candidates.next();
+        let runner_up = best.and_then(|(matching, distance, _)| {
+            candidates
+                .find(|(other, _, _)| other.path != matching.path)
+                .filter(|(_, other, _)| {
+                    options.is_ambiguous(distance as f64, *other as f64)
+                })
+                .map(|(other, distance, _)| (other.clone(), distance))
+        });
+        let variant = best.map(|(_, _, variant)| variant);
+        Match {
+            uncertain: best
+                .is_some_and(|(_, distance, _)| options.is_uncertain(distance)),
+            dst: best
+                .map(|(matching, distance, _)| (matching.clone(), distance)),
+            src: image,
+            filename_match: false,
+            mirrored: variant == Some(Variant::Mirrored),
+            inverted: variant == Some(Variant::Inverted),
+            duplicate: false,
+            identical: false,
+            runner_up,
+        }
+    }
+}
+
+/// Find the pages of an index (see [`index_pages`]) within `distance` of the
+/// given page, closest first (then in the order of the pages).
+///
+/// This is the lookup done for each page when matching, for one page at a
+/// time: e.g. to find where a loose page comes from.
+pub fn match_one(
+    tree: &BkTree<HashedImage>,
+    query: &HashedImage,
+    distance: u8,
+) -> Vec<(HashedImage, isize)> {
+    let mut matches = tree
+        .find(query.clone(), distance.into())
+        .into_iter()
+        .map(|(image, distance)| (image.clone(), distance))
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|(image, distance)| (*distance, image.index));
+    matches
+}
+
+/// Compute, for each page of the `new` version, the distance to its closest
+/// page of the `old` version (none when the old version is empty), whatever
+/// the maximum distance.
+///
+/// Every pair of pages is compared. The distribution of these distances helps
+/// choosing the maximum distance, between the true matches and the false
+/// positives.
+pub fn best_distances(
+    old: &[HashedImage],
+    new: &[HashedImage],
+    options: &MatchOptions,
+) -> Vec<Option<isize>> {
+    new.par_iter()
+        .map(|image| {
+            old.iter()
+                .map(|candidate| {
+                    variant_distance(image, candidate, options.metric).0
+                })
+                .min()
+        })
+        .collect()
+}
+
+/// Compute the Hamming distance between two images (see [`Metric`]).
+pub fn image_distance(img1: &HashedImage, img2: &HashedImage) -> isize {
+    img1.hash.dist(&img2.hash) as isize
+}
+
+/// Find, for each page of the `new` version, the matching page in the `old`
+/// version.
+///
+/// Pages are matched when their distance is at most `options.distance`.
+///
+/// The lookups run in parallel, but the pages are then assigned in order, so
+/// the result doesn't depend on the number of threads. Among the candidates
+/// of equal cost, a page takes the preferred one (see [`Prefer`]), then the
+/// first one in position, then by filename, so that repeated runs give the
+/// same mapping.
+pub fn match_pages(
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+) -> MatchReport {
+    match_pages_with_progress(old, new, options, &ProgressBar::hidden())
+}
+
+/// Like [`match_pages`], reporting the progress as each page of the `new`
+/// version is matched.
+pub fn match_pages_with_progress(
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+    progress: &ProgressBar,
+) -> MatchReport {
+    match_pages_indexed(&PageIndex::new(old, options), new, options, progress)
+}
+
+/// Like [`match_pages_with_progress`], with the pages of the old version
+/// already indexed (with the same `options`), e.g. to match several new
+/// versions against it.
+pub fn match_pages_indexed(
+    index: &PageIndex,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+    progress: &ProgressBar,
+) -> MatchReport {
+    let start = Instant::now();
+    let count = new.len();
+    progress.start(Task::Matching, "Matching pages…".to_owned(), count);
+    let old = index.pages.clone();
+    let options = &*options.aligned(&old, &new);
+    let (identical, old, new) = if options.pair_identical {
+        pair_identical(old, new, options)
+    } else {
+        (Vec::new(), old, new)
+    };
+    identical.iter().for_each(|_| progress.inc());
+    let (mut mapping, mut missing, indexing) = match options.assignment {
+        Assignment::Optimal if !options.report_duplicates => {
+            assign_optimal(old, new, options, progress)
+        },
+        _ => assign_greedy(index, old, new, options, progress),
+    };
+    progress.finish();
+    if !identical.is_empty() {
+        mapping.extend(identical);
+        mapping.sort_by_key(|m| m.src.index);
+    }
+    if options.filename_fallback {
+        pair_by_filename(&mut mapping, &mut missing, options);
+    }
+    // Whatever the assignment, the missing pages are reported in order.
+    missing.sort_by_key(|image| image.index);
+    let cost = mapping
+        .iter()
+        .filter_map(|m| {
+            let (image, distance) = m.dst.as_ref()?;
+            Some(options.cost(&m.src, image, *distance).total())
+        })
+        .sum();
+    let duration = start.elapsed();
+    info!(
+        "matched {} of {} pages ({} assignment) in {:.2?}",
+        mapping.iter().filter(|m| m.dst.is_some()).count(),
+        count,
+        options.assignment,
+        duration
+    );
+
+    MatchReport {
+        mapping,
+        missing,
+        cost,
+        offset: options.offset,
+        indexing,
+        duration,
+    }
+}
+
+/// Pair the pages of both versions that have the same content digest, the
+/// closest in position first.
+///
+/// Returns the matches, with the pages of each version left to match.
+fn pair_identical(
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+) -> (Vec<Match>, Vec<HashedImage>, Vec<HashedImage>) {
+    let mut by_digest = HashMap::<u64, Vec<HashedImage>>::new();
+    let mut rest = Vec::new();
+    for image in old {
+        match image.digest {
+            Some(digest) => by_digest.entry(digest).or_default().push(image),
+            None => rest.push(image),
+        }
+    }
+
+    let mut matches = Vec::new();
+    let mut unmatched = Vec::new();
+    for image in new {
+        let candidates = image
+            .digest
+            .and_then(|digest| by_digest.get_mut(&digest))
+            .filter(|candidates| !candidates.is_empty());
+        let candidates = match candidates {
+            Some(candidates) => candidates,
+            None => {
+                unmatched.push(image);
+                continue;
+            },
+        };
+        let position = |candidate: &HashedImage| {
+            (candidate.index as isize + options.offset - image.index as isize)
+                .abs()
+        };
+        let (i, _) = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, candidate)| (position(candidate), candidate.index))
+            .expect("no candidates");
+        let candidate = candidates.remove(i);
+        let distance = options.metric.distance(&image, &candidate);
+        matches.push(Match {
+            src: image,
+            dst: Some((candidate, distance)),
+            uncertain: false,
+            filename_match: false,
+            mirrored: false,
+            inverted: false,
+            duplicate: false,
+            identical: true,
+            runner_up: None,
+        });
+    }
+    debug!("paired {} identical pages", matches.len());
+
+    // Keep the pages left in order.
+    rest.extend(by_digest.into_values().flatten());
+    rest.sort_by_key(|image| image.index);
+    (matches, rest, unmatched)
+}
+
+/// Pair the unmatched pages with the missing pages of the same filename.
+///
+/// When `options.flatten` is set, only the last components of the filenames
+/// are compared, and the first missing page wins when several share it.
+fn pair_by_filename(
+    mapping: &mut [Match],
+    missing: &mut Vec<HashedImage>,
+    options: &MatchOptions,
+) {
+    let flatten = options.flatten;
+    let name = |image: &HashedImage| {
+        let path = Path::new(&image.path);
+        match path.file_name() {
+            Some(name) if flatten => name.to_owned(),
+            _ => image.path.clone(),
+        }
+    };
+    for m in mapping.iter_mut().filter(|m| m.dst.is_none()) {
+        let src = name(&m.src);
+        if let Some((i, _)) = missing
+            .iter()
+            .enumerate()
+            .filter(|(_, image)| name(image) == src)
+            .min_by_key(|(_, image)| image.index)
+        {
+            let image = missing.remove(i);
+            let distance = options.metric.distance(&m.src, &image);
+            m.dst = Some((image, distance));
+            m.filename_match = true;
+            m.mirrored = false;
+            m.inverted = false;
+        }
+    }
+}
+
+/// Flag the matches sharing their page of the old version with another one.
+fn flag_duplicates(mapping: &mut [Match]) {
+    let mut claims = HashMap::new();
+    for (image, _) in mapping.iter().filter_map(|m| m.dst.as_ref()) {
+        *claims.entry(image.path.clone()).or_insert(0) += 1;
+    }
+    for m in mapping.iter_mut() {
+        m.duplicate = m
+            .dst
+            .as_ref()
+            .is_some_and(|(image, _)| claims[&image.path] > 1);
+    }
+}
+
+/// Pair the pages in order, each page taking its best remaining match (or its
+/// best match, when reporting the duplicates).
+///
+/// Also returns the time spent indexing the pages.
+fn assign_greedy(
+    index: &PageIndex,
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+    progress: &ProgressBar,
+) -> (Vec<Match>, Vec<HashedImage>, Duration) {
+    // The candidates of all the pages are looked up at once.
+    let chunk = new.len().max(1);
+    let mut matches =
+        stream_indexed(IndexRef::Borrowed(index), old, new, options, chunk);
+    let mut mapping = matches
+        .by_ref()
+        .inspect(|_| progress.inc())
+        .collect::<Vec<_>>();
+    let indexing = matches.indexing();
+    let (missing, _) = matches.finish();
+    if options.report_duplicates {
+        flag_duplicates(&mut mapping);
+    }
+
+    (mapping, missing, indexing)
+}
+
+/// Find, for each page of the `new` version, the matching page in the `old`
+/// version, yielding the matches as soon as they are decided.
+///
+/// Pages are paired with the greedy assignment, whatever `options.assignment`,
+/// and neither the duplicates nor the filename matches are flagged: these need
+/// the whole mapping, see [`match_pages`].
+///
+/// The old version is indexed up front, but the candidates of the new pages
+/// are looked up as they are matched, 64 pages at a time, so
+/// that the first matches come before all the pages are looked up.
+pub fn stream_matches(
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+) -> MatchStream<'_> {
+    let index = PageIndex::new(old, options);
+    let old = index.pages.clone();
+    let duration = index.duration;
+    let mut stream =
+        stream_indexed(IndexRef::Owned(index), old, new, options, STREAM_CHUNK);
+    stream.indexing += duration;
+    stream
+}
+
+/// Number of pages of the new version whose candidates are looked up at once,
+/// when streaming the matches.
+const STREAM_CHUNK: usize = 64;
+
+/// Like [`stream_matches`], with the pages of the old version already indexed:
+/// only those of `old` (some of the indexed pages) are matched, looking the
+/// candidates of `chunk` new pages up at a time.
+fn stream_indexed<'a>(
+    index: IndexRef<'a>,
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &'a MatchOptions,
+    chunk: usize,
+) -> MatchStream<'a> {
+    let options = options.aligned(&old, &new);
+    let start = Instant::now();
+    let available = old
+        .iter()
+        .map(|image| image.path.clone())
+        .collect::<HashSet<_>>();
+
+    // Keep track of the pages presents in the "old" version but missing from
+    // the "new" one.
+    let missing = old
+        .iter()
+        .map(|image| image.path.clone())
+        .collect::<HashSet<_>>();
+
+    MatchStream {
+        pages: new.into_iter(),
+        ready: Vec::new().into_iter().zip(Vec::new()),
+        chunk,
+        index,
+        available,
+        old,
+        missing,
+        cost: 0.,
+        options,
+        indexing: start.elapsed(),
+    }
+}
+
+/// Index of the pages of the old version, owned or borrowed.
+enum IndexRef<'a> {
+    Owned(PageIndex),
+    Borrowed(&'a PageIndex),
+}
+
+impl std::ops::Deref for IndexRef<'_> {
+    type Target = PageIndex;
+
+    fn deref(&self) -> &PageIndex {
+        match self {
+            Self::Owned(index) => index,
+            Self::Borrowed(index) => index,
+        }
+    }
+}
+
+/// Matches of the pages of the new version, in order (see
+/// [`stream_matches`]).
+pub struct MatchStream<'a> {
+    /// Pages of the new version left to look up.
+    pages: vec::IntoIter<HashedImage>,
+    /// Pages of the new version looked up but left to match, with their
+    /// candidates.
+    ready: iter::Zip<vec::IntoIter<HashedImage>, vec::IntoIter<Vec<Candidate>>>,
+    /// Number of pages looked up at once.
+    chunk: usize,
+    index: IndexRef<'a>,
+    /// Filenames of the pages of the old version that can be matched.
+    available: HashSet<OsString>,
+    /// Pages of the old version.
+    old: Vec<HashedImage>,
+    /// Filenames of the pages of the old version that have no match yet.
+    missing: HashSet<OsString>,
+    /// Total cost of the matches so far.
+    cost: f64,
+    options: Cow<'a, MatchOptions>,
+    /// Time spent indexing the pages.
+    indexing: Duration,
+}
+
+impl MatchStream<'_> {
+    /// Return the time spent indexing the pages of the old version, and
+    /// looking up the candidates of the new ones matched so far.
+    pub fn indexing(&self) -> Duration {
+        self.indexing
+    }
+
+    /// Look the candidates of the next chunk of pages up, if any is left.
+    fn look_up(&mut self) -> bool {
+        let new = self.pages.by_ref().take(self.chunk).collect::<Vec<_>>();
+        if new.is_empty() {
+            return false;
+        }
+        let start = Instant::now();
+        let candidates = if self.options.brute_force {
+            compare_all(&self.old, &new, &self.options)
+        } else {
+            self.index.find_candidates(
+                &self.available,
+                &new,
+                self.options.distance,
+            )
+        };
+        self.indexing += start.elapsed();
+        self.ready = new.into_iter().zip(candidates);
+        true
+    }
+
+    /// Return the pages of the old version that are missing from the new one,
+    /// with the total cost of the matches (see [`MatchCost`]).
+    ///
+    /// Only the pages matched so far are accounted for.
+    pub fn finish(self) -> (Vec<HashedImage>, f64) {
+        let Self {
+            old, missing, cost, ..
+        } = self;
+        let missing = old
+            .into_iter()
+            .filter(|image| missing.contains(&image.path))
+            .collect();
+
+        (missing, cost)
+    }
+}
+
+impl Iterator for MatchStream<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let (image, matches) = match self.ready.next() {
+            Some(page) => page,
+            None if self.look_up() => self.ready.next()?,
+            None => return None,
+        };
+        let options = &*self.options;
+
+        // For each page of the "new" version, try to find a match in the "old"
+        // one.
+        let mut matches = matches
+            .into_iter()
+            // Only keep matching images that have no match yet.
+            .filter(|(image, _, _)| {
+                options.report_duplicates || self.missing.contains(&image.path)
+            })
+            // Order the match by distance first, then by "page number".
+            //
+            // i.e. two release of the same book should have the same page in
+            // the same order (barring 1-2 missing pages or page swapping), so a
+            // closer match in term of "page number" is more likely to be the
+            // right one, rather than a match at the opposite side of the book
+            // where it's likely a false positive…
+            .map(|(candidate, distance, variant)| {
+                let cost = options.cost(&image, &candidate, distance).total();
+                (candidate, distance, variant, cost)
+            })
+            .collect::<Vec<_>>();
+        // Exact ties are broken by position then filename, to not depend on
+        // the order of the lookups. The sort is stable, so the direct
+        // candidate comes before its mirrored and inverted twins.
+        matches.sort_by(|(a, _, _, a_cost), (b, _, _, b_cost)| {
+            a_cost
+                .total_cmp(b_cost)
+                .then_with(|| options.prefer.compare(a, b))
+                .then_with(|| a.index.cmp(&b.index))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        let mut matches = matches.into_iter();
+        let m = match matches.next() {
+            // Cool, we got a match, remove from missing set and pair the two
+            // page together for the final report.
+            Some((matching, distance, variant, cost)) => {
+                self.missing.remove(&matching.path);
+                self.cost += cost;
+                // The candidates may be listed once per hash.
+                let runner_up = matches
+                    .find(|(other, _, _, _)| other.path != matching.path)
+                    .filter(|(_, _, _, other)| {
+                        options.is_ambiguous(cost, *other)
+                    })
+                    .map(|(other, distance, _, _)| (other, distance));
+                Match {
+                    src: image,
+                    dst: Some((matching, distance)),
+                    uncertain: options.is_uncertain(distance),
+                    filename_match: false,
+                    mirrored: variant == Variant::Mirrored,
+                    inverted: variant == Variant::Inverted,
+                    duplicate: false,
+                    identical: false,
+                    runner_up,
+                }
+            },
+            // No match, the "new" release have an extra page (or the "old"
+            // release was incomplete)
+            None => {
+                Match {
+                    src: image,
+                    dst: None,
+                    uncertain: false,
+                    filename_match: false,
+                    mirrored: false,
+                    inverted: false,
+                    duplicate: false,
+                    identical: false,
+                    runner_up: None,
+                }
+            },
+        };
+
+        Some(m)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.pages.len() + self.ready.len();
+        (count, Some(count))
+    }
+}
+
+/// Pair the pages so that the total cost of the matches is minimal.
+///
+/// Every pair of pages is considered, and a page left unmatched costs more
+/// than any match, so the number of matches is favored over their quality.
+///
+/// Also returns the time spent computing the costs.
+fn assign_optimal(
+    old: Vec<HashedImage>,
+    new: Vec<HashedImage>,
+    options: &MatchOptions,
+    progress: &ProgressBar,
+) -> (Vec<Match>, Vec<HashedImage>, Duration) {
+    let start = Instant::now();
+    // Cost of the possible matches of each new page, by old page.
+    let candidates = new
+        .par_iter()
+        .map(|image| {
+            old.iter()
+                .map(|candidate| {
+                    let (dist, variant) =
+                        variant_distance(image, candidate, options.metric);
+                    (dist <= options.distance.into()).then(|| {
+                        let cost = options.cost(image, candidate, dist);
+                        let cost = (cost.total() * COST_SCALE).round() as i64;
+                        (dist, variant, cost)
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let unmatched = candidates
+        .iter()
+        .flatten()
+        .filter_map(|candidate| candidate.map(|(_, _, cost)| cost))
+        .max()
+        .unwrap_or_default()
+        + 1;
+    // Large enough to never be picked, small enough to never overflow.
+    let forbidden = unmatched * (new.len() as i64 + 1);
+
+    // One row per new page and one column per old page, followed by one
+    // column per new page to leave it unmatched.
+    let costs = candidates
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|candidate| {
+                    candidate.map_or(forbidden, |(_, _, cost)| cost)
+                })
+                .chain(iter::repeat(unmatched).take(new.len()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let indexing = start.elapsed();
+    let assignment = hungarian::solve(&costs);
+
+    let mut matched = vec![false; old.len()];
+    let mapping = new
+        .into_iter()
+        .zip(assignment)
+        .zip(candidates)
+        .map(|((image, column), candidates)| {
+            progress.inc();
+            match candidates.get(column).copied().flatten() {
+                Some((distance, variant, cost)) => {
+                    matched[column] = true;
+                    let runner_up = candidates
+                        .iter()
+                        .enumerate()
+                        .filter(|&(other, _)| other != column)
+                        .filter_map(|(other, candidate)| {
+                            candidate.map(|(distance, _, cost)| {
+                                (cost, other, distance)
+                            })
+                        })
+                        .min()
+                        .filter(|&(other, _, _)| {
+                            options.is_ambiguous(
+                                cost as f64 / COST_SCALE,
+                                other as f64 / COST_SCALE,
+                            )
+                        })
+                        .map(|(_, other, distance)| {
+                            (old[other].clone(), distance)
+                        });
+                    Match {
+                        src: image,
+                        dst: Some((old[column].clone(), distance)),
+                        uncertain: options.is_uncertain(distance),
+                        filename_match: false,
+                        mirrored: variant == Variant::Mirrored,
+                        inverted: variant == Variant::Inverted,
+                        duplicate: false,
+                        identical: false,
+                        runner_up,
+                    }
+                },
+                None => {
+                    Match {
+                        src: image,
+                        dst: None,
+                        uncertain: false,
+                        filename_match: false,
+                        mirrored: false,
+                        inverted: false,
+                        duplicate: false,
+                        identical: false,
+                        runner_up: None,
+                    }
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    let missing = old
+        .into_iter()
+        .zip(matched)
+        .filter_map(|(image, matched)| (!matched).then_some(image))
+        .collect();
+
+    (mapping, missing, indexing)
+}
+
+/// Align the pages of several versions of the same book.
+///
+/// The first version is used as reference: the pages of every other version
+/// are matched against it, and the pages without a match are added to the
+/// reference, so that the extra pages shared by several versions are aligned
+/// as well.
+///
+/// Returns one row per logical page, with one column per version (`None`
+/// where the page is missing from that version).
+pub fn align_versions(
+    versions: Vec<Vec<HashedImage>>,
+    options: &MatchOptions,
+) -> Vec<Vec<Option<HashedImage>>> {
+    let count = versions.len();
+    // Each row is represented by its first page, with the version it comes
+    // from.
+    let mut rows = Vec::<(usize, HashedImage, Vec<Option<HashedImage>>)>::new();
+
+    for (column, pages) in versions.into_iter().enumerate() {
+        // The pages are told apart by path while matching, and pages from
+        // different versions may share one: the representatives are matched
+        // under the path of their version, and their row is looked up by it.
+        let qualified = |version: usize, image: &HashedImage| {
+            Path::new(&version.to_string())
+                .join(&image.path)
+                .into_os_string()
+        };
+        let mut row_of = HashMap::with_capacity(rows.len());
+        let reference = rows
+            .iter()
+            .enumerate()
+            .map(|(row, (version, image, _))| {
+                let path = qualified(*version, image);
+                row_of.insert(path.clone(), row);
+                HashedImage {
+                    path,
+                    ..image.clone()
+                }
+            })
+            .collect();
+
+        for m in match_pages(reference, pages, options).mapping {
+            match m.dst {
+                Some((image, _)) => {
+                    rows[row_of[&image.path]].2[column] = Some(m.src);
+                },
+                None => {
+                    let mut cells = vec![None; count];
+                    cells[column] = Some(m.src.clone());
+                    rows.push((column, m.src, cells));
+                },
+            }
+        }
+    }
+
+    // Keep the extra pages close to where they appear in their version.
+    rows.sort_by_key(|(_, image, _)| image.index);
+    rows.into_iter().map(|(_, _, cells)| cells).collect()
+}
+
+/// Find, for each page of the `new` version, the pages of the `old` version
+/// within `options.distance`, by comparing every pair of pages.
+fn compare_all(
+    old: &[HashedImage],
+    new: &[HashedImage],
+    options: &MatchOptions,
+) -> Vec<Vec<Candidate>> {
+    let start = Instant::now();
+    let metric = options.metric;
+
+    let candidates = new
+        .par_iter()
+        .map(|image| {
+            // Like with the BK-tree, the old pages may be listed once per hash.
+            variants(image)
+                .flat_map(|(hash, variant)| {
+                    old.iter().map(move |candidate| {
+                        let dist = metric.hash_distance(hash, image, candidate);
+                        (candidate, dist, variant)
+                    })
+                })
+                .filter(|&(_, dist, _)| dist <= options.distance.into())
+                .map(|(candidate, dist, variant)| {
+                    (candidate.clone(), dist, variant)
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    debug!(
+        "compared {} pairs of pages in {:.2?}",
+        old.len() * new.len(),
+        start.elapsed()
+    );
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the page at the given position of a version, with a hash made
+    /// of the given byte.
+    fn page(path: &str, index: usize, hash: u8) -> HashedImage {
+        HashedImage {
+            path: path.into(),
+            index,
+            size: 0,
+            hash: ImageHash::from_bytes(&[hash; 8]).expect("hash"),
+            mirror_hash: None,
+            inverted_hash: None,
+            thumbnail: None,
+            digest: None,
+            dimensions: None,
+        }
+    }
+
+    #[test]
+    fn copies_of_a_page() {
+        // The second page of the old version is a copy of the first one.
+        let old = vec![page("o1", 0, 0), page("o2", 1, 0), page("o3", 2, 0xff)];
+        let new = vec![page("n1", 0, 0), page("n2", 1, 0xff)];
+
+        for options in [
+            MatchOptions::new(4),
+            MatchOptions {
+                brute_force: true,
+                ..MatchOptions::new(4)
+            },
+            MatchOptions {
+                assignment: Assignment::Optimal,
+                ..MatchOptions::new(4)
+            },
+        ] {
+            let report = match_pages(old.clone(), new.clone(), &options);
+            let matched = report
+                .mapping
+                .iter()
+                .map(|m| m.dst.as_ref().map(|(image, _)| image.path.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(matched, [Some("o1".into()), Some("o3".into())]);
+            let missing = report
+                .missing
+                .iter()
+                .map(|image| image.path.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(missing, ["o2"], "{}", options.assignment);
+
+            // Each copy is matched in turn.
+            let new = vec![page("n1", 0, 0), page("n2", 1, 0)];
+            let report = match_pages(old.clone(), new, &options);
+            assert!(report.mapping.iter().all(|m| m.dst.is_some()));
+            assert_eq!(report.missing.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Reading of the page files: streamed through a buffered reader, or mapped
+//! in memory.
+use crate::{debug, digest};
+use eyre::{Context, Result};
+use image::ImageFormat;
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Number of leading bytes of a streamed file kept in memory, to identify its
+/// format and read its metadata (e.g. its EXIF orientation).
+pub const HEADER_SIZE: usize = 256 * 1024;
+
+/// Content of a page file.
+pub enum Contents {
+    /// The whole file, mapped in memory.
+    #[cfg(unix)]
+    Mapped(Mmap),
+    /// The whole file, read in memory.
+    Read(Vec<u8>),
+    /// A file decoded as it is read, of which only the header is in memory.
+    Streamed(Streamed),
+}
+
+/// A page file read a buffer at a time, see [`Contents::Streamed`].
+pub struct Streamed {
+    file: File,
+    path: PathBuf,
+    /// First [`HEADER_SIZE`] bytes of the file.
+    header: Vec<u8>,
+    digest: u64,
+}
+
+impl Contents {
+    /// Return the whole content, unless it is streamed.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            #[cfg(unix)]
+            Self::Mapped(mmap) => Some(mmap),
+            Self::Read(contents) => Some(contents),
+            Self::Streamed(_) => None,
+        }
+    }
+
+    /// Return the beginning of the content: the whole content, unless it is
+    /// streamed.
+    pub fn header(&self) -> &[u8] {
+        match self {
+            Self::Streamed(streamed) => &streamed.header,
+            _ => self.bytes().expect("content in memory"),
+        }
+    }
+
+    /// Compute the digest of the whole content (see [`digest::fnv1a`]).
+    pub fn digest(&self) -> u64 {
+        match self {
+            Self::Streamed(streamed) => streamed.digest,
+            _ => digest::fnv1a(self.header()),
+        }
+    }
+}
+
+impl Streamed {
+    /// Return the first [`HEADER_SIZE`] bytes of the file.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Return a buffered reader of the file, from its start.
+    pub fn reader(&self) -> io::Result<BufReader<&File>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(BufReader::new(file))
+    }
+
+    /// Read the whole file, e.g. to describe it when it cannot be decoded.
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.reader()
+            .and_then(|mut reader| reader.read_to_end(&mut contents))
+            .wrap_err_with(|| {
+                format!("cannot read page {}", self.path.display())
+            })?;
+        Ok(contents)
+    }
+}
+
+/// Load the content of the page at `path`, expected to be `size` bytes long.
+///
+/// By default, the file is read once to compute its digest, keeping only its
+/// header in memory, then decoded from a buffered reader: its whole content is
+/// never held in memory. The files that fit in the header, and those that the
+/// decoders need whole (WebP images, unknown formats), are read in memory.
+///
+/// When `mmap` is set, the file is mapped in memory instead, so that its pages
+/// can be reclaimed under memory pressure. Files that cannot be mapped (e.g.
+/// empty ones, or on the platforms other than Unix) are streamed anyway.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn read_page(path: &Path, size: usize, mmap: bool) -> Result<Contents> {
+    let file = File::open(path)
+        .wrap_err_with(|| format!("cannot open page {}", path.display()))?;
+
+    #[cfg(unix)]
+    if mmap {
+        match Mmap::new(&file) {
+            Ok(mmap) => return Ok(Contents::Mapped(mmap)),
+            Err(err) => {
+                debug!("cannot map {}, reading it: {}", path.display(), err);
+            },
+        }
+    }
+
+    stream(file, path, size)
+        .wrap_err_with(|| format!("cannot read page {}", path.display()))
+}
+
+/// Read the file a buffer at a time, keeping its header (see [`read_page`]).
+fn stream(mut file: File, path: &Path, size: usize) -> io::Result<Contents> {
+    let mut header = Vec::with_capacity(size.min(HEADER_SIZE));
+    let mut buffer = vec![0; 64 * 1024];
+    let mut digest = digest::Fnv1a::new();
+    let mut total = 0;
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        digest.update(&buffer[..read]);
+        total += read;
+        let kept = read.min(HEADER_SIZE - header.len());
+        header.extend_from_slice(&buffer[..kept]);
+        if kept < read && !is_streamable(&header) {
+            // Read the rest, whole.
+            file.seek(SeekFrom::Start(0))?;
+            let mut contents = Vec::with_capacity(size);
+            file.read_to_end(&mut contents)?;
+            return Ok(Contents::Read(contents));
+        }
+    }
+    if total == header.len() {
+        return Ok(Contents::Read(header));
+    }
+
+    Ok(Contents::Streamed(Streamed {
+        file,
+        path: path.to_owned(),
+        header,
+        digest: digest.finish(),
+    }))
+}
+
+/// Check if the file starting with `header` can be decoded from a reader.
+fn is_streamable(header: &[u8]) -> bool {
+    match image::guess_format(header) {
+        // The extended WebP images are rewritten whole, see
+        // [`formats::simple_webp`](crate::formats::simple_webp).
+        Ok(ImageFormat::WebP) | Err(_) => false,
+        Ok(_) => crate::formats::unsupported_format(header).is_none(),
+    }
+}
+
+/// A read-only, private memory map of a whole file.
+///
+/// The file must not be truncated while it is mapped.
+#[cfg(unix)]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only, and owned.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    fn new(file: &File) -> std::io::Result<Self> {
+        use std::{convert::TryInto, io, os::unix::io::AsRawFd, ptr};
+
+        let len = file.metadata()?.len().try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "file too large")
+        })?;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty file",
+            ));
+        }
+
+        // SAFETY: the arguments are valid, and the result is checked.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is valid for `len` bytes until dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `mmap`, with this length.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
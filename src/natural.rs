@@ -0,0 +1,171 @@
+//! Orderings of the page paths: natural, where numbers are compared by value,
+//! or case-insensitive.
+use std::{cmp::Ordering, iter::Peekable, path::Path, str::Chars};
+
+/// Compare two paths in natural order, component by component.
+///
+/// Runs of digits are compared by value, so `page2` comes before `page10`, and
+/// `page02` ties with `page2` (the one with fewer leading zeros comes first
+/// to keep the ordering total).
+pub fn compare_paths(a: &Path, b: &Path) -> Ordering {
+    let mut a = a.components();
+    let mut b = b.components();
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => {
+                let ordering = compare_str(
+                    &x.as_os_str().to_string_lossy(),
+                    &y.as_os_str().to_string_lossy(),
+                )
+                .then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (x, y) => return x.is_some().cmp(&y.is_some()),
+        }
+    }
+}
+
+/// Compare two paths component by component, ignoring the case.
+///
+/// Paths that only differ by their case are compared as is, to keep the
+/// ordering total.
+pub fn compare_paths_ci(a: &Path, b: &Path) -> Ordering {
+    let lowercase = |path: &Path| {
+        path.components()
+            .map(|component| {
+                component.as_os_str().to_string_lossy().to_lowercase()
+            })
+            .collect::<Vec<_>>()
+    };
+    lowercase(a).cmp(&lowercase(b)).then_with(|| a.cmp(b))
+}
+
+/// Compare two strings in natural order.
+fn compare_str(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    // Leading zeros only break the ties once the whole strings are compared.
+    let mut zeros = Ordering::Equal;
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x_zeros, x) = take_number(&mut a);
+                let (y_zeros, y) = take_number(&mut b);
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                zeros = zeros.then(x_zeros.cmp(&y_zeros));
+            },
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            },
+            (x, y) => return x.is_some().cmp(&y.is_some()).then(zeros),
+        }
+    }
+}
+
+/// Consume a run of digits, returning its number of leading zeros and its
+/// significant digits.
+fn take_number(chars: &mut Peekable<Chars<'_>>) -> (usize, String) {
+    let mut zeros = 0;
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if c == '0' && digits.is_empty() {
+            zeros += 1;
+        } else {
+            digits.push(c);
+        }
+    }
+    (zeros, digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sort the paths with the given ordering.
+    fn sorted<'a>(
+        paths: &[&'a str],
+        compare: fn(&Path, &Path) -> Ordering,
+    ) -> Vec<&'a str> {
+        let mut paths = paths.to_vec();
+        paths.sort_by(|a, b| compare(Path::new(a), Path::new(b)));
+        paths
+    }
+
+    #[test]
+    fn digit_runs() {
+        let paths = ["page10.png", "page2.png", "page1.png", "page1b.png"];
+        assert_eq!(
+            sorted(&paths, compare_paths),
+            ["page1.png", "page1b.png", "page2.png", "page10.png"]
+        );
+        // Several runs, compared one after the other.
+        assert_eq!(
+            sorted(&["v2-p10", "v10-p1", "v2-p9"], compare_paths),
+            ["v2-p9", "v2-p10", "v10-p1"]
+        );
+        // Way past the integer types.
+        assert_eq!(
+            compare_paths(
+                Path::new("99999999999999999999999999"),
+                Path::new("100000000000000000000000000")
+            ),
+            Ordering::Less
+        );
+        // Component by component, not on the whole path.
+        assert_eq!(
+            sorted(&["ch10/p1", "ch9/p2", "ch9/p10"], compare_paths),
+            ["ch9/p2", "ch9/p10", "ch10/p1"]
+        );
+        assert_eq!(
+            sorted(&["ch1/p1", "ch1"], compare_paths),
+            ["ch1", "ch1/p1"]
+        );
+    }
+
+    #[test]
+    fn leading_zeros() {
+        assert_eq!(
+            sorted(&["p010", "p9", "p02", "p1"], compare_paths),
+            ["p1", "p02", "p9", "p010"]
+        );
+        // Equal values are told apart by their leading zeros, fewer first,
+        // but only once the rest of the paths ties.
+        assert_eq!(
+            sorted(&["p002", "p2", "p02"], compare_paths),
+            ["p2", "p02", "p002"]
+        );
+        assert_eq!(sorted(&["p02b", "p2a"], compare_paths), ["p2a", "p02b"]);
+        assert_eq!(
+            compare_paths(Path::new("p0"), Path::new("p00")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn mixed_case() {
+        // The natural order keeps the case: uppercase letters come first.
+        assert_eq!(
+            sorted(&["page2", "Page10", "page1"], compare_paths),
+            ["Page10", "page1", "page2"]
+        );
+        assert_eq!(
+            sorted(&["b.png", "A.png", "a.png", "B.png"], compare_paths_ci),
+            ["A.png", "a.png", "B.png", "b.png"]
+        );
+        assert_eq!(
+            sorted(&["CH2/p1", "ch1/P2", "Ch1/p1"], compare_paths_ci),
+            ["Ch1/p1", "ch1/P2", "CH2/p1"]
+        );
+        // Without natural order: digits are compared one by one.
+        assert_eq!(sorted(&["P10", "p9"], compare_paths_ci), ["P10", "p9"]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Rasterization of PDF documents, one image per page.
+//!
+//! Pages are rendered by `pdftoppm` (from Poppler), which must be installed,
+//! and only when the `pdf` feature is enabled.
+use crate::archive::Entry;
+use eyre::{Context, Result};
+use std::{fs::File, io::Read, path::Path};
+
+/// Signature found at the start of a PDF document.
+const SIGNATURE: &[u8] = b"%PDF-";
+
+/// Check if the given path is a PDF document.
+pub fn is_pdf(path: &Path) -> Result<bool> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+
+    let mut signature = [0; 5];
+    let mut file = File::open(path)
+        .wrap_err_with(|| format!("open {}", path.display()))?;
+    Ok(match file.read_exact(&mut signature) {
+        Ok(()) => signature == SIGNATURE,
+        Err(_) => false,
+    })
+}
+
+/// Render every page of the document at the given path, at `dpi` dots per
+/// inch.
+///
+/// Pages are returned in order, as PNG images named `page-00001.png`,
+/// `page-00002.png`, …
+#[cfg(feature = "pdf")]
+pub fn rasterize(path: &Path, dpi: u32) -> Result<Vec<Entry>> {
+    use eyre::{ensure, eyre};
+    use std::{
+        env,
+        fs,
+        path::PathBuf,
+        process::{self, Command},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Number of documents rasterized so far, to name the directories.
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Temporary directory, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            // Nothing sensible to do if the cleanup fails.
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    let directory = TempDir(env::temp_dir().join(format!(
+        "img-match-{}-{}",
+        process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    )));
+    fs::create_dir_all(&directory.0).wrap_err_with(|| {
+        format!("create directory {}", directory.0.display())
+    })?;
+
+    let output = Command::new("pdftoppm")
+        .arg("-r")
+        .arg(dpi.to_string())
+        .arg("-png")
+        .arg(path)
+        .arg(directory.0.join("page"))
+        .output()
+        .wrap_err("run pdftoppm (is Poppler installed?)")?;
+    ensure!(
+        output.status.success(),
+        "pdftoppm failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    // Files are named after the page number, padded to a varying width.
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(&directory.0).wrap_err("list rendered pages")? {
+        let path = entry.wrap_err("access directory entry")?.path();
+        let number = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix("page-"))
+            .and_then(|number| number.parse::<usize>().ok())
+            .ok_or_else(|| eyre!("unexpected file {}", path.display()))?;
+        let contents = fs::read(&path)
+            .wrap_err_with(|| format!("read {}", path.display()))?;
+        pages.push((number, contents));
+    }
+    pages.sort_by_key(|&(number, _)| number);
+
+    Ok(pages
+        .into_iter()
+        .map(|(number, contents)| Entry {
+            name: format!("page-{:05}.png", number).into(),
+            contents,
+        })
+        .collect())
+}
+
+/// Render every page of the document at the given path.
+///
+/// Always fails, as the support of PDF is disabled.
+#[cfg(not(feature = "pdf"))]
+pub fn rasterize(_path: &Path, _dpi: u32) -> Result<Vec<Entry>> {
+    eyre::bail!("PDF support is disabled (enable the `pdf` feature)")
+}
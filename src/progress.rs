@@ -0,0 +1,183 @@
+//! Progress reporting on the standard error, or to a callback.
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+};
+
+/// Width of the bar, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Task whose progress is reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Task {
+    /// Hashing the pages of a version.
+    #[default]
+    Hashing,
+    /// Matching the pages of the new version.
+    Matching,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hashing => "hashed",
+            Self::Matching => "matched",
+        })
+    }
+}
+
+/// Progress of the current task, given to the callback of a [`ProgressBar`].
+///
+/// Displayed as `hashed 3/12`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub task: Task,
+    /// Number of steps done so far.
+    pub done: usize,
+    /// Number of steps of the task.
+    pub total: usize,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}/{}", self.task, self.done, self.total)
+    }
+}
+
+/// Function called on every step of a task.
+type Callback = Box<dyn Fn(Progress) + Send + Sync>;
+
+/// A progress bar, that can be shared between threads.
+///
+/// The bar is only drawn when the standard error is a terminal, otherwise only
+/// the message is printed.
+pub struct ProgressBar {
+    visible: bool,
+    callback: Option<Callback>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    task: Task,
+    message: String,
+    done: usize,
+    total: usize,
+}
+
+impl ProgressBar {
+    /// Initialize a new progress bar.
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            callback: None,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Initialize a progress bar that never prints anything.
+    pub fn hidden() -> Self {
+        Self {
+            visible: false,
+            callback: None,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Initialize a progress bar that never prints anything, but calls
+    /// `callback` when a task starts and on each of its steps.
+    ///
+    /// The callback is called from the threads doing the work, one at a time.
+    pub fn with_callback(
+        callback: impl Fn(Progress) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            visible: false,
+            callback: Some(Box::new(callback)),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Start a new task of `total` steps.
+    pub(crate) fn start(&self, task: Task, message: String, total: usize) {
+        if !self.visible && self.callback.is_none() {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("poisoned progress bar");
+        *state = State {
+            task,
+            message,
+            done: 0,
+            total,
+        };
+        self.report(&state);
+        if !self.visible {
+            return;
+        }
+        if io::stderr().is_terminal() {
+            state.draw();
+        } else {
+            eprintln!("{}", state.message);
+        }
+    }
+
+    /// Advance the current task by one step.
+    pub(crate) fn inc(&self) {
+        if !self.visible && self.callback.is_none() {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("poisoned progress bar");
+        state.done += 1;
+        self.report(&state);
+        if self.visible && io::stderr().is_terminal() {
+            state.draw();
+        }
+    }
+
+    /// Terminate the current task.
+    pub(crate) fn finish(&self) {
+        if self.visible && io::stderr().is_terminal() {
+            eprintln!();
+        }
+    }
+
+    /// Give the progress of the current task to the callback, if any.
+    fn report(&self, state: &State) {
+        if let Some(callback) = &self.callback {
+            callback(Progress {
+                task: state.task,
+                done: state.done,
+                total: state.total,
+            });
+        }
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    fn draw(&self) {
+        let filled = (self.done * BAR_WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(BAR_WIDTH);
+        let mut stderr = io::stderr().lock();
+        // Nothing sensible to do if we cannot print the progress.
+        let _ = write!(
+            stderr,
+            "\r{} [{}{}] {}/{}",
+            self.message,
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            self.done,
+            self.total
+        );
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,1220 @@
+//! Rendering of the final matching report.
+use eyre::{bail, Report as Error, Result};
+use img_match::{
+    json::push_string,
+    Change,
+    HashedImage,
+    Match,
+    PageChange,
+    SkippedPage,
+    Spread,
+    SpreadKind,
+    Summary,
+};
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Output format of the report.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Human-readable report.
+    Text,
+    /// A single JSON document.
+    Json,
+    /// One row per page, with a header.
+    Csv,
+    /// One JSON object per page, on its own line.
+    ///
+    /// The reports that are not about the page mapping are written as a
+    /// single JSON document, on one line.
+    JsonLines,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            "csv" => Self::Csv,
+            "json-lines" => Self::JsonLines,
+            _ => bail!("invalid format {}", s),
+        })
+    }
+}
+
+/// Version whose pages key the report.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GroupBy {
+    /// One entry per page of the new version, with its match.
+    New,
+    /// One entry per page of the old version, with what it became.
+    Old,
+    /// Sections of pages by match quality: exact, close and uncertain
+    /// matches, new and missing pages.
+    Quality,
+}
+
+impl FromStr for GroupBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "new" => Self::New,
+            "old" => Self::Old,
+            "quality" => Self::Quality,
+            _ => bail!("invalid grouping {}", s),
+        })
+    }
+}
+
+/// When to color the text report.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal, unless `NO_COLOR` is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Check if the report written to the standard output is colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Auto => {
+                io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+            },
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => Self::Auto,
+            "always" => Self::Always,
+            "never" => Self::Never,
+            _ => bail!("invalid color choice {}", s),
+        })
+    }
+}
+
+/// ANSI colors of the text report: the exact matches, the other matches, and
+/// the new and missing pages.
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const RED: &str = "31";
+
+/// Pages to report on, with the root directories of each version.
+pub struct Report<'a> {
+    pub old: &'a Path,
+    pub new: &'a Path,
+    pub mapping: &'a [Match],
+    pub missing: &'a [HashedImage],
+    pub skipped: &'a [SkippedPage],
+    /// Pages split or merged between the versions, when detected.
+    pub spreads: &'a [Spread],
+    /// Maximum distances that would match more of the missing pages, with
+    /// how many more (see [`MatchReport::suggest_distances`]).
+    ///
+    /// [`MatchReport::suggest_distances`]: img_match::MatchReport::suggest_distances
+    pub suggestions: &'a [(u8, usize)],
+    /// Total cost of the matches, reported with the optimal assignment only.
+    pub cost: Option<f64>,
+    /// Number of bits of the hashes, to compute the confidence of the matches.
+    pub hash_bits: u32,
+    /// Statistics of the matching, when requested.
+    pub summary: Option<Summary>,
+    /// Color the text report.
+    pub color: bool,
+    /// Only report the new and missing pages in the text report: the mapping
+    /// header, the suggestions and the total cost are left out.
+    pub changes_only: bool,
+}
+
+/// Write the report in the requested format.
+pub fn render_report(
+    out: &mut impl Write,
+    format: Format,
+    report: &Report<'_>,
+) -> Result<()> {
+    match format {
+        Format::Text => render_text(out, report),
+        Format::Json => render_json(out, report),
+        Format::Csv => render_csv(out, report),
+        Format::JsonLines => render_json_lines(out, report),
+    }
+}
+
+// TODO: find a clearer way to expose this, currently it's very noisy and need
+// manual scrutiny…
+fn render_text(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    write_text_header(out, report)?;
+    for m in report.mapping {
+        write_text_match(out, report, m)?;
+    }
+    write_text_footer(out, report)
+}
+
+/// Write the header of the text report, before the matches (only the new
+/// pages when reporting the changes only).
+pub fn write_text_header(
+    out: &mut impl Write,
+    report: &Report<'_>,
+) -> Result<()> {
+    match report.changes_only {
+        true => writeln!(out, "NEW PAGES")?,
+        false => writeln!(out, "PAGE MAPPING:")?,
+    }
+    Ok(())
+}
+
+/// Write a match of the text report.
+///
+/// Only the roots and hash size of the report are used, so that the matches
+/// can be written as soon as they are decided.
+pub fn write_text_match(
+    out: &mut impl Write,
+    report: &Report<'_>,
+    m: &Match,
+) -> Result<()> {
+    match &m.dst {
+        Some((image, distance)) => {
+            let line = format!(
+                "{} {} {} (DISTANCE: {}, CONFIDENCE: {:.0}%{}{}{})",
+                report.new.join(&m.src.path).display(),
+                m.status().to_uppercase().replace('-', " "),
+                report.old.join(&image.path).display(),
+                distance,
+                confidence(m, report.hash_bits) * 100.,
+                if m.mirrored { ", MIRRORED" } else { "" },
+                if m.inverted { ", INVERTED" } else { "" },
+                m.runner_up.as_ref().map_or_else(String::new, |(other, d)| {
+                    format!(
+                        ", RUNNER-UP: {} AT {}",
+                        report.old.join(&other.path).display(),
+                        d
+                    )
+                })
+            );
+            let exact =
+                *distance == 0 && matches!(m.status(), "match" | "identical");
+            let color = if exact { GREEN } else { YELLOW };
+            writeln!(out, "\t{}", paint(report, color, line))?;
+        },
+        None => {
+            let line = format!(
+                "{} (NEW PAGE)",
+                report.new.join(&m.src.path).display()
+            );
+            writeln!(out, "\t{}", paint(report, RED, line))?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Write the end of the text report, after the matches: the missing, split or
+/// merged and skipped pages, the total cost and the summary.
+pub fn write_text_footer(
+    out: &mut impl Write,
+    report: &Report<'_>,
+) -> Result<()> {
+    if !report.missing.is_empty() {
+        writeln!(out, "\nMISSING PAGES")?;
+        for image in report.missing {
+            let line = report.old.join(&image.path).display().to_string();
+            writeln!(out, "\t{}", paint(report, RED, line))?;
+        }
+    }
+
+    if !report.spreads.is_empty() {
+        writeln!(out, "\nSPREADS")?;
+    }
+    for spread in report.spreads {
+        let join = |root: &Path, images: &[HashedImage]| {
+            images
+                .iter()
+                .map(|image| root.join(&image.path).display().to_string())
+                .collect::<Vec<_>>()
+                .join(" + ")
+        };
+        writeln!(
+            out,
+            "\t{} {} FROM {} (DISTANCE: {})",
+            join(report.new, &spread.new),
+            match spread.kind {
+                SpreadKind::Split => "SPLIT",
+                SpreadKind::Merged => "MERGED",
+            },
+            join(report.old, &spread.old),
+            spread.distance
+        )?;
+    }
+
+    let suggestions = match report.changes_only {
+        true => &[],
+        false => report.suggestions,
+    };
+    if !suggestions.is_empty() {
+        writeln!(out, "\nSUGGESTIONS")?;
+    }
+    for (distance, matched) in suggestions {
+        writeln!(
+            out,
+            "\tWITH --distance {}, {} MORE PAGE(S) WOULD MATCH",
+            distance, matched
+        )?;
+    }
+
+    for (too_small, title) in
+        [(false, "SKIPPED FILES"), (true, "SKIPPED (TOO SMALL)")]
+    {
+        let mut pages = report
+            .skipped
+            .iter()
+            .filter(|page| page.too_small == too_small)
+            .peekable();
+        if pages.peek().is_some() {
+            writeln!(out, "\n{}", title)?;
+        }
+        for page in pages {
+            writeln!(out, "\t{} ({})", page.path.display(), page.reason)?;
+        }
+    }
+
+    if let Some(cost) = report.cost.filter(|_| !report.changes_only) {
+        writeln!(out, "\nTOTAL COST: {:.2}", cost)?;
+    }
+    write_summary(out, report)?;
+
+    Ok(())
+}
+
+/// Wrap a line of the text report in the given ANSI color, if enabled.
+fn paint(report: &Report<'_>, color: &str, line: String) -> String {
+    if report.color {
+        format!("\x1b[{}m{}\x1b[0m", color, line)
+    } else {
+        line
+    }
+}
+
+fn render_json(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    let mut json = String::from("{\"matches\":[");
+    for (i, m) in report.mapping.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        push_json_match(&mut json, report, m)?;
+    }
+    json.push_str("],\"missing\":[");
+    for (i, image) in report.missing.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        push_json_path(&mut json, &report.old.join(&image.path));
+    }
+    if !report.spreads.is_empty() {
+        json.push_str("],\"spreads\":[");
+        for (i, spread) in report.spreads.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            push_json_spread(&mut json, report, spread)?;
+        }
+    }
+    if !report.suggestions.is_empty() {
+        json.push_str("],\"suggestions\":[");
+        push_json_suggestions(&mut json, report)?;
+    }
+    json.push_str("],\"skipped\":[");
+    for (i, page) in report.skipped.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        json.push_str("{\"path\":");
+        push_json_path(&mut json, &page.path);
+        json.push_str(",\"reason\":");
+        push_string(&mut json, &page.reason);
+        write!(json, ",\"too_small\":{}}}", page.too_small)?;
+    }
+    json.push(']');
+    push_json_cost(&mut json, report)?;
+    push_json_summary(&mut json, report)?;
+    json.push('}');
+
+    writeln!(out, "{}", json)?;
+    Ok(())
+}
+
+/// Append the total cost of the matches to a JSON object, when reported.
+fn push_json_cost(json: &mut String, report: &Report<'_>) -> Result<()> {
+    if let Some(cost) = report.cost {
+        write!(json, ",\"cost\":{:.3}", cost)?;
+    }
+    Ok(())
+}
+
+/// Append a match of the report to a JSON document, as an object.
+fn push_json_match(
+    json: &mut String,
+    report: &Report<'_>,
+    m: &Match,
+) -> Result<()> {
+    json.push_str("{\"src\":");
+    push_json_path(json, &report.new.join(&m.src.path));
+    json.push_str(",\"dst\":");
+    match &m.dst {
+        Some((image, distance)) => {
+            push_json_path(json, &report.old.join(&image.path));
+            write!(
+                json,
+                ",\"distance\":{},\"confidence\":{:.4},\"uncertain\":{},\
+                 \"filename_match\":{},\"mirrored\":{},\"inverted\":{},\
+                 \"duplicate\":{},\"identical\":{}",
+                distance,
+                confidence(m, report.hash_bits),
+                m.uncertain,
+                m.filename_match,
+                m.mirrored,
+                m.inverted,
+                m.duplicate,
+                m.identical
+            )?;
+            push_json_runner_up(json, report, m)?;
+        },
+        None => json.push_str(
+            "null,\"distance\":null,\"confidence\":null,\
+             \"uncertain\":false,\"filename_match\":false,\
+             \"mirrored\":false,\"inverted\":false,\"duplicate\":false,\
+             \"identical\":false,\"runner_up\":null",
+        ),
+    }
+    json.push('}');
+    Ok(())
+}
+
+fn render_csv(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    writeln!(out, "new_path,old_path,distance,status")?;
+    for m in report.mapping {
+        let src = csv_field(&report.new.join(&m.src.path));
+        match &m.dst {
+            Some((image, distance)) => {
+                let dst = csv_field(&report.old.join(&image.path));
+                writeln!(out, "{},{},{},{}", src, dst, distance, m.status())?;
+            },
+            None => writeln!(out, "{},,,new", src)?,
+        }
+    }
+    for image in report.missing {
+        let dst = csv_field(&report.old.join(&image.path));
+        writeln!(out, ",{},,missing", dst)?;
+    }
+
+    Ok(())
+}
+
+fn render_json_lines(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    for m in report.mapping {
+        write_json_line(out, report, m)?;
+    }
+    write_json_lines_footer(out, report)
+}
+
+/// Write a match as a JSON line, of kind `match` or `new`.
+///
+/// Only the roots and hash size of the report are used, so that the matches
+/// can be written as soon as they are decided.
+pub fn write_json_line(
+    out: &mut impl Write,
+    report: &Report<'_>,
+    m: &Match,
+) -> Result<()> {
+    let mut json = String::from("{\"kind\":");
+    match &m.dst {
+        Some((image, distance)) => {
+            json.push_str("\"match\",\"src\":");
+            push_json_path(&mut json, &report.new.join(&m.src.path));
+            json.push_str(",\"dst\":");
+            push_json_path(&mut json, &report.old.join(&image.path));
+            write!(
+                json,
+                ",\"distance\":{},\"confidence\":{:.4},\"status\":\"{}\",\
+                 \"mirrored\":{},\"inverted\":{}",
+                distance,
+                confidence(m, report.hash_bits),
+                m.status(),
+                m.mirrored,
+                m.inverted
+            )?;
+            push_json_runner_up(&mut json, report, m)?;
+            json.push('}');
+        },
+        None => {
+            json.push_str("\"new\",\"src\":");
+            push_json_path(&mut json, &report.new.join(&m.src.path));
+            json.push_str(",\"dst\":null,\"distance\":null}");
+        },
+    }
+
+    writeln!(out, "{}", json)?;
+    Ok(())
+}
+
+/// Write the JSON lines after the matches: the missing, split or merged and
+/// skipped pages, and the summary.
+pub fn write_json_lines_footer(
+    out: &mut impl Write,
+    report: &Report<'_>,
+) -> Result<()> {
+    for image in report.missing {
+        let mut json =
+            String::from("{\"kind\":\"missing\",\"src\":null,\"dst\":");
+        push_json_path(&mut json, &report.old.join(&image.path));
+        json.push_str(",\"distance\":null}");
+        writeln!(out, "{}", json)?;
+    }
+    for spread in report.spreads {
+        let mut json = String::new();
+        push_json_spread(&mut json, report, spread)?;
+        writeln!(out, "{}", json)?;
+    }
+    for (distance, matched) in report.suggestions {
+        writeln!(
+            out,
+            "{{\"kind\":\"suggestion\",\"distance\":{},\"matched\":{}}}",
+            distance, matched
+        )?;
+    }
+    for page in report.skipped {
+        let mut json = String::from("{\"kind\":\"skipped\",\"path\":");
+        push_json_path(&mut json, &page.path);
+        json.push_str(",\"reason\":");
+        push_string(&mut json, &page.reason);
+        write!(json, ",\"too_small\":{}}}", page.too_small)?;
+        writeln!(out, "{}", json)?;
+    }
+    if report.summary.is_some() {
+        let mut json = String::from("{\"kind\":\"summary\"");
+        push_json_summary(&mut json, report)?;
+        json.push('}');
+        writeln!(out, "{}", json)?;
+    }
+
+    Ok(())
+}
+
+/// Write the report keyed by the pages of the old version, in the requested
+/// format.
+///
+/// Each old page is listed in order with the new page it became, if any,
+/// followed by the new pages that have no match.
+pub fn render_by_old(
+    out: &mut impl Write,
+    format: Format,
+    report: &Report<'_>,
+) -> Result<()> {
+    let mut pages = report
+        .mapping
+        .iter()
+        .filter_map(|m| {
+            m.dst
+                .as_ref()
+                .map(|(image, distance)| (image, Some((m, *distance))))
+        })
+        .chain(report.missing.iter().map(|image| (image, None)))
+        .collect::<Vec<_>>();
+    pages.sort_by_key(|(image, _)| image.index);
+    let new = report
+        .mapping
+        .iter()
+        .filter(|m| m.dst.is_none())
+        .map(|m| report.new.join(&m.src.path))
+        .collect::<Vec<_>>();
+
+    match format {
+        Format::Text => {
+            writeln!(out, "PAGE MAPPING (BY OLD PAGE):")?;
+            for (image, m) in pages {
+                let old = report.old.join(&image.path);
+                match m {
+                    Some((m, distance)) => writeln!(
+                        out,
+                        "\t{} {} {} (DISTANCE: {}, CONFIDENCE: {:.0}%{}{})",
+                        old.display(),
+                        m.status().to_uppercase().replace('-', " "),
+                        report.new.join(&m.src.path).display(),
+                        distance,
+                        confidence(m, report.hash_bits) * 100.,
+                        if m.mirrored { ", MIRRORED" } else { "" },
+                        if m.inverted { ", INVERTED" } else { "" }
+                    )?,
+                    None => writeln!(out, "\t{} (REMOVED)", old.display())?,
+                }
+            }
+
+            if !new.is_empty() {
+                writeln!(out, "\nNEW PAGES")?;
+                for path in new {
+                    writeln!(out, "\t{}", path.display())?;
+                }
+            }
+            write_summary(out, report)?;
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{\"pages\":[");
+            for (i, (image, m)) in pages.into_iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                json.push_str("{\"old\":");
+                push_json_path(&mut json, &report.old.join(&image.path));
+                json.push_str(",\"new\":");
+                match m {
+                    Some((m, distance)) => {
+                        push_json_path(
+                            &mut json,
+                            &report.new.join(&m.src.path),
+                        );
+                        write!(
+                            json,
+                            ",\"distance\":{},\"confidence\":{:.4},\
+                             \"status\":\"{}\",\"mirrored\":{},\
+                             \"inverted\":{}}}",
+                            distance,
+                            confidence(m, report.hash_bits),
+                            m.status(),
+                            m.mirrored,
+                            m.inverted
+                        )?;
+                    },
+                    None => json.push_str(
+                        "null,\"distance\":null,\"confidence\":null,\
+                         \"status\":\"removed\",\"mirrored\":false,\
+                         \"inverted\":false}",
+                    ),
+                }
+            }
+            json.push_str("],\"new\":[");
+            for (i, path) in new.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                push_json_path(&mut json, path);
+            }
+            json.push(']');
+            push_json_summary(&mut json, report)?;
+            json.push('}');
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            writeln!(out, "old,new,distance,status")?;
+            for (image, m) in pages {
+                let old = csv_field(&report.old.join(&image.path));
+                match m {
+                    Some((m, distance)) => writeln!(
+                        out,
+                        "{},{},{},{}",
+                        old,
+                        csv_field(&report.new.join(&m.src.path)),
+                        distance,
+                        m.status()
+                    )?,
+                    None => writeln!(out, "{},,,removed", old)?,
+                }
+            }
+            for path in new {
+                writeln!(out, ",{},,new", csv_field(&path))?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Quality tier of a match, when grouping the report by quality.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Tier {
+    /// At distance 0.
+    Exact,
+    /// Within the acceptance distance.
+    Close,
+    /// Beyond the acceptance distance (or only paired by filename), to be
+    /// reviewed.
+    Uncertain,
+    /// Without a match.
+    New,
+}
+
+impl Tier {
+    /// Every tier, in the order of the report.
+    const ALL: [Self; 4] =
+        [Self::Exact, Self::Close, Self::Uncertain, Self::New];
+
+    fn of(m: &Match) -> Self {
+        match &m.dst {
+            None => Self::New,
+            Some(_) if m.uncertain || m.filename_match => Self::Uncertain,
+            Some((_, 0)) => Self::Exact,
+            Some(_) => Self::Close,
+        }
+    }
+
+    /// Name of the tier, in the JSON and CSV reports.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Close => "close",
+            Self::Uncertain => "uncertain",
+            Self::New => "new",
+        }
+    }
+
+    /// Title of the section of the text report.
+    fn title(self) -> &'static str {
+        match self {
+            Self::Exact => "EXACT MATCHES",
+            Self::Close => "CLOSE MATCHES",
+            Self::Uncertain => "UNCERTAIN MATCHES",
+            Self::New => "NEW PAGES",
+        }
+    }
+}
+
+/// Write the report grouped by match quality, in the requested format.
+///
+/// The pages of the new version are split into the exact matches, the close
+/// ones (within the acceptance distance), the uncertain ones and the new
+/// pages, each in order, followed by the missing pages.
+pub fn render_by_quality(
+    out: &mut impl Write,
+    format: Format,
+    report: &Report<'_>,
+) -> Result<()> {
+    let tier =
+        |tier: Tier| report.mapping.iter().filter(move |m| Tier::of(m) == tier);
+
+    match format {
+        Format::Text => {
+            writeln!(out, "PAGE MAPPING (BY QUALITY):")?;
+            for section in Tier::ALL {
+                let matches = tier(section).collect::<Vec<_>>();
+                if matches.is_empty() {
+                    continue;
+                }
+                writeln!(out, "\n{} ({})", section.title(), matches.len())?;
+                for m in matches {
+                    write_text_match(out, report, m)?;
+                }
+            }
+            write_text_footer(out, report)?;
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{");
+            for section in Tier::ALL {
+                write!(json, "\"{}\":[", section.name())?;
+                for (i, m) in tier(section).enumerate() {
+                    if i != 0 {
+                        json.push(',');
+                    }
+                    push_json_match(&mut json, report, m)?;
+                }
+                json.push_str("],");
+            }
+            json.push_str("\"missing\":[");
+            for (i, image) in report.missing.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                push_json_path(&mut json, &report.old.join(&image.path));
+            }
+            if !report.suggestions.is_empty() {
+                json.push_str("],\"suggestions\":[");
+                push_json_suggestions(&mut json, report)?;
+            }
+            json.push(']');
+            push_json_cost(&mut json, report)?;
+            push_json_summary(&mut json, report)?;
+            json.push('}');
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            writeln!(out, "tier,new_path,old_path,distance,status")?;
+            for section in Tier::ALL {
+                for m in tier(section) {
+                    let src = csv_field(&report.new.join(&m.src.path));
+                    match &m.dst {
+                        Some((image, distance)) => writeln!(
+                            out,
+                            "{},{},{},{},{}",
+                            section.name(),
+                            src,
+                            csv_field(&report.old.join(&image.path)),
+                            distance,
+                            m.status()
+                        )?,
+                        None => writeln!(out, "new,{},,,new", src)?,
+                    }
+                }
+            }
+            for image in report.missing {
+                let dst = csv_field(&report.old.join(&image.path));
+                writeln!(out, "missing,,{},,missing", dst)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Write the statistics of the matching for each distance of a sweep, in the
+/// requested format.
+pub fn render_sweep(
+    out: &mut impl Write,
+    format: Format,
+    sweep: &[(u8, Summary)],
+) -> Result<()> {
+    let optional = |value: Option<f64>, null: &str| {
+        value.map_or_else(|| null.to_owned(), |value| format!("{:.2}", value))
+    };
+
+    match format {
+        Format::Text => {
+            writeln!(out, "DISTANCE SWEEP:")?;
+            writeln!(
+                out,
+                "\t{:>8}  {:>7}  {:>7}  {:>5}  {:>13}",
+                "DISTANCE", "MATCHED", "MISSING", "ADDED", "MEAN DISTANCE"
+            )?;
+            for (distance, summary) in sweep {
+                writeln!(
+                    out,
+                    "\t{:>8}  {:>7}  {:>7}  {:>5}  {:>13}",
+                    distance,
+                    summary.matched,
+                    summary.missing,
+                    summary.added,
+                    optional(summary.mean_distance, "-")
+                )?;
+            }
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{\"sweep\":[");
+            for (i, (distance, summary)) in sweep.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                write!(
+                    json,
+                    "{{\"distance\":{},\"matched\":{},\"missing\":{},\
+                     \"added\":{},\"mean_distance\":{}}}",
+                    distance,
+                    summary.matched,
+                    summary.missing,
+                    summary.added,
+                    optional(summary.mean_distance, "null")
+                )?;
+            }
+            json.push_str("]}");
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            writeln!(out, "distance,matched,missing,added,mean_distance")?;
+            for (distance, summary) in sweep {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    distance,
+                    summary.matched,
+                    summary.missing,
+                    summary.added,
+                    optional(summary.mean_distance, "")
+                )?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Write the statistics of the matching on a single line, if requested.
+fn write_summary(out: &mut impl Write, report: &Report<'_>) -> Result<()> {
+    let summary = match &report.summary {
+        Some(summary) => summary,
+        None => return Ok(()),
+    };
+    let optional = |value: Option<f64>| {
+        value.map_or_else(|| "-".to_owned(), |value| format!("{:.2}", value))
+    };
+    writeln!(
+        out,
+        "\nSUMMARY: old={} new={} matched={} missing={} added={} \
+         mean_distance={} median_distance={}",
+        summary.old_pages,
+        summary.new_pages,
+        summary.matched,
+        summary.missing,
+        summary.added,
+        optional(summary.mean_distance),
+        optional(summary.median_distance)
+    )?;
+    Ok(())
+}
+
+/// Append the statistics of the matching to a JSON object, if requested.
+fn push_json_summary(json: &mut String, report: &Report<'_>) -> Result<()> {
+    let summary = match &report.summary {
+        Some(summary) => summary,
+        None => return Ok(()),
+    };
+    let optional = |value: Option<f64>| {
+        value.map_or_else(|| "null".to_owned(), |value| format!("{:.3}", value))
+    };
+    write!(
+        json,
+        ",\"summary\":{{\"old_pages\":{},\"new_pages\":{},\"matched\":{},\
+         \"missing\":{},\"added\":{},\"mean_distance\":{},\
+         \"median_distance\":{}}}",
+        summary.old_pages,
+        summary.new_pages,
+        summary.matched,
+        summary.missing,
+        summary.added,
+        optional(summary.mean_distance),
+        optional(summary.median_distance)
+    )?;
+    Ok(())
+}
+
+/// Append the runner-up of a match to a JSON object, as a `runner_up` field.
+fn push_json_runner_up(
+    json: &mut String,
+    report: &Report<'_>,
+    m: &Match,
+) -> Result<()> {
+    json.push_str(",\"runner_up\":");
+    match &m.runner_up {
+        Some((image, distance)) => {
+            json.push_str("{\"path\":");
+            push_json_path(json, &report.old.join(&image.path));
+            write!(json, ",\"distance\":{}}}", distance)?;
+        },
+        None => json.push_str("null"),
+    }
+    Ok(())
+}
+
+/// Append the suggested distances to a JSON document, as objects.
+fn push_json_suggestions(json: &mut String, report: &Report<'_>) -> Result<()> {
+    for (i, (distance, matched)) in report.suggestions.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            "{{\"distance\":{},\"matched\":{}}}",
+            distance, matched
+        )?;
+    }
+    Ok(())
+}
+
+/// Append a split or merged page to a JSON document, as an object.
+fn push_json_spread(
+    json: &mut String,
+    report: &Report<'_>,
+    spread: &Spread,
+) -> Result<()> {
+    write!(json, "{{\"kind\":\"{}\",\"old\":[", spread.kind)?;
+    for (i, image) in spread.old.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        push_json_path(json, &report.old.join(&image.path));
+    }
+    json.push_str("],\"new\":[");
+    for (i, image) in spread.new.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        push_json_path(json, &report.new.join(&image.path));
+    }
+    write!(json, "],\"distance\":{}}}", spread.distance)?;
+    Ok(())
+}
+
+/// Return the confidence of a match, which must have a counterpart.
+pub fn confidence(m: &Match, bits: u32) -> f64 {
+    m.confidence(bits).expect("match without counterpart")
+}
+
+/// Write the changes between the two versions, like a diff, in the requested
+/// format.
+pub fn render_diff(
+    out: &mut impl Write,
+    format: Format,
+    report: &Report<'_>,
+    changes: &[PageChange],
+) -> Result<()> {
+    let path = |root: &Path, image: &Option<HashedImage>| {
+        image.as_ref().map(|image| root.join(&image.path))
+    };
+    let name = |change| match change {
+        Change::Kept => "kept",
+        Change::Reordered => "reordered",
+        Change::Inserted => "inserted",
+        Change::Deleted => "deleted",
+    };
+
+    match format {
+        Format::Text => {
+            writeln!(out, "PAGE CHANGES:")?;
+            for change in changes {
+                let old = path(report.old, &change.old);
+                let new = path(report.new, &change.new);
+                match (change.change, old, new) {
+                    (Change::Kept, Some(old), Some(new)) => {
+                        writeln!(
+                            out,
+                            "  {} = {}",
+                            new.display(),
+                            old.display()
+                        )?;
+                    },
+                    (Change::Reordered, Some(old), Some(new)) => {
+                        writeln!(
+                            out,
+                            "~ {} = {} (REORDERED)",
+                            new.display(),
+                            old.display()
+                        )?;
+                    },
+                    (_, _, Some(new)) => {
+                        writeln!(out, "+ {} (INSERTED)", new.display())?;
+                    },
+                    (_, Some(old), None) => {
+                        writeln!(out, "- {} (DELETED)", old.display())?;
+                    },
+                    (_, None, None) => (),
+                }
+            }
+            write_summary(out, report)?;
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{\"changes\":[");
+            for (i, change) in changes.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                write!(json, "{{\"change\":\"{}\"", name(change.change))?;
+                for (key, path) in [
+                    ("old", path(report.old, &change.old)),
+                    ("new", path(report.new, &change.new)),
+                ] {
+                    write!(json, ",\"{}\":", key)?;
+                    match path {
+                        Some(path) => push_json_path(&mut json, &path),
+                        None => json.push_str("null"),
+                    }
+                }
+                json.push('}');
+            }
+            json.push(']');
+            push_json_summary(&mut json, report)?;
+            json.push('}');
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            writeln!(out, "change,old,new")?;
+            for change in changes {
+                let old = path(report.old, &change.old);
+                let new = path(report.new, &change.new);
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    name(change.change),
+                    old.as_deref().map(csv_field).unwrap_or_default(),
+                    new.as_deref().map(csv_field).unwrap_or_default()
+                )?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Write the clusters of duplicate pages, found under `root`, in the
+/// requested format.
+pub fn render_duplicates(
+    out: &mut impl Write,
+    format: Format,
+    root: &Path,
+    clusters: &[Vec<HashedImage>],
+) -> Result<()> {
+    match format {
+        Format::Text => {
+            writeln!(out, "DUPLICATE PAGES:")?;
+            for (i, cluster) in clusters.iter().enumerate() {
+                if i != 0 {
+                    writeln!(out)?;
+                }
+                for image in cluster {
+                    writeln!(out, "\t{}", root.join(&image.path).display())?;
+                }
+            }
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{\"duplicates\":[");
+            for (i, cluster) in clusters.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                json.push('[');
+                for (j, image) in cluster.iter().enumerate() {
+                    if j != 0 {
+                        json.push(',');
+                    }
+                    push_json_path(&mut json, &root.join(&image.path));
+                }
+                json.push(']');
+            }
+            json.push_str("]}");
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            writeln!(out, "cluster,path")?;
+            for (i, cluster) in clusters.iter().enumerate() {
+                for image in cluster {
+                    let path = csv_field(&root.join(&image.path));
+                    writeln!(out, "{},{}", i + 1, path)?;
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Write the pages of several versions as a table, in the requested format.
+///
+/// There is one row per page and one column per version, in the order of
+/// `roots`.
+pub fn render_versions(
+    out: &mut impl Write,
+    format: Format,
+    roots: &[PathBuf],
+    rows: &[Vec<Option<HashedImage>>],
+) -> Result<()> {
+    match format {
+        Format::Text => {
+            let mut table = vec![roots
+                .iter()
+                .map(|root| root.display().to_string())
+                .collect::<Vec<_>>()];
+            table.extend(rows.iter().map(|row| {
+                row.iter()
+                    .map(|cell| {
+                        cell.as_ref().map_or_else(String::new, |image| {
+                            Path::new(&image.path).display().to_string()
+                        })
+                    })
+                    .collect()
+            }));
+            let widths = (0..roots.len())
+                .map(|column| {
+                    table
+                        .iter()
+                        .map(|row| row[column].chars().count())
+                        .max()
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+
+            writeln!(out, "PAGE TABLE:")?;
+            for row in table {
+                let line = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, &width)| format!("{:width$}", cell))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                writeln!(out, "\t{}", line.trim_end())?;
+            }
+        },
+        Format::Json | Format::JsonLines => {
+            let mut json = String::from("{\"versions\":[");
+            for (i, root) in roots.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                push_json_path(&mut json, root);
+            }
+            json.push_str("],\"pages\":[");
+            for (i, row) in rows.iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                json.push('[');
+                for (j, (cell, root)) in row.iter().zip(roots).enumerate() {
+                    if j != 0 {
+                        json.push(',');
+                    }
+                    match cell {
+                        Some(image) => {
+                            push_json_path(&mut json, &root.join(&image.path));
+                        },
+                        None => json.push_str("null"),
+                    }
+                }
+                json.push(']');
+            }
+            json.push_str("]}");
+            writeln!(out, "{}", json)?;
+        },
+        Format::Csv => {
+            let header =
+                roots.iter().map(|root| csv_field(root)).collect::<Vec<_>>();
+            writeln!(out, "{}", header.join(","))?;
+            for row in rows {
+                let fields = row
+                    .iter()
+                    .zip(roots)
+                    .map(|(cell, root)| {
+                        cell.as_ref().map_or_else(String::new, |image| {
+                            csv_field(&root.join(&image.path))
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Append the path as a JSON string.
+fn push_json_path(json: &mut String, path: &Path) {
+    push_string(json, &path.to_string_lossy());
+}
+
+/// Format the path as a CSV field, quoting it when necessary.
+pub fn csv_field(path: &Path) -> String {
+    let value = path.to_string_lossy();
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.into_owned()
+    }
+}
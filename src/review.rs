@@ -0,0 +1,100 @@
+//! Interactive review of the uncertain matches.
+use eyre::{Context, Result};
+use img_match::{warn, MatchOptions, MatchReport};
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Ask whether to keep each uncertain match, after opening both pages.
+///
+/// Rejected matches are undone, the others are confirmed. Pages extracted
+/// from an archive or a PDF document cannot be opened, only their paths are
+/// shown.
+pub fn review_matches(
+    report: &mut MatchReport,
+    options: &MatchOptions,
+    old_root: &Path,
+    new_root: &Path,
+) -> Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let stderr = io::stderr();
+
+    for i in 0..report.mapping.len() {
+        let m = &report.mapping[i];
+        let (image, distance) = match &m.dst {
+            Some((image, distance)) if m.uncertain => (image, *distance),
+            _ => continue,
+        };
+        let new = new_root.join(&m.src.path);
+        let old = old_root.join(&image.path);
+        open(&new);
+        open(&old);
+
+        let accepted = loop {
+            let mut stderr = stderr.lock();
+            write!(
+                stderr,
+                "{} matches {} (distance {}), keep it? [y/n] ",
+                new.display(),
+                old.display(),
+                distance
+            )?;
+            stderr.flush()?;
+            let answer = match lines.next() {
+                Some(line) => line.wrap_err("read answer")?,
+                // Keep the remaining matches as they are.
+                None => return Ok(()),
+            };
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => break true,
+                "n" | "no" => break false,
+                _ => continue,
+            }
+        };
+
+        if accepted {
+            report.mapping[i].uncertain = false;
+        } else {
+            report.reject(i, options);
+        }
+    }
+
+    Ok(())
+}
+
+/// Commands opening a file with the default application, in order of
+/// preference: the next one is tried when a command is not installed.
+#[cfg(target_os = "windows")]
+const OPENERS: &[&[&str]] = &[&["explorer"]];
+#[cfg(target_os = "macos")]
+const OPENERS: &[&[&str]] = &[&["open"]];
+// The freedesktop.org command (Linux, the BSDs…), then the GLib one.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const OPENERS: &[&[&str]] = &[&["xdg-open"], &["gio", "open"]];
+
+/// Open the file with the default application, in the background.
+fn open(path: &Path) {
+    if !path.is_file() {
+        return;
+    }
+    let mut error = None;
+    for opener in OPENERS {
+        let spawned = Command::new(opener[0])
+            .args(&opener[1..])
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(_) => return,
+            Err(err) => error = Some(err),
+        }
+    }
+    if let Some(err) = error {
+        warn!("cannot open {}: {}", path.display(), err);
+    }
+}
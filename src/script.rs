@@ -0,0 +1,316 @@
+//! Generation of a script copying the new pages over the old ones.
+use crate::report::Report;
+use eyre::{bail, Report as Error, Result};
+use img_match::Match;
+use std::{io::Write, path::Path, str::FromStr};
+
+/// Shell used to run the generated script.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Bash,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "bash" => Self::Bash,
+            "powershell" => Self::PowerShell,
+            _ => bail!("invalid shell {}", s),
+        })
+    }
+}
+
+impl Shell {
+    /// Quote the path as a single shell argument, on a single line.
+    ///
+    /// The control characters (e.g. newlines) are escaped, so that no path
+    /// can end the line and run as a command, even when commented out.
+    fn quote(self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+        let escaped = path.contains(char::is_control);
+        match self {
+            Self::Bash if escaped => {
+                let mut quoted = String::from("$'");
+                for c in path.chars() {
+                    match c {
+                        '\\' => quoted.push_str(r"\\"),
+                        '\'' => quoted.push_str(r"\'"),
+                        '\n' => quoted.push_str(r"\n"),
+                        '\r' => quoted.push_str(r"\r"),
+                        '\t' => quoted.push_str(r"\t"),
+                        c if c.is_control() => {
+                            for byte in c.to_string().bytes() {
+                                quoted.push_str(&format!(r"\x{:02x}", byte));
+                            }
+                        },
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('\'');
+                quoted
+            },
+            Self::Bash => format!("'{}'", path.replace('\'', r"'\''")),
+            Self::PowerShell if escaped => {
+                let mut quoted = String::from("\"");
+                for c in path.chars() {
+                    match c {
+                        // Also the typographic quotes, that PowerShell takes as
+                        // double quotes.
+                        '`' | '$' | '"' | '\u{201c}' | '\u{201d}'
+                        | '\u{201e}' => {
+                            quoted.push('`');
+                            quoted.push(c);
+                        },
+                        '\n' => quoted.push_str("`n"),
+                        '\r' => quoted.push_str("`r"),
+                        '\t' => quoted.push_str("`t"),
+                        c if c.is_control() => {
+                            quoted.push_str(&format!("`u{{{:x}}}", c as u32));
+                        },
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            },
+            // The typographic single quotes are quotes as well.
+            Self::PowerShell => {
+                let mut quoted = String::from("'");
+                for c in path.chars() {
+                    if matches!(c, '\'' | '\u{2018}'..='\u{201b}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                quoted
+            },
+        }
+    }
+}
+
+/// Write a script copying each matched new page over its old counterpart.
+///
+/// The copy keeps the name of the old page, but the extension of the new one
+/// (it reflects the actual format of the file): when the extensions differ, the
+/// old page is removed after the copy. Unmatched pages are listed as
+/// comments, to be handled manually, and so are the copies of uncertain and
+/// filename matches.
+pub fn render_script(
+    out: &mut impl Write,
+    shell: Shell,
+    report: &Report<'_>,
+) -> Result<()> {
+    write_script_header(out, shell)?;
+    for m in report.mapping.iter() {
+        write_script_match(out, shell, report, m)?;
+    }
+    write_script_footer(out, report)
+}
+
+/// Write the beginning of the script, before the copies.
+pub fn write_script_header(out: &mut impl Write, shell: Shell) -> Result<()> {
+    match shell {
+        Shell::Bash => writeln!(out, "#!/usr/bin/env bash\nset -e\n")?,
+        Shell::PowerShell => {
+            writeln!(out, "$ErrorActionPreference = 'Stop'\n")?;
+        },
+    }
+    Ok(())
+}
+
+/// Write the copy of a matched page (or the comment of a new page).
+pub fn write_script_match(
+    out: &mut impl Write,
+    shell: Shell,
+    report: &Report<'_>,
+    m: &Match,
+) -> Result<()> {
+    let src = report.new.join(&m.src.path);
+    match &m.dst {
+        Some((image, _)) => {
+            let old = report.old.join(&image.path);
+            let mut dst = old.clone();
+            if let Some(extension) = src.extension() {
+                dst.set_extension(extension);
+            }
+            // Extensions differing by case only may name the same file.
+            let replaced = !old
+                .to_string_lossy()
+                .eq_ignore_ascii_case(&dst.to_string_lossy());
+            let (src, dst) = (shell.quote(&src), shell.quote(&dst));
+            if m.filename_match {
+                write!(out, "# FILENAME MATCH: ")?;
+            } else if m.duplicate {
+                write!(out, "# DUPLICATE MATCH: ")?;
+            } else if m.runner_up.is_some() {
+                write!(out, "# AMBIGUOUS MATCH: ")?;
+            } else if m.uncertain {
+                write!(out, "# UNCERTAIN MATCH: ")?;
+            }
+            // On the same line, to be commented out along with the copy.
+            match shell {
+                Shell::Bash => {
+                    write!(out, "cp -- {} {}", src, dst)?;
+                    if replaced {
+                        write!(out, " && rm -- {}", shell.quote(&old))?;
+                    }
+                },
+                Shell::PowerShell => {
+                    write!(
+                        out,
+                        "Copy-Item -LiteralPath {} -Destination {}",
+                        src, dst
+                    )?;
+                    if replaced {
+                        write!(
+                            out,
+                            "; Remove-Item -LiteralPath {}",
+                            shell.quote(&old)
+                        )?;
+                    }
+                },
+            }
+            writeln!(out)?;
+        },
+        // Debug formatting escapes the newlines, that would otherwise end
+        // the comment.
+        None => writeln!(out, "# NEW PAGE: {:?}", src)?,
+    }
+
+    Ok(())
+}
+
+/// Write the end of the script, listing the missing pages.
+pub fn write_script_footer(
+    out: &mut impl Write,
+    report: &Report<'_>,
+) -> Result<()> {
+    for image in report.missing {
+        let path = report.old.join(&image.path);
+        writeln!(out, "# MISSING PAGE: {:?}", path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use img_hash::ImageHash;
+    use img_match::HashedImage;
+
+    #[test]
+    fn quote_bash() {
+        let quote = |path: &str| Shell::Bash.quote(Path::new(path));
+        assert_eq!(quote("a b/p1.png"), "'a b/p1.png'");
+        assert_eq!(quote("it's.png"), r"'it'\''s.png'");
+        assert_eq!(quote("x\ntouch PWNED #.bmp"), r"$'x\ntouch PWNED #.bmp'");
+        assert_eq!(quote("a'\\\r\t\u{1b}.png"), r"$'a\'\\\r\t\x1b.png'");
+    }
+
+    #[test]
+    fn quote_powershell() {
+        let quote = |path: &str| Shell::PowerShell.quote(Path::new(path));
+        assert_eq!(quote("a b/p1.png"), "'a b/p1.png'");
+        assert_eq!(quote("it's \u{2019}.png"), "'it''s \u{2019}\u{2019}.png'");
+        assert_eq!(quote("x\ntouch PWNED #.bmp"), "\"x`ntouch PWNED #.bmp\"");
+        assert_eq!(quote("$a`\"\r.png"), "\"`$a```\"`r.png\"");
+    }
+
+    /// Build a page of the given path, whose hash is irrelevant.
+    fn page(path: &str) -> HashedImage {
+        HashedImage {
+            path: path.into(),
+            index: 0,
+            size: 0,
+            hash: ImageHash::from_bytes(&[0; 8]).expect("hash"),
+            mirror_hash: None,
+            inverted_hash: None,
+            thumbnail: None,
+            digest: None,
+            dimensions: None,
+        }
+    }
+
+    /// Write the line of the script copying the new page `src` over the old
+    /// page `dst`, from `n` to `o`.
+    fn copy(shell: Shell, src: &str, dst: &str, duplicate: bool) -> String {
+        let m = Match {
+            src: page(src),
+            dst: Some((page(dst), 0)),
+            uncertain: false,
+            filename_match: false,
+            mirrored: false,
+            inverted: false,
+            duplicate,
+            identical: false,
+            runner_up: None,
+        };
+        let report = Report {
+            old: Path::new("o"),
+            new: Path::new("n"),
+            mapping: &[],
+            missing: &[],
+            skipped: &[],
+            spreads: &[],
+            suggestions: &[],
+            cost: None,
+            hash_bits: 64,
+            summary: None,
+            color: false,
+            changes_only: false,
+        };
+        let mut out = Vec::new();
+        write_script_match(&mut out, shell, &report, &m).expect("write script");
+        String::from_utf8(out).expect("UTF-8 script")
+    }
+
+    #[test]
+    fn commented_copy_on_one_line() {
+        for shell in [Shell::Bash, Shell::PowerShell] {
+            let script = copy(shell, "x\ntouch PWNED #.bmp", "p1.bmp", true);
+            assert_eq!(script.lines().count(), 1, "{}", script);
+            assert!(script.starts_with("# DUPLICATE MATCH: "), "{}", script);
+        }
+    }
+
+    #[test]
+    fn changed_extension() {
+        // The old page is removed once replaced by a page of another format.
+        assert_eq!(
+            copy(Shell::Bash, "p1.png", "p1.jpg", false),
+            "cp -- 'n/p1.png' 'o/p1.png' && rm -- 'o/p1.jpg'\n"
+        );
+        assert_eq!(
+            copy(Shell::PowerShell, "p1.png", "p1.jpg", false),
+            "Copy-Item -LiteralPath 'n/p1.png' -Destination 'o/p1.png'; \
+             Remove-Item -LiteralPath 'o/p1.jpg'\n"
+        );
+        // Along with the copy, when commented out.
+        assert_eq!(
+            copy(Shell::Bash, "p1.png", "p1.jpg", true),
+            "# DUPLICATE MATCH: cp -- 'n/p1.png' 'o/p1.png' && rm -- \
+             'o/p1.jpg'\n"
+        );
+
+        // Nothing is removed when the copy overwrites the old page: same
+        // name, or one differing by its case (the same file on the
+        // case-insensitive file systems).
+        assert_eq!(
+            copy(Shell::Bash, "p1.png", "p1.png", false),
+            "cp -- 'n/p1.png' 'o/p1.png'\n"
+        );
+        assert_eq!(
+            copy(Shell::Bash, "p1.PNG", "p1.png", false),
+            "cp -- 'n/p1.PNG' 'o/p1.PNG'\n"
+        );
+        assert_eq!(
+            copy(Shell::PowerShell, "p1.PNG", "p1.png", false),
+            "Copy-Item -LiteralPath 'n/p1.PNG' -Destination 'o/p1.PNG'\n"
+        );
+    }
+}
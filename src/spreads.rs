@@ -0,0 +1,255 @@
+//! Detection of the pages split in two, or merged into one, between versions.
+use crate::{
+    debug,
+    extract::read_pages,
+    hash::{decode_image, preprocess},
+    HashConfig,
+    HashOptions,
+    HashedImage,
+    MatchReport,
+};
+use eyre::{eyre, Result};
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use img_hash::ImageHash;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt,
+    path::Path,
+};
+
+/// How the pages changed between the versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpreadKind {
+    /// A page of the old version is split into two pages of the new one.
+    Split,
+    /// Two pages of the old version are merged into a page of the new one.
+    Merged,
+}
+
+impl fmt::Display for SpreadKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Split => "split",
+            Self::Merged => "merged",
+        })
+    }
+}
+
+/// A page split or merged between the versions.
+#[derive(Clone, Debug)]
+pub struct Spread {
+    /// How the pages changed.
+    pub kind: SpreadKind,
+    /// Pages from the old version: one when split, two when merged.
+    pub old: Vec<HashedImage>,
+    /// Pages from the new version: two when split, one when merged.
+    pub new: Vec<HashedImage>,
+    /// Distance between the single page and the two others put together.
+    pub distance: isize,
+}
+
+/// Look for the pages left unmatched in the report that were split in two, or
+/// merged into one.
+///
+/// Two consecutive pages are put side by side (in both orders, for the books
+/// read from right to left) and on top of each other, then their hashes are
+/// compared to those of the pages from the other version. Pairs are matched
+/// when their distance is at most `distance`, in order, and each page is part
+/// of one pair at most.
+///
+/// The report is left as is: these pages are still new and missing.
+pub fn detect_spreads(
+    report: &MatchReport,
+    old_root: &Path,
+    new_root: &Path,
+    options: &HashOptions,
+    distance: u8,
+) -> Result<Vec<Spread>> {
+    let new = report
+        .mapping
+        .iter()
+        .filter(|m| m.dst.is_none())
+        .map(|m| &m.src)
+        .collect::<Vec<_>>();
+    let old = report.missing.iter().collect::<Vec<_>>();
+    if new.is_empty() || old.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut spreads = find_spreads(
+        SpreadKind::Split,
+        &old,
+        &new,
+        new_root,
+        options,
+        distance,
+    )?;
+    // Pages that are already part of a split cannot be merged as well.
+    let used = spreads
+        .iter()
+        .flat_map(|spread| spread.old.iter().chain(&spread.new))
+        .map(|image| (image.index, image.path.clone()))
+        .collect::<HashSet<_>>();
+    let unused = |image: &&HashedImage| {
+        !used.contains(&(image.index, image.path.clone()))
+    };
+    let old = old.into_iter().filter(unused).collect::<Vec<_>>();
+    let new = new.into_iter().filter(unused).collect::<Vec<_>>();
+    spreads.extend(find_spreads(
+        SpreadKind::Merged,
+        &old,
+        &new,
+        old_root,
+        options,
+        distance,
+    )?);
+
+    spreads.sort_by_key(|spread| spread.new[0].index);
+    Ok(spreads)
+}
+
+/// Match the pairs of consecutive pages from one version (found under `root`)
+/// with the single pages from the other.
+fn find_spreads(
+    kind: SpreadKind,
+    old: &[&HashedImage],
+    new: &[&HashedImage],
+    root: &Path,
+    options: &HashOptions,
+    distance: u8,
+) -> Result<Vec<Spread>> {
+    let (pairs, singles) = match kind {
+        SpreadKind::Split => (new, old),
+        SpreadKind::Merged => (old, new),
+    };
+    // The missing pages are not in order.
+    let mut pairs = pairs.to_vec();
+    pairs.sort_by_key(|image| image.index);
+    let adjacent = pairs
+        .windows(2)
+        .filter(|pair| pair[0].index + 1 == pair[1].index)
+        .map(|pair| (pair[0], pair[1]))
+        .collect::<Vec<_>>();
+    if adjacent.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let pages = adjacent
+        .iter()
+        .flat_map(|&(first, second)| [first, second])
+        .collect::<Vec<_>>();
+    let mut contents = read_pages(root, &pages, options)?;
+    let mut images = HashMap::<OsString, DynamicImage>::new();
+
+    let mut spreads = Vec::new();
+    let mut used = vec![false; singles.len()];
+    let mut last = None;
+    for (first, second) in adjacent {
+        // The first page may belong to the previous pair.
+        if last == Some(first.index) {
+            continue;
+        }
+        for image in [first, second] {
+            if !images.contains_key(&image.path) {
+                let bytes = contents.remove(&image.path).ok_or_else(|| {
+                    eyre!("read {}", image.path.to_string_lossy())
+                })?;
+                let decoded =
+                    decode_image(&image.path, &bytes, &options.config)?;
+                images.insert(image.path.clone(), decoded);
+            }
+        }
+
+        let hashes = composite_hashes(
+            &images[&first.path],
+            &images[&second.path],
+            options.config,
+        );
+        let best = singles
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !used[i])
+            .filter_map(|(i, single)| {
+                hashes
+                    .iter()
+                    .map(|hash| hash.dist(&single.hash) as isize)
+                    .min()
+                    .map(|distance| (distance, i))
+            })
+            .min();
+        let (best_distance, i) = match best {
+            Some(best) if best.0 <= distance.into() => best,
+            _ => continue,
+        };
+
+        debug!(
+            "{} + {} {} {} (distance: {})",
+            first.path.to_string_lossy(),
+            second.path.to_string_lossy(),
+            kind,
+            singles[i].path.to_string_lossy(),
+            best_distance
+        );
+        used[i] = true;
+        last = Some(second.index);
+        let pair = vec![first.clone(), second.clone()];
+        let single = vec![singles[i].clone()];
+        let (old, new) = match kind {
+            SpreadKind::Split => (single, pair),
+            SpreadKind::Merged => (pair, single),
+        };
+        spreads.push(Spread {
+            kind,
+            old,
+            new,
+            distance: best_distance,
+        });
+    }
+    Ok(spreads)
+}
+
+/// Hash two pages put together: side by side, in both orders, and on top of
+/// each other.
+fn composite_hashes(
+    first: &DynamicImage,
+    second: &DynamicImage,
+    config: HashConfig,
+) -> Vec<ImageHash> {
+    let hasher = config.to_hasher();
+    [
+        side_by_side(first, second),
+        side_by_side(second, first),
+        stacked(first, second),
+    ]
+    .iter()
+    .map(|image| hasher.hash_image(preprocess(image, &config).as_ref()))
+    .collect()
+}
+
+/// Put the pages side by side, aligned on their top edge.
+fn side_by_side(left: &DynamicImage, right: &DynamicImage) -> DynamicImage {
+    let (left_width, left_height) = left.dimensions();
+    let (right_width, right_height) = right.dimensions();
+    let mut canvas =
+        blank(left_width + right_width, left_height.max(right_height));
+    imageops::replace(&mut canvas, &left.to_rgba8(), 0, 0);
+    imageops::replace(&mut canvas, &right.to_rgba8(), left_width, 0);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Put the pages on top of each other, aligned on their left edge.
+fn stacked(top: &DynamicImage, bottom: &DynamicImage) -> DynamicImage {
+    let (top_width, top_height) = top.dimensions();
+    let (bottom_width, bottom_height) = bottom.dimensions();
+    let mut canvas =
+        blank(top_width.max(bottom_width), top_height + bottom_height);
+    imageops::replace(&mut canvas, &top.to_rgba8(), 0, 0);
+    imageops::replace(&mut canvas, &bottom.to_rgba8(), 0, top_height);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Return a white image of the given size.
+fn blank(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]))
+}
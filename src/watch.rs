@@ -0,0 +1,191 @@
+//! Watching of the new version, to match its pages as they are added.
+use crate::report::{write_text_header, write_text_match, Report};
+use eyre::{Context, Result};
+use img_match::{
+    debug,
+    hash_bytes,
+    list_images,
+    warn,
+    HashOptions,
+    HashedImage,
+    MatchOptions,
+    PageIndex,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Match the pages found under the new root of the report against the `old`
+/// pages, then again every time a page is added or changed, until
+/// interrupted, writing the matches to `out`.
+///
+/// On Linux, the directory is checked again `interval` after a change is
+/// notified (by inotify), so that the changes in between are handled at once;
+/// elsewhere, it is checked every `interval`. The pages of
+/// the old version are only indexed once. Each page is matched on its own, by
+/// hash distance (see [`PageIndex::match_page`]): the positions change as the
+/// pages are added, so they are left out. A page that cannot be hashed (e.g.
+/// still being written) is tried again once it changes.
+pub fn watch(
+    out: &mut impl Write,
+    old: Vec<HashedImage>,
+    options: &HashOptions,
+    match_options: &MatchOptions,
+    interval: Duration,
+    report: &Report<'_>,
+) -> Result<()> {
+    let old = PageIndex::new(old, match_options);
+    let watcher = Watcher::new()?;
+    // Size and modification time of the pages already seen.
+    let mut seen = HashMap::<PathBuf, (u64, Option<SystemTime>)>::new();
+    write_text_header(out, report).wrap_err("render report")?;
+
+    loop {
+        // Watched before listing: the pages added meanwhile are notified.
+        watcher.add_tree(report.new, options.recursive)?;
+        let pages = list_images(report.new, options)
+            .wrap_err_with(|| format!("listing {}", report.new.display()))?;
+        for (index, filename) in pages.into_iter().enumerate() {
+            let path = report.new.join(&filename);
+            // The page may have been removed since.
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    debug!(
+                        "cannot read metadata for {}: {}",
+                        path.display(),
+                        err
+                    );
+                    continue;
+                },
+            };
+            let stamp = (metadata.len(), metadata.modified().ok());
+            if seen.get(&filename) == Some(&stamp) {
+                continue;
+            }
+            seen.insert(filename.clone(), stamp);
+
+            let hashed = fs::read(&path)
+                .wrap_err_with(|| format!("read {}", path.display()))
+                .and_then(|contents| {
+                    hash_bytes(filename, index, contents, options)
+                });
+            let image = match hashed {
+                Ok(image) => image,
+                Err(err) => {
+                    warn!("cannot hash {}: {:#}", path.display(), err);
+                    continue;
+                },
+            };
+
+            let m = old.match_page(image, match_options);
+            write_text_match(out, report, &m)
+                .and_then(|_| out.flush().map_err(Into::into))
+                .wrap_err("render report")?;
+        }
+
+        watcher.wait()?;
+        thread::sleep(interval);
+    }
+}
+
+/// Notification of the changes in directories, with inotify.
+#[cfg(target_os = "linux")]
+struct Watcher {
+    fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn new() -> Result<Self> {
+        // SAFETY: no pointer is involved.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .wrap_err("cannot watch for changes");
+        }
+        Ok(Self { fd })
+    }
+
+    /// Watch the directory, and its subdirectories when `recursive` is set.
+    ///
+    /// The directories already watched are left as is.
+    fn add_tree(&self, dir: &Path, recursive: bool) -> Result<()> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .wrap_err_with(|| format!("invalid path {}", dir.display()))?;
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MOVED_TO
+            | libc::IN_CREATE
+            | libc::IN_DELETE;
+        // SAFETY: the path is a valid C string.
+        if unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), mask) } < 0
+        {
+            return Err(std::io::Error::last_os_error())
+                .wrap_err_with(|| format!("cannot watch {}", dir.display()));
+        }
+
+        if recursive {
+            let entries = fs::read_dir(dir)
+                .wrap_err_with(|| format!("read {}", dir.display()))?;
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    self.add_tree(&entry.path(), recursive)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for a change, unless some were notified since the last call.
+    ///
+    /// The changes notified while sleeping afterwards are left for the next
+    /// call, which then returns at once.
+    fn wait(&self) -> Result<()> {
+        // Large enough for many events: they are discarded anyway.
+        let mut buffer = [0_u8; 64 * 1024];
+        // SAFETY: the buffer is valid for its whole length.
+        let read = unsafe {
+            libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len())
+        };
+        if read < 0 {
+            return Err(std::io::Error::last_os_error())
+                .wrap_err("cannot wait for changes");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor is owned.
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Notification of the changes in directories, unsupported: the directories
+/// are checked every interval instead.
+#[cfg(not(target_os = "linux"))]
+struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    fn add_tree(&self, _dir: &Path, _recursive: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn wait(&self) -> Result<()> {
+        Ok(())
+    }
+}